@@ -25,13 +25,7 @@ impl ControllerContext {
         opt_expr_str: &str,
         obj_measures: Vec<String>,
     ) -> ControllerContext {
-        // Get the Xup model
-        let base_value = model[0][constrained_measure_idx];
-        let mut xup_model: Vec<f64> = model
-            .iter()
-            .map(|model_entry| model_entry[constrained_measure_idx] / base_value)
-            .collect();
-        xup_model[0] = 1.0;
+        let xup_model = ControllerContext::xup_model(&model, constrained_measure_idx);
 
         let expr_type = match obj_measures.len() {
             0 | 1 => ExpressionType::Value,
@@ -52,6 +46,22 @@ impl ControllerContext {
         }
     }
 
+    fn xup_model(model: &[Vec<f64>], constrained_measure_idx: usize) -> Vec<f64> {
+        let base_value = model[0][constrained_measure_idx];
+        let mut xup_model: Vec<f64> = model
+            .iter()
+            .map(|model_entry| model_entry[constrained_measure_idx] / base_value)
+            .collect();
+        xup_model[0] = 1.0;
+        xup_model
+    }
+
+    pub fn change_model(&mut self, model: Vec<Vec<f64>>, cost_model: Vec<Vec<f64>>) {
+        self.xup_model = ControllerContext::xup_model(&model, self.constrained_measure_idx);
+        self.model = model;
+        self.cost_model = cost_model;
+    }
+
     pub fn change_opt_expr(
         &mut self,
         opt_type: OptimizationType,
@@ -5,7 +5,7 @@ mod sched_type;
 mod xup_state;
 pub mod tool;
 pub(self) use controller_context::ControllerContext;
-pub(self) use sched_type::SchedType;
+pub use sched_type::SchedType;
 pub(self) use xup_state::XupState;
 
 pub(crate) use controller_logging::Log;
@@ -336,6 +336,13 @@ impl OptimizingController {
             .change_opt_expr(opt_type, opt_expr_str, obj_measures, cost_model);
     }
 
+    /// Replaces the model the schedule is computed from, e.g. after the active
+    /// model was pruned for a new objective. Schedules computed before refer to
+    /// indices of the old model.
+    pub fn change_model(&mut self, model: Vec<Vec<f64>>, cost_model: Vec<Vec<f64>>) {
+        self.ctx.change_model(model, cost_model);
+    }
+
     pub fn change_target(&mut self, new_value: f64) {
         self.ctx.change_target_value(new_value);
     }
@@ -5,7 +5,7 @@ mod optimization_type;
 
 pub(crate) use kalman_filter::KalmanFilter;
 pub(crate) use optimization_expression::ExpressionType;
-pub(crate) use optimization_expression::ObjectiveFunction;
+pub use optimization_expression::ObjectiveFunction;
 
 pub use controller::OptimizingController as Controller;
 pub use optimization_type::OptimizationType;
@@ -15,17 +15,20 @@ pub struct ObjectiveFunction {
 
 impl ObjectiveFunction {
     pub fn new(obj: &str) -> ObjectiveFunction {
+        ObjectiveFunction::try_new(obj).unwrap()
+    }
+
+    pub fn try_new(obj: &str) -> Result<ObjectiveFunction, fasteval::Error> {
         let parser = fasteval::Parser::new();
         let mut slab = fasteval::Slab::new();
         let expr = parser
-            .parse(obj, &mut slab.ps)
-            .unwrap()
+            .parse(obj, &mut slab.ps)?
             .from(&slab.ps)
             .compile(&slab.ps, &mut slab.cs);
-        ObjectiveFunction {
+        Ok(ObjectiveFunction {
             slab,
             expr: RefCell::new(expr),
-        }
+        })
     }
 
     pub fn evaluate(&self, value_map: &mut BTreeMap<String, f64>) -> Result<f64, anyhow::Error> {
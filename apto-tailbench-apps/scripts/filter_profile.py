@@ -99,7 +99,7 @@ def main(mt_fname, kt_fname):
         mt_lines = [line.strip() for line in f.readlines()]
 
     with open(kt_fname) as f:
-        kt_lines = [line.strip() for line in f.readlines()]
+        kt_lines = [line.strip() for line in f.readlines() if not line.startswith("#")]
 
     app_measure_table, app_knob_table = make_app_profile(mt_lines, kt_lines)
     write_profile(app_measure_table, app_knob_table, "app")
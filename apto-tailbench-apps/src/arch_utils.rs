@@ -287,12 +287,16 @@ pub fn generate_hyperthreading(
         .map(|(&t0, &t1)| (t0, t1))
         .collect();
 
-    Rc::new(ApplicationKnob::new(
-        "hyperthreading".to_string(),
-        allowed_values,
-        init_value,
-        Some(Box::new(move |_prev, new| {
-            toggle_hyperthreading(&pids.borrow(), num_cores.get(), new, core_pairs.iter());
-        })),
-    ))
+    // Toggles the siblings of the cores in use, so it has to follow the core count
+    Rc::new(
+        ApplicationKnob::new(
+            "hyperthreading".to_string(),
+            allowed_values,
+            init_value,
+            Some(Box::new(move |_prev, new| {
+                toggle_hyperthreading(&pids.borrow(), num_cores.get(), new, core_pairs.iter());
+            })),
+        )
+        .after(["utilizedPhysicalCores"]),
+    )
 }
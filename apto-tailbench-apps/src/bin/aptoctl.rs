@@ -0,0 +1,84 @@
+use apto::*;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
+
+/// Queries and retargets a running Apto instance through its control socket
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about=None)]
+struct Options {
+    /// Control socket of the instance, e.g. <CONTROL_SOCKET>/apto.<TAG>.sock
+    socket: String,
+    /// Seconds to wait for the reply, 0 to wait forever
+    #[clap(long, default_value_t = 10)]
+    timeout: u64,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Goal,
+    Schedule,
+    Configuration,
+    /// Aggregates of the measures in the current window
+    Window,
+    Controller,
+    /// Changes the target of the constraint
+    Retarget {
+        target: f64,
+    },
+    /// Replaces the goal with the one in a goal file
+    ChangeGoal {
+        path: String,
+    },
+    Freeze,
+    Unfreeze,
+    Gain {
+        value: f64,
+    },
+    ErrorMultiplier {
+        value: f64,
+    },
+    DerivativeMultiplier {
+        value: f64,
+    },
+}
+
+fn main() {
+    let options = Options::parse();
+    let mut client = ControlClient::connect(&options.socket)
+        .unwrap_or_else(|e| panic!("Could not connect to {}: {}", options.socket, e));
+    let timeout = (options.timeout > 0).then(|| Duration::from_secs(options.timeout));
+    client
+        .set_timeout(timeout)
+        .unwrap_or_else(|e| panic!("Could not set the timeout: {}", e));
+    let request = match options.command {
+        Command::Goal => ControlRequest::Goal,
+        Command::Schedule => ControlRequest::Schedule,
+        Command::Configuration => ControlRequest::Configuration,
+        Command::Window => ControlRequest::Window,
+        Command::Controller => ControlRequest::Controller,
+        Command::Retarget { target } => ControlRequest::Retarget { target },
+        Command::ChangeGoal { path } => ControlRequest::ChangeGoal {
+            goal: Goal::from(path),
+        },
+        Command::Freeze => ControlRequest::Freeze,
+        Command::Unfreeze => ControlRequest::Unfreeze,
+        Command::Gain { value } => ControlRequest::SetControllerGain { value },
+        Command::ErrorMultiplier { value } => ControlRequest::SetErrorMultiplier { value },
+        Command::DerivativeMultiplier { value } => {
+            ControlRequest::SetDerivativeMultiplier { value }
+        }
+    };
+
+    let reply = client
+        .send(&request)
+        .unwrap_or_else(|e| panic!("Could not send {:?}: {}", request, e));
+    match reply {
+        ControlReply::Error(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1)
+        }
+        reply => println!("{}", serde_json::to_string_pretty(&reply).unwrap()),
+    }
+}
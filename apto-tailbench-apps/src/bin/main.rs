@@ -44,6 +44,33 @@ enum Variants {
     Profile {
         app: Application,
     },
+    /// Projects a profile onto a subset of its knobs
+    Project {
+        mt: String,
+        kt: String,
+        mt_out: String,
+        kt_out: String,
+        /// Knobs to keep in the projected profile
+        #[clap(long = "keep", required = true)]
+        keep: Vec<String>,
+        /// Pins a dropped knob to a value, e.g. uncoreFrequency=24
+        #[clap(long = "fix")]
+        fix: Vec<String>,
+        /// How to merge the remaining knobs: min, mean or best:<measure>:<minimize|maximize>
+        #[clap(long = "aggregate", default_value = "min")]
+        aggregate: ProjectionAggregate,
+    },
+    /// Composes single-application profiles into the multi-application scheduler profile
+    Compose {
+        mt_out: String,
+        kt_out: String,
+        /// A tenant profile over the system knobs and its latency target: <mt>,<kt>,<target>
+        #[clap(long = "tenant", required = true)]
+        tenant: Vec<String>,
+        /// Aggregate columns of the normalized performance: harmonic, geometric, arithmetic or min
+        #[clap(long = "aggregate", default_value = "harmonic")]
+        aggregate: Vec<PerfAggregate>,
+    },
 }
 
 fn main() {
@@ -77,7 +104,96 @@ fn main() {
             run_non_adaptive(app0, app1, options.warmup_time, options.exec_time)
         }
         Variants::Profile { app } => run_monolithic(app, 0.0, options.warmup_time, options.exec_time),
+        Variants::Project {
+            mt,
+            kt,
+            mt_out,
+            kt_out,
+            keep,
+            fix,
+            aggregate,
+        } => run_projection(&mt, &kt, &mt_out, &kt_out, keep, fix, aggregate),
+        Variants::Compose {
+            mt_out,
+            kt_out,
+            tenant,
+            aggregate,
+        } => run_composition(&mt_out, &kt_out, tenant, aggregate),
+    }
+}
+
+fn run_projection(
+    mt: &str,
+    kt: &str,
+    mt_out: &str,
+    kt_out: &str,
+    keep: Vec<String>,
+    fix: Vec<String>,
+    aggregate: ProjectionAggregate,
+) {
+    let mut projection = ProfileProjection::new(keep).aggregate(aggregate);
+    for pin in fix.iter() {
+        let value = pin
+            .split_once('=')
+            .and_then(|(knob, value)| Some((knob, value.parse().ok()?)));
+        match value {
+            Some((knob, value)) => projection = projection.fix(knob, value),
+            None => panic!("Expected <knob>=<value> but got '{}'", pin),
+        }
+    }
+
+    let (measure_table, knob_table) = MeasureTable::try_new(mt)
+        .and_then(|mt| Ok((mt, KnobTable::try_new(kt)?)))
+        .and_then(|(mt, kt)| projection.apply(&mt, &kt))
+        .unwrap_or_else(|e| panic!("Could not project profile: {}", e));
+    measure_table
+        .save(mt_out)
+        .and_then(|_| knob_table.save(kt_out))
+        .unwrap_or_else(|e| panic!("Could not write projected profile: {}", e));
+    println!(
+        "Projected {} configurations onto {:?}",
+        knob_table.configurations.len(),
+        projection.keep
+    );
+}
+
+fn run_composition(
+    mt_out: &str,
+    kt_out: &str,
+    tenants: Vec<String>,
+    aggregates: Vec<PerfAggregate>,
+) {
+    let mut composer = aggregates
+        .into_iter()
+        .fold(ProfileComposer::new(), |composer, aggregate| {
+            composer.aggregate(aggregate)
+        });
+    for tenant in tenants.iter() {
+        let (mt, kt, target) = match tenant.split(',').collect::<Vec<&str>>()[..] {
+            [mt, kt, target] => match target.parse::<f64>() {
+                Ok(target) => (mt, kt, target),
+                Err(_) => panic!("Invalid latency target '{}'", target),
+            },
+            _ => panic!("Expected <mt>,<kt>,<target> but got '{}'", tenant),
+        };
+        let (measure_table, knob_table) = MeasureTable::try_new(mt)
+            .and_then(|mt| Ok((mt, KnobTable::try_new(kt)?)))
+            .unwrap_or_else(|e| panic!("Could not read tenant profile: {}", e));
+        composer = composer.tenant(measure_table, knob_table, target);
     }
+
+    let (measure_table, knob_table) = composer
+        .compose()
+        .unwrap_or_else(|e| panic!("Could not compose profiles: {}", e));
+    measure_table
+        .save(mt_out)
+        .and_then(|_| knob_table.save(kt_out))
+        .unwrap_or_else(|e| panic!("Could not write composed profile: {}", e));
+    println!(
+        "Composed {} tenants over {} configurations",
+        tenants.len(),
+        knob_table.configurations.len()
+    );
 }
 
 fn run_monolithic(app: Application, goal: f64, warmup: u64, exec_time: u64) {
@@ -133,7 +249,7 @@ fn run_multi_application(
         OptimizationType::Minimize,
         "powerConsumption".to_string(),
     );
-    let config = AptoConfig::new(
+    let mut config = AptoConfig::new(
         2,
         &scheduler_profile.0,
         &scheduler_profile.1,
@@ -141,6 +257,14 @@ fn run_multi_application(
         goal,
         sys_window,
     );
+    for (name, expression) in [
+        ("harmonicMeanPerf", "2 / (1 / perf0 + 1 / perf1)"),
+        ("harmonicMeanError", "2 / (1 / abs(err0) + 1 / abs(err1))"),
+        ("geometricMeanPerf", "(perf0 * perf1) ^ 0.5"),
+        ("geometricMeanError", "(abs(err0) * abs(err1)) ^ 0.5"),
+    ] {
+        config.add_derived_measure(DerivedMeasure::new(name, expression).unwrap());
+    }
     let mut apto = Apto::new(config);
 
     let (first_average, second_average) = (
@@ -224,18 +348,6 @@ fn run_multi_application(
         apto.measure("err1", e1);
         apto.measure("perf1", p1);
 
-        let hm_perf = 2.0 / ((1.0 / p0) + (1.0 / p1));
-        apto.measure("harmonicMeanPerf", hm_perf); // Controller should only see the last values
-
-        let hm_err = 2.0 / ((1.0 / e0.abs()) + (1.0 / e1.abs()));
-        apto.measure("harmonicMeanError", hm_err);
-
-        let gm_perf = ((p0.ln() + p1.ln()) / 2.0).exp();
-        apto.measure("geometricMeanPerf", gm_perf);
-
-        let gm_err = ((e0.abs().ln() + e1.abs().ln()) / 2.0).exp();
-        apto.measure("geometricMeanError", gm_err); // Controller should only see the last values
-
         true
     };
 
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub(crate) type AggregateFn = Box<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// How the values of a measure in a window are reduced to the one value the
+/// controller and the profile see. Written as `mean`, `last`, `sum`, `min`,
+/// `max`, `median`, `p<N>` (e.g. `p99`, `p99.9`), `ewma:<weight>`, `count`,
+/// `per_second`, `twmean` or `rate`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Aggregation {
+    Mean,
+    Last,
+    Sum,
+    Min,
+    Max,
+    Median,
+    /// Percentile between 0 and 100, interpolated between the closest values.
+    Percentile(f64),
+    /// Exponentially weighted mean over the window in measuring order, with
+    /// the weight of every new value in (0, 1].
+    Ewma(f64),
+    /// Number of values in the window.
+    Count,
+    /// Sum of the values per second, e.g. requests per second when every
+    /// request is measured as 1.
+    PerSecond,
+    /// Mean of the values, each weighted by the time since the value before.
+    TimeWeightedMean,
+    /// Last value per iteration of the window, for deltas that are measured
+    /// once per window such as `energyDelta`.
+    Rate,
+}
+
+impl Aggregation {
+    /// What a measure is aggregated with unless configured otherwise.
+    pub fn default_for(measure: &str) -> Aggregation {
+        match measure {
+            "powerConsumption" | "harmonicMean" | "geometricMean" | "harmonicMeanABS" => {
+                Aggregation::Last
+            }
+            "energyDelta" => Aggregation::Rate,
+            _ => Aggregation::Mean,
+        }
+    }
+
+    /// The quantile in [0, 1] for percentiles and the median.
+    pub fn quantile(&self) -> Option<f64> {
+        match *self {
+            Aggregation::Median => Some(0.5),
+            Aggregation::Percentile(ptile) => Some(ptile / 100.0),
+            _ => None,
+        }
+    }
+
+    /// `None` for the mean, quantiles and aggregations over time, which
+    /// `Measurement` computes itself. `Rate` reads the number of
+    /// iterations of the last window from `window_samples`.
+    pub(crate) fn function(&self, window_samples: &Arc<AtomicU64>) -> Option<AggregateFn> {
+        let func: AggregateFn = match *self {
+            Aggregation::Mean
+            | Aggregation::Median
+            | Aggregation::Percentile(_)
+            | Aggregation::PerSecond
+            | Aggregation::TimeWeightedMean => return None,
+            Aggregation::Last => Box::new(|vals| vals.last().copied().unwrap_or(f64::NAN)),
+            Aggregation::Sum => Box::new(|vals| vals.iter().sum()),
+            Aggregation::Min => {
+                Box::new(|vals| or_nan(vals, vals.iter().copied().fold(f64::INFINITY, f64::min)))
+            }
+            Aggregation::Max => Box::new(|vals| {
+                or_nan(vals, vals.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+            }),
+            Aggregation::Ewma(weight) => Box::new(move |vals| match vals.split_first() {
+                Some((first, rest)) => rest
+                    .iter()
+                    .fold(*first, |avg, val| (1.0 - weight) * avg + weight * val),
+                None => f64::NAN,
+            }),
+            Aggregation::Count => Box::new(|vals| vals.len() as f64),
+            Aggregation::Rate => {
+                let window_samples = window_samples.clone();
+                Box::new(move |vals| {
+                    vals.last().copied().unwrap_or(f64::NAN)
+                        / (window_samples.load(Ordering::Relaxed) as f64)
+                })
+            }
+        };
+        Some(func)
+    }
+}
+
+/// Percentile between 0 and 100 of sorted values, interpolated between the
+/// closest ones.
+pub(crate) fn percentile(sorted: &[f64], ptile: f64) -> f64 {
+    let index = ((sorted.len() - 1) as f64) * ptile / 100.0;
+    let (low, high) = (
+        sorted[index.floor() as usize],
+        sorted[index.ceil() as usize],
+    );
+    low + index.fract() * (high - low)
+}
+
+fn or_nan(vals: &[f64], aggregate: f64) -> f64 {
+    if vals.is_empty() {
+        f64::NAN
+    } else {
+        aggregate
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Aggregation::Mean => write!(f, "mean"),
+            Aggregation::Last => write!(f, "last"),
+            Aggregation::Sum => write!(f, "sum"),
+            Aggregation::Min => write!(f, "min"),
+            Aggregation::Max => write!(f, "max"),
+            Aggregation::Median => write!(f, "median"),
+            Aggregation::Percentile(ptile) => write!(f, "p{}", ptile),
+            Aggregation::Ewma(weight) => write!(f, "ewma:{}", weight),
+            Aggregation::Count => write!(f, "count"),
+            Aggregation::PerSecond => write!(f, "per_second"),
+            Aggregation::TimeWeightedMean => write!(f, "twmean"),
+            Aggregation::Rate => write!(f, "rate"),
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "mean" => return Ok(Aggregation::Mean),
+            "last" => return Ok(Aggregation::Last),
+            "sum" => return Ok(Aggregation::Sum),
+            "min" => return Ok(Aggregation::Min),
+            "max" => return Ok(Aggregation::Max),
+            "median" => return Ok(Aggregation::Median),
+            "count" => return Ok(Aggregation::Count),
+            "per_second" => return Ok(Aggregation::PerSecond),
+            "twmean" => return Ok(Aggregation::TimeWeightedMean),
+            "rate" => return Ok(Aggregation::Rate),
+            _ => {}
+        }
+        if let Some(ptile) = s.strip_prefix('p') {
+            return match ptile.parse::<f64>() {
+                Ok(ptile) if (0.0..=100.0).contains(&ptile) => Ok(Aggregation::Percentile(ptile)),
+                _ => Err(format!("invalid percentile in aggregation '{}'", s)),
+            };
+        }
+        if let Some(weight) = s.strip_prefix("ewma:") {
+            return match weight.parse::<f64>() {
+                Ok(weight) if weight > 0.0 && weight <= 1.0 => Ok(Aggregation::Ewma(weight)),
+                _ => Err(format!(
+                    "EWMA weight must be in (0, 1] in aggregation '{}'",
+                    s
+                )),
+            };
+        }
+        Err(format!(
+            "unknown aggregation '{}', expected mean, last, sum, min, max, median, p<N>, ewma:<weight>, count, per_second, twmean or rate",
+            s
+        ))
+    }
+}
+
+impl TryFrom<String> for Aggregation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Aggregation> for String {
+    fn from(aggregation: Aggregation) -> String {
+        aggregation.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aggregation;
+    use crate::testing::{builder, column, goal, profile, profiling_tables, step};
+    use crate::{OperatingMode, SamplingPlan};
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    fn aggregate(aggregation: &str, vals: &[f64]) -> f64 {
+        let aggregation: Aggregation = aggregation.parse().unwrap();
+        let func = aggregation.function(&Arc::new(AtomicU64::new(4))).unwrap();
+        func(vals)
+    }
+
+    #[test]
+    fn aggregate_window() {
+        let vals = [4.0, 1.0, 3.0, 2.0];
+        assert_eq!(aggregate("last", &vals), 2.0);
+        assert_eq!(aggregate("sum", &vals), 10.0);
+        assert_eq!(aggregate("min", &vals), 1.0);
+        assert_eq!(aggregate("max", &vals), 4.0);
+        assert_eq!(aggregate("ewma:0.5", &vals), 2.375);
+        assert_eq!(aggregate("count", &vals), 4.0);
+        assert_eq!(aggregate("rate", &vals), 0.5);
+        assert!(aggregate("max", &[]).is_nan());
+        assert!(Aggregation::Mean
+            .function(&Arc::new(AtomicU64::new(1)))
+            .is_none());
+        assert_eq!(Aggregation::Median.quantile(), Some(0.5));
+        assert!(Aggregation::PerSecond
+            .function(&Arc::new(AtomicU64::new(1)))
+            .is_none());
+
+        assert_eq!("p99.9".parse(), Ok(Aggregation::Percentile(99.9)));
+        assert_eq!(Aggregation::Percentile(99.0).quantile(), Some(0.99));
+        assert_eq!(
+            Aggregation::Ewma(0.2).to_string().parse(),
+            Ok(Aggregation::Ewma(0.2))
+        );
+        assert!("p101".parse::<Aggregation>().is_err());
+        assert!("ewma:0".parse::<Aggregation>().is_err());
+        assert!("mode".parse::<Aggregation>().is_err());
+        assert_eq!(Aggregation::default_for("energyDelta"), Aggregation::Rate);
+        assert_eq!(Aggregation::default_for("latency"), Aggregation::Mean);
+    }
+
+    #[test]
+    fn choose_aggregations() {
+        let config = builder(&step())
+            .goal(goal().aggregation("quality", Aggregation::Percentile(99.0)))
+            .aggregation("quality", Aggregation::Min)
+            .aggregation("operations", Aggregation::Max)
+            .build()
+            .unwrap();
+        assert_eq!(config.aggregation("quality"), Aggregation::Percentile(99.0));
+        assert_eq!(config.aggregation("operations"), Aggregation::Max);
+        assert_eq!(config.aggregation("energyDelta"), Aggregation::Rate);
+        assert_eq!(config.aggregation("latency"), Aggregation::Mean);
+    }
+
+    #[test]
+    fn profile_configured_aggregations() {
+        let output_dir = "/tmp/apto_aggregated_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .mode(OperatingMode::Profile {
+                iterations: 3,
+                plan: SamplingPlan::Full,
+            })
+            .output_dir(output_dir)
+            .aggregation("operations", Aggregation::Max)
+            .aggregation("quality", Aggregation::Sum)
+            .build()
+            .unwrap();
+        let mut operations = 0.0;
+        let profiled = profile(config, |apto, _| {
+            operations += 100.0;
+            apto.record("operations", operations);
+            apto.record("quality", 0.5);
+        });
+        let (max, sum) = (
+            column(&profiled, "operations"),
+            column(&profiled, "quality"),
+        );
+        let rows: Vec<(f64, f64)> = profiled
+            .profile
+            .iter()
+            .map(|row| (row[max], row[sum]))
+            .collect();
+        assert_eq!(rows, vec![(300.0, 1.5), (600.0, 1.5)]);
+    }
+}
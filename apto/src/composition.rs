@@ -0,0 +1,338 @@
+use crate::profile::{ActiveModel, KnobTable, MeasureTable, ProfileError};
+use log::warn;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Combines the normalized performance of all tenants into one column of the
+/// composed profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfAggregate {
+    Harmonic,
+    Geometric,
+    Arithmetic,
+    Min,
+}
+
+impl PerfAggregate {
+    pub fn column(&self) -> &'static str {
+        match self {
+            PerfAggregate::Harmonic => "harmonicMeanPerf",
+            PerfAggregate::Geometric => "geometricMeanPerf",
+            PerfAggregate::Arithmetic => "arithmeticMeanPerf",
+            PerfAggregate::Min => "minPerf",
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        let n = values.len() as f64;
+        match self {
+            PerfAggregate::Harmonic => n / values.iter().map(|v| 1.0 / v).sum::<f64>(),
+            PerfAggregate::Geometric => (values.iter().map(|v| v.ln()).sum::<f64>() / n).exp(),
+            PerfAggregate::Arithmetic => values.iter().sum::<f64>() / n,
+            PerfAggregate::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+        }
+    }
+}
+
+impl FromStr for PerfAggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "harmonic" => Ok(PerfAggregate::Harmonic),
+            "geometric" => Ok(PerfAggregate::Geometric),
+            "arithmetic" => Ok(PerfAggregate::Arithmetic),
+            "min" => Ok(PerfAggregate::Min),
+            _ => Err(format!("unknown performance aggregate '{}'", s)),
+        }
+    }
+}
+
+/// A single-application profile over the system knobs together with the
+/// latency the application has to meet.
+pub struct Tenant {
+    pub measure_table: MeasureTable,
+    pub knob_table: KnobTable,
+    pub target: f64,
+}
+
+/// Composes the profiles of co-located applications into the profile of the
+/// system scheduler. For every system configuration the composed profile has
+/// the normalized performance `perf<i>` of every tenant, the requested
+/// aggregates of them and the summed power consumption.
+///
+/// The performance of tenant `i` is its target speedup over its fastest
+/// configuration divided by the speedup of the configuration averaged over
+/// all tenants, so that 1.0 means the targets are met on average.
+pub struct ProfileComposer {
+    tenants: Vec<Tenant>,
+    aggregates: Vec<PerfAggregate>,
+    latency_measure: String,
+    power_measure: String,
+}
+
+impl Default for ProfileComposer {
+    fn default() -> Self {
+        ProfileComposer::new()
+    }
+}
+
+impl ProfileComposer {
+    pub fn new() -> ProfileComposer {
+        ProfileComposer {
+            tenants: Vec::new(),
+            aggregates: Vec::new(),
+            latency_measure: "appLatency".to_string(),
+            power_measure: "powerConsumption".to_string(),
+        }
+    }
+
+    pub fn tenant(
+        mut self,
+        measure_table: MeasureTable,
+        knob_table: KnobTable,
+        target: f64,
+    ) -> Self {
+        self.tenants.push(Tenant {
+            measure_table,
+            knob_table,
+            target,
+        });
+        self
+    }
+
+    /// Adds an aggregate column. Without any, only the harmonic mean is added.
+    pub fn aggregate(mut self, aggregate: PerfAggregate) -> Self {
+        if !self.aggregates.contains(&aggregate) {
+            self.aggregates.push(aggregate);
+        }
+        self
+    }
+
+    pub fn latency_measure(mut self, name: &str) -> Self {
+        self.latency_measure = name.to_string();
+        self
+    }
+
+    pub fn power_measure(mut self, name: &str) -> Self {
+        self.power_measure = name.to_string();
+        self
+    }
+
+    /// Joins the tenants on their knob settings; configurations missing from
+    /// any tenant or without a positive latency are left out. Ids follow the
+    /// order of the first tenant.
+    pub fn compose(&self) -> Result<(MeasureTable, KnobTable), ProfileError> {
+        let Some(first) = self.tenants.first() else {
+            return Ok((
+                MeasureTable {
+                    names: vec!["id".to_string()],
+                    profile: Vec::new(),
+                },
+                KnobTable {
+                    names: vec!["id".to_string()],
+                    configurations: Vec::new(),
+                },
+            ));
+        };
+        let knob_names: Vec<String> = first
+            .knob_table
+            .names
+            .iter()
+            .filter(|&name| name != "id")
+            .cloned()
+            .collect();
+
+        // Per tenant: knob settings -> (latency, power)
+        let mut tenant_measures = Vec::with_capacity(self.tenants.len());
+        for tenant in self.tenants.iter() {
+            for name in knob_names.iter() {
+                if !tenant.knob_table.names.contains(name) {
+                    return Err(ProfileError::MissingKnob {
+                        knob: name.clone(),
+                        header: tenant.knob_table.names.clone(),
+                    });
+                }
+            }
+            let column = |measure: &str| {
+                tenant
+                    .measure_table
+                    .names
+                    .iter()
+                    .position(|name| name == measure)
+                    .ok_or_else(|| ProfileError::MissingMeasure {
+                        role: "Composition",
+                        measure: measure.to_string(),
+                        header: tenant.measure_table.names.clone(),
+                    })
+            };
+            let (latency_idx, power_idx) =
+                (column(&self.latency_measure)?, column(&self.power_measure)?);
+            let measures: HashMap<Vec<u64>, (f64, f64)> =
+                ActiveModel::new(&tenant.measure_table, &tenant.knob_table)
+                    .configs
+                    .into_iter()
+                    .filter(|(row, _)| row[latency_idx].is_finite() && row[latency_idx] > 0.0)
+                    .map(|(row, settings)| {
+                        let knobs = knob_names.iter().map(|name| settings[name]).collect();
+                        (knobs, (row[latency_idx], row[power_idx]))
+                    })
+                    .collect();
+            tenant_measures.push(measures);
+        }
+
+        let mut configurations: Vec<Vec<u64>> =
+            ActiveModel::new(&first.measure_table, &first.knob_table)
+                .configs
+                .into_iter()
+                .map(|(_, settings)| knob_names.iter().map(|name| settings[name]).collect())
+                .collect();
+        let nr_configurations = configurations.len();
+        configurations.retain(|knobs| tenant_measures.iter().all(|m| m.contains_key(knobs)));
+        if configurations.len() < nr_configurations {
+            warn!(
+                "{} configurations are not profiled for every tenant",
+                nr_configurations - configurations.len()
+            );
+        }
+
+        let min_latencies: Vec<f64> = tenant_measures
+            .iter()
+            .map(|measures| {
+                configurations
+                    .iter()
+                    .map(|knobs| measures[knobs].0)
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .collect();
+        let aggregates = if self.aggregates.is_empty() {
+            vec![PerfAggregate::Harmonic]
+        } else {
+            self.aggregates.clone()
+        };
+
+        let mut names = vec!["id".to_string()];
+        names.extend((0..self.tenants.len()).map(|idx| format!("perf{}", idx)));
+        names.extend(aggregates.iter().map(|a| a.column().to_string()));
+        names.push(self.power_measure.clone());
+
+        let mut profile = Vec::with_capacity(configurations.len());
+        let mut kt_configurations = Vec::with_capacity(configurations.len());
+        for (id, knobs) in configurations.into_iter().enumerate() {
+            let speedups: Vec<f64> = tenant_measures
+                .iter()
+                .zip(min_latencies.iter())
+                .map(|(measures, min_latency)| measures[&knobs].0 / min_latency)
+                .collect();
+            let generic_speedup = speedups.iter().sum::<f64>() / speedups.len() as f64;
+            let perfs: Vec<f64> = self
+                .tenants
+                .iter()
+                .zip(min_latencies.iter())
+                .map(|(tenant, min_latency)| tenant.target / min_latency / generic_speedup)
+                .collect();
+            let power: f64 = tenant_measures.iter().map(|m| m[&knobs].1).sum();
+
+            let mut row = vec![id as f64];
+            row.extend(perfs.iter().copied());
+            row.extend(aggregates.iter().map(|a| a.apply(&perfs)));
+            row.push(power);
+            profile.push(row);
+
+            kt_configurations.push(
+                std::iter::once(("id".to_string(), id as u64))
+                    .chain(knob_names.iter().cloned().zip(knobs))
+                    .collect(),
+            );
+        }
+
+        Ok((
+            MeasureTable { names, profile },
+            KnobTable {
+                names: std::iter::once("id".to_string())
+                    .chain(knob_names)
+                    .collect(),
+                configurations: kt_configurations,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PerfAggregate, ProfileComposer};
+    use crate::profile::{KnobTable, MeasureTable};
+
+    fn tenant(rows: &[(u64, f64, f64)]) -> (MeasureTable, KnobTable) {
+        let names = vec!["id".to_string(), "uncore".to_string()];
+        let knob_table = KnobTable {
+            names: names.clone(),
+            configurations: rows
+                .iter()
+                .enumerate()
+                .map(|(id, (uncore, _, _))| {
+                    names.iter().cloned().zip([id as u64, *uncore]).collect()
+                })
+                .collect(),
+        };
+        let measure_table = MeasureTable {
+            names: ["id", "appLatency", "powerConsumption"]
+                .map(String::from)
+                .to_vec(),
+            profile: rows
+                .iter()
+                .enumerate()
+                .map(|(id, (_, latency, power))| vec![id as f64, *latency, *power])
+                .collect(),
+        };
+        (measure_table, knob_table)
+    }
+
+    #[test]
+    fn compose_tenants() {
+        let (mt0, kt0) = tenant(&[(12, 4.0, 10.0), (24, 2.0, 20.0), (36, 1.0, 30.0)]);
+        // Listed in a different order and without the slowest setting
+        let (mt1, kt1) = tenant(&[(36, 10.0, 3.0), (24, 30.0, 2.0), (12, 0.0, 0.0)]);
+        let composer = ProfileComposer::new()
+            .tenant(mt0, kt0, 2.0)
+            .tenant(mt1, kt1, 20.0)
+            .aggregate(PerfAggregate::Harmonic)
+            .aggregate(PerfAggregate::Min);
+        let (mt, kt) = composer.compose().unwrap();
+
+        assert_eq!(
+            mt.names,
+            vec![
+                "id",
+                "perf0",
+                "perf1",
+                "harmonicMeanPerf",
+                "minPerf",
+                "powerConsumption"
+            ]
+        );
+        assert_eq!(kt.configurations.len(), 2);
+        assert_eq!(kt.configurations[0]["uncore"], 24);
+        // uncore 24: speedups 2 and 3 average 2.5, targets 2 and 2 over it
+        assert_eq!(mt.profile[0], vec![0.0, 0.8, 0.8, 0.8, 0.8, 22.0]);
+        // uncore 36: both at their fastest, targets 2 and 2
+        assert_eq!(mt.profile[1], vec![1.0, 2.0, 2.0, 2.0, 2.0, 33.0]);
+
+        let (mt2, kt2) = tenant(&[(24, 1.0, 1.0)]);
+        let composer = ProfileComposer::new()
+            .tenant(mt2.clone(), kt2.clone(), 1.0)
+            .tenant(mt2, kt2, 4.0)
+            .aggregate("geometric".parse().unwrap())
+            .aggregate("arithmetic".parse().unwrap());
+        let (mt, _) = composer.compose().unwrap();
+        assert!((mt.profile[0][3] - 2.0).abs() < 1e-12);
+        assert_eq!(mt.profile[0][4..], [2.5, 2.0]);
+
+        let (mt3, kt3) = tenant(&[(24, 1.0, 1.0)]);
+        let missing = ProfileComposer::new()
+            .tenant(mt3, kt3, 1.0)
+            .latency_measure("latency");
+        assert!(missing.compose().is_err());
+        assert!("median".parse::<PerfAggregate>().is_err());
+    }
+}
@@ -1,21 +1,420 @@
-use crate::profile::{KnobTable, MeasureTable};
+use crate::aggregation::Aggregation;
+use crate::derived::DerivedMeasure;
+use crate::knobs::{apply_order, SharedKnob};
+use crate::outliers::OutlierPolicy;
+use crate::profile::{KnobTable, MeasureTable, ProfileError};
+use crate::profile_file::{ProfileFile, ProfileFormat};
+use crate::profile_output::{FlushPolicy, ProfileOutput};
+use crate::sampling::SamplingPlan;
+use crate::telemetry::{Telemetry, TelemetryFormat};
+use crate::timeline::GoalTimeline;
+use crate::validation::{validate_profile, ValidationReport};
+use crate::window::WindowPolicy;
 use crate::Goal;
 use crate::Tunable;
-use std::collections::HashMap;
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
-use std::path::Path;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub struct Configurations<T> {
+/// What an Apto instance does with the windows it observes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperatingMode {
+    /// Measure every configuration of `plan` for `iterations` iterations.
+    Profile { iterations: u64, plan: SamplingPlan },
+    /// Let the controller pick the configurations.
+    Adaptive,
+    /// Keep the configuration the knobs were set to.
+    NonAdaptive,
+}
+
+/// How the model follows what the configurations achieve at run time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LearningPolicy {
+    /// Weight of a new observation in the moving average, in (0, 1].
+    pub weight: f64,
+    /// Number of learned windows after which the controller is handed the
+    /// refined model.
+    pub period: u64,
+}
+
+impl LearningPolicy {
+    /// Hands the controller the refined model every 10 windows.
+    pub fn new(weight: f64) -> LearningPolicy {
+        LearningPolicy { weight, period: 10 }
+    }
+
+    pub fn period(mut self, period: u64) -> LearningPolicy {
+        self.period = period;
+        self
+    }
+
+    fn check(self) -> Result<LearningPolicy, ProfileError> {
+        let invalid = |message: String| ProfileError::InvalidSetting {
+            setting: "online learning",
+            message,
+        };
+        if !(self.weight > 0.0 && self.weight <= 1.0) {
+            return Err(invalid(format!(
+                "weight must be in (0, 1] but is {}",
+                self.weight
+            )));
+        }
+        if self.period == 0 {
+            return Err(invalid("period must be at least one window".to_string()));
+        }
+        Ok(self)
+    }
+}
+
+pub struct Configurations<T, H = Rc<dyn Tunable<T>>> {
     pub(crate) instance_id: usize,
-    pub(crate) measure_table: MeasureTable,
-    pub(crate) knob_table: KnobTable,
-    pub(crate) knobs: HashMap<String, Rc<dyn Tunable<T>>>,
+    /// Shared with every other instance built from the same tables; online
+    /// learning copies them before refining.
+    pub(crate) measure_table: Arc<MeasureTable>,
+    pub(crate) knob_table: Arc<KnobTable>,
+    /// `Rc<dyn Tunable<T>>`, or `SharedKnob<T>` for an instance that is used
+    /// from several threads.
+    pub(crate) knobs: HashMap<String, H>,
+    /// Names of the knobs in the order they are set, see `Tunable::apply_after`.
+    pub(crate) apply_order: Vec<String>,
     pub(crate) goal: Goal,
-    pub(crate) window_size: u64,
+    pub(crate) window: WindowPolicy,
+    pub(crate) output: ProfileOutput,
+    pub(crate) mode: OperatingMode,
+    /// Whether environment variables such as `PROFILE` and `WINDOW` take
+    /// precedence over the configured settings.
+    pub(crate) env_overrides: bool,
+    /// Whether every window is logged at info level.
+    pub(crate) log_state: bool,
+    /// Measures and knobs recorded every iteration, if any.
+    pub(crate) telemetry: Option<Telemetry>,
+    /// Socket the instance accepts control requests on, if any.
+    pub(crate) control_socket: Option<PathBuf>,
+    /// Goal changes that are still to be applied.
+    pub(crate) timeline: Option<GoalTimeline>,
+    /// Aggregations of measures that are not aggregated as by default, see
+    /// `Aggregation::default_for`.
+    pub(crate) aggregations: BTreeMap<String, Aggregation>,
+    /// Measures computed from other measures, in the order they are
+    /// evaluated.
+    pub(crate) derived: Vec<DerivedMeasure>,
+    /// Measures whose values are filtered for outliers before they are
+    /// aggregated.
+    pub(crate) outlier_policies: BTreeMap<String, OutlierPolicy>,
+    /// Whether configurations that are dominated for the goal are left out
+    /// of the model.
+    pub(crate) prune_dominated: bool,
+    /// Whether knob combinations missing from the profile are predicted
+    /// from the profiled ones.
+    pub(crate) predict_missing: bool,
+    /// Whether predicted configurations are left out of the model again.
+    pub(crate) exclude_predicted: bool,
+    pub(crate) online_learning: Option<LearningPolicy>,
+    /// Runs of every configuration when profiling, averaged into its row.
+    pub(crate) profile_repetitions: usize,
+    /// Iterations of every profiling run whose measures are discarded.
+    pub(crate) profile_warmup: usize,
+    /// Type of the knob values.
+    value_type: PhantomData<T>,
+}
+
+enum ProfileTables {
+    Paths(String, String),
+    InMemory(Arc<MeasureTable>, Arc<KnobTable>),
+}
+
+/// Assembles an `AptoConfig` from named settings. Only the tables, the goal
+/// and the window size are required.
+pub struct ConfigurationsBuilder<T, H = Rc<dyn Tunable<T>>> {
+    instance_id: usize,
+    tables: Option<ProfileTables>,
+    knobs: Vec<H>,
+    goal: Option<Goal>,
+    window: Option<WindowPolicy>,
+    mode: OperatingMode,
+    output: ProfileOutput,
+    env_overrides: bool,
+    log_state: bool,
+    telemetry: Option<Telemetry>,
+    control_socket: Option<PathBuf>,
+    timeline: Option<GoalTimeline>,
+    aggregations: BTreeMap<String, Aggregation>,
+    derived: Vec<DerivedMeasure>,
+    outlier_policies: BTreeMap<String, OutlierPolicy>,
+    prune_dominated: bool,
+    predict_missing: bool,
+    exclude_predicted: bool,
+    online_learning: Option<LearningPolicy>,
+    profile_repetitions: usize,
+    profile_warmup: usize,
+    any_host: bool,
+    value_type: PhantomData<T>,
+}
+
+impl<T: Copy, H: Tunable<T>> ConfigurationsBuilder<T, H> {
+    fn new() -> ConfigurationsBuilder<T, H> {
+        ConfigurationsBuilder {
+            instance_id: 0,
+            tables: None,
+            knobs: Vec::new(),
+            goal: None,
+            window: None,
+            mode: OperatingMode::Adaptive,
+            output: ProfileOutput::default(),
+            env_overrides: true,
+            log_state: true,
+            telemetry: None,
+            control_socket: None,
+            timeline: None,
+            aggregations: BTreeMap::new(),
+            derived: Vec::new(),
+            outlier_policies: BTreeMap::new(),
+            prune_dominated: false,
+            predict_missing: false,
+            exclude_predicted: false,
+            online_learning: None,
+            profile_repetitions: 1,
+            profile_warmup: 0,
+            any_host: false,
+            value_type: PhantomData,
+        }
+    }
+
+    pub fn instance_id(mut self, instance_id: usize) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Reads the profile from a measure table and knob table, or from a
+    /// combined profile if `mt_path` has a `.yaml`, `.json` or `.bin` extension.
+    pub fn tables<P: Display>(mut self, mt_path: P, kt_path: P) -> Self {
+        self.tables = Some(ProfileTables::Paths(
+            mt_path.to_string(),
+            kt_path.to_string(),
+        ));
+        self
+    }
+
+    /// Uses tables that are already in memory. Passing the same `Arc`s to
+    /// several instances shares one copy of the profile between them.
+    pub fn profile<M, K>(mut self, measure_table: M, knob_table: K) -> Self
+    where
+        M: Into<Arc<MeasureTable>>,
+        K: Into<Arc<KnobTable>>,
+    {
+        self.tables = Some(ProfileTables::InMemory(
+            measure_table.into(),
+            knob_table.into(),
+        ));
+        self
+    }
+
+    pub fn knob(mut self, knob: H) -> Self {
+        self.knobs.push(knob);
+        self
+    }
+
+    pub fn knobs(mut self, knobs: Vec<H>) -> Self {
+        self.knobs.extend(knobs);
+        self
+    }
+
+    pub fn goal(mut self, goal: Goal) -> Self {
+        self.goal = Some(goal);
+        self
+    }
+
+    pub fn window_size(mut self, window_size: u64) -> Self {
+        self.window = Some(WindowPolicy::Iterations(window_size));
+        self
+    }
+
+    /// Ends windows after a duration instead of a number of iterations.
+    pub fn window(mut self, window: WindowPolicy) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn mode(mut self, mode: OperatingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Writes the profiling tables to `dir` instead of the working directory.
+    pub fn output_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.output = ProfileOutput::in_dir(dir).flush(self.output.flush);
+        self
+    }
+
+    pub fn profile_output(mut self, output: ProfileOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// With `false`, the environment cannot change any setting; useful in
+    /// tests.
+    pub fn env_overrides(mut self, enabled: bool) -> Self {
+        self.env_overrides = enabled;
+        self
+    }
+
+    /// Logs every window at info level (on by default).
+    pub fn log_state(mut self, enabled: bool) -> Self {
+        self.log_state = enabled;
+        self
+    }
+
+    /// Records the selected measures and knobs after every iteration.
+    pub fn telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Accepts control requests on a Unix domain socket at `path`, see
+    /// `ControlRequest`.
+    pub fn control_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.control_socket = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Changes the goal on its own as the run progresses.
+    pub fn goal_timeline(mut self, timeline: GoalTimeline) -> Self {
+        self.timeline = Some(timeline);
+        self
+    }
+
+    /// Aggregates `measure` over a window with `aggregation`, unless the
+    /// goal names another one.
+    pub fn aggregation(mut self, measure: &str, aggregation: Aggregation) -> Self {
+        self.aggregations.insert(measure.to_string(), aggregation);
+        self
+    }
+
+    /// Computes a measure from others; later ones can use earlier ones.
+    pub fn derived_measure(mut self, measure: DerivedMeasure) -> Self {
+        self.derived.push(measure);
+        self
+    }
+
+    /// Rejects or clamps outliers among the values of `measure`, see
+    /// `OutlierPolicy`.
+    pub fn outlier_policy(mut self, measure: &str, policy: OutlierPolicy) -> Self {
+        self.outlier_policies.insert(measure.to_string(), policy);
+        self
+    }
+
+    /// Leaves out the configurations the controller would never pick for
+    /// the goal (off by default).
+    pub fn prune_dominated(mut self, enabled: bool) -> Self {
+        self.prune_dominated = enabled;
+        self
+    }
+
+    /// Predicts the knob combinations that were not profiled from the
+    /// profiled ones (off by default).
+    pub fn predict_missing(mut self, enabled: bool) -> Self {
+        self.predict_missing = enabled;
+        self
+    }
+
+    /// Drops the predicted configurations from the model again, e.g. to
+    /// compare control with and without them from the same configuration.
+    pub fn exclude_predicted(mut self, enabled: bool) -> Self {
+        self.exclude_predicted = enabled;
+        self
+    }
+
+    /// Refines the model with what the configurations achieve at run time.
+    pub fn online_learning(mut self, policy: LearningPolicy) -> Self {
+        self.online_learning = Some(policy);
+        self
+    }
+
+    /// Profiles every configuration `repetitions` times (once by default).
+    pub fn profile_repetitions(mut self, repetitions: usize) -> Self {
+        self.profile_repetitions = repetitions.max(1);
+        self
+    }
+
+    /// Discards the first `iterations` of every profiling run.
+    pub fn profile_warmup(mut self, iterations: usize) -> Self {
+        self.profile_warmup = iterations;
+        self
+    }
+
+    /// Accepts a combined profile that was recorded on another machine.
+    pub fn any_host(mut self, enabled: bool) -> Self {
+        self.any_host = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Configurations<T, H>, ProfileError> {
+        let goal = self.goal.ok_or(ProfileError::MissingSetting("goal"))?;
+        let window = self
+            .window
+            .ok_or(ProfileError::MissingSetting("window size"))?;
+        let any_host = self.any_host || (self.env_overrides && env_flag("PROFILE_ANY_HOST"));
+        let mut config = match self.tables {
+            Some(ProfileTables::Paths(mt_path, kt_path)) => Configurations::load(
+                self.instance_id,
+                (mt_path, kt_path),
+                self.knobs,
+                goal,
+                window,
+                any_host,
+            )?,
+            Some(ProfileTables::InMemory(measure_table, knob_table)) => {
+                Configurations::check_tables(
+                    self.instance_id,
+                    (measure_table, "<measure table>".to_string()),
+                    (knob_table, "<knob table>".to_string()),
+                    self.knobs,
+                    goal,
+                    window,
+                )?
+            }
+            None => return Err(ProfileError::MissingSetting("profile tables")),
+        };
+        config.mode = self.mode;
+        config.output = self.output;
+        config.env_overrides = self.env_overrides;
+        config.log_state = self.log_state;
+        config.telemetry = self.telemetry;
+        config.control_socket = self.control_socket;
+        config.timeline = self.timeline;
+        config.aggregations = self.aggregations;
+        config.outlier_policies = self.outlier_policies;
+        config.prune_dominated = self.prune_dominated;
+        config.predict_missing = self.predict_missing;
+        config.exclude_predicted = self.exclude_predicted;
+        config.online_learning = self
+            .online_learning
+            .map(LearningPolicy::check)
+            .transpose()?;
+        config.profile_repetitions = self.profile_repetitions;
+        config.profile_warmup = self.profile_warmup;
+        for measure in self.derived {
+            config.add_derived_measure(measure);
+        }
+        Ok(config)
+    }
+}
+
+/// Whether the environment variable `name` is set.
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok()
 }
 
 impl<T: Copy> Configurations<T> {
+    pub fn builder() -> ConfigurationsBuilder<T> {
+        ConfigurationsBuilder::new()
+    }
+
     pub fn new<P>(
         instance_id: usize,
         mt_path: P,
@@ -27,17 +426,554 @@ impl<T: Copy> Configurations<T> {
     where
         P: AsRef<Path> + Display,
     {
-        let measure_table = MeasureTable::new(mt_path);
-        let knob_table = KnobTable::new(kt_path);
-        let knobs = knobs.into_iter().map(|k| (k.name(), k)).collect();
+        Configurations::try_new(instance_id, mt_path, kt_path, knobs, goal, window_size)
+            .unwrap_or_else(|e| panic!("Invalid profile for instance {}: {}", instance_id, e))
+    }
 
-        Configurations {
+    /// `mt_path` may also point to a combined profile (`.yaml`, `.json` or
+    /// `.bin`), in which case `kt_path` is ignored and the profile is only
+    /// accepted if it was recorded on this machine.
+    pub fn try_new<P>(
+        instance_id: usize,
+        mt_path: P,
+        kt_path: P,
+        knobs: Vec<Rc<dyn Tunable<T>>>,
+        goal: Goal,
+        window_size: u64,
+    ) -> Result<Configurations<T>, ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        let window = WindowPolicy::Iterations(window_size);
+        let any_host = env_flag("PROFILE_ANY_HOST");
+        Configurations::load(
+            instance_id,
+            (mt_path, kt_path),
+            knobs,
+            goal,
+            window,
+            any_host,
+        )
+    }
+
+    pub fn from_tables<M, K>(
+        instance_id: usize,
+        measure_table: M,
+        knob_table: K,
+        knobs: Vec<Rc<dyn Tunable<T>>>,
+        goal: Goal,
+        window_size: u64,
+    ) -> Configurations<T>
+    where
+        M: Into<Arc<MeasureTable>>,
+        K: Into<Arc<KnobTable>>,
+    {
+        Configurations::try_from_tables(
             instance_id,
             measure_table,
             knob_table,
             knobs,
             goal,
             window_size,
+        )
+        .unwrap_or_else(|e| panic!("Invalid profile for instance {}: {}", instance_id, e))
+    }
+
+    /// Same as `try_new` for tables that are already in memory, e.g. shared
+    /// between the instances of a multi-tenant run.
+    pub fn try_from_tables<M, K>(
+        instance_id: usize,
+        measure_table: M,
+        knob_table: K,
+        knobs: Vec<Rc<dyn Tunable<T>>>,
+        goal: Goal,
+        window_size: u64,
+    ) -> Result<Configurations<T>, ProfileError>
+    where
+        M: Into<Arc<MeasureTable>>,
+        K: Into<Arc<KnobTable>>,
+    {
+        Configurations::check_tables(
+            instance_id,
+            (measure_table.into(), "<measure table>".to_string()),
+            (knob_table.into(), "<knob table>".to_string()),
+            knobs,
+            goal,
+            WindowPolicy::Iterations(window_size),
+        )
+    }
+}
+
+impl<T: Copy> Configurations<T, SharedKnob<T>> {
+    /// Same as `builder` for an instance that is created on one thread and
+    /// used from others.
+    pub fn shared_builder() -> ConfigurationsBuilder<T, SharedKnob<T>> {
+        ConfigurationsBuilder::new()
+    }
+}
+
+impl<T: Copy, H: Tunable<T>> Configurations<T, H> {
+    /// Reads the tables at `paths`, accepting a combined profile recorded
+    /// on another machine if `any_host` is set.
+    fn load<P>(
+        instance_id: usize,
+        (mt_path, kt_path): (P, P),
+        knobs: Vec<H>,
+        goal: Goal,
+        window: WindowPolicy,
+        any_host: bool,
+    ) -> Result<Configurations<T, H>, ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        let mt_name = mt_path.to_string();
+        let kt_name = kt_path.to_string();
+        let tables = match ProfileFormat::from_path(&mt_path) {
+            Some(_) => {
+                let profile = ProfileFile::load(mt_path)?;
+                profile.check_host(&mt_name, any_host)?;
+                if profile.metadata.window_size != window.resolution() {
+                    warn!(
+                        "{} was profiled with window {} but instance {} uses {}",
+                        mt_name, profile.metadata.window_size, instance_id, window
+                    );
+                }
+                (profile.measure_table(), profile.knob_table())
+            }
+            None => (
+                MeasureTable::try_new(mt_path)?,
+                KnobTable::try_new(kt_path)?,
+            ),
+        };
+        Configurations::check_tables(
+            instance_id,
+            (Arc::new(tables.0), mt_name),
+            (Arc::new(tables.1), kt_name),
+            knobs,
+            goal,
+            window,
+        )
+    }
+
+    /// Checks the tables against the knobs and goal. The names only label
+    /// the tables in errors.
+    fn check_tables(
+        instance_id: usize,
+        (measure_table, mt_name): (Arc<MeasureTable>, String),
+        (knob_table, kt_name): (Arc<KnobTable>, String),
+        knobs: Vec<H>,
+        goal: Goal,
+        window: WindowPolicy,
+    ) -> Result<Configurations<T, H>, ProfileError> {
+        let knobs = knobs.into_iter().map(|k| (k.name(), k)).collect();
+        let apply_order = apply_order(&knobs).map_err(ProfileError::KnobCycle)?;
+
+        let config = Configurations {
+            instance_id,
+            measure_table,
+            knob_table,
+            knobs,
+            apply_order,
+            goal,
+            window,
+            output: ProfileOutput::default(),
+            mode: OperatingMode::Adaptive,
+            env_overrides: true,
+            log_state: true,
+            telemetry: None,
+            control_socket: None,
+            timeline: None,
+            aggregations: BTreeMap::new(),
+            derived: Vec::new(),
+            outlier_policies: BTreeMap::new(),
+            prune_dominated: false,
+            predict_missing: false,
+            exclude_predicted: false,
+            online_learning: None,
+            profile_repetitions: 1,
+            profile_warmup: 0,
+            value_type: PhantomData,
+        };
+        config.check_goal()?;
+
+        let report = config.validate();
+        if !report.is_valid() {
+            return Err(ProfileError::Inconsistent {
+                measure_table: mt_name,
+                knob_table: kt_name,
+                report,
+            });
+        }
+        for issue in report.issues.iter() {
+            warn!("{} (instance {})", issue, instance_id);
+        }
+
+        Ok(config)
+    }
+
+    /// Where profiling writes its tables instead of the working directory.
+    pub fn set_profile_output(&mut self, output: ProfileOutput) {
+        self.output = output;
+    }
+
+    /// The configured mode, unless the environment overrides it: `PROFILE`
+    /// switches to profiling for that many iterations and `PROFILE_PLAN`
+    /// replaces the profiling plan.
+    pub(crate) fn resolved_mode(&self) -> Result<OperatingMode, ProfileError> {
+        let plan: Option<SamplingPlan> = self.env_setting("PROFILE_PLAN")?;
+        let iterations: Option<u64> = self.env_setting("PROFILE")?;
+        let (configured, planned) = match &self.mode {
+            OperatingMode::Profile { iterations, plan } => (Some(*iterations), Some(plan.clone())),
+            _ => (None, None),
+        };
+        let mode = match iterations.or(configured) {
+            Some(iterations) => OperatingMode::Profile {
+                iterations,
+                plan: plan.or(planned).unwrap_or(SamplingPlan::Full),
+            },
+            None => self.mode.clone(),
+        };
+        Ok(mode)
+    }
+
+    /// The configured flush policy, unless `PROFILE_FLUSH` overrides it.
+    pub(crate) fn resolved_flush(&self) -> Result<FlushPolicy, ProfileError> {
+        let flush = self.env_setting("PROFILE_FLUSH")?;
+        Ok(flush.unwrap_or(self.output.flush))
+    }
+
+    pub fn set_window(&mut self, window: WindowPolicy) {
+        self.window = window;
+    }
+
+    /// Iterations per window, or the number of parts a time-based window is
+    /// split into by the schedule.
+    pub(crate) fn window_size(&self) -> u64 {
+        self.window.resolution()
+    }
+
+    /// Replaces the window with the one in `WINDOW`, if set.
+    pub(crate) fn add_env_window(&mut self) -> Result<(), ProfileError> {
+        if let Some(window) = self.env_setting("WINDOW")? {
+            self.window = window;
+        }
+        Ok(())
+    }
+
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = Some(telemetry);
+    }
+
+    /// Adds a sink for `TELEMETRY=csv|jsonl|binary`, written to
+    /// `telemetry.<instance>.<ext>` in the working directory. Without any
+    /// configured telemetry it records what used to be dumped unconditionally.
+    pub(crate) fn add_env_telemetry(&mut self) -> Result<(), ProfileError> {
+        let Some(format) = self.env_setting::<TelemetryFormat>("TELEMETRY")? else {
+            return Ok(());
+        };
+        let path = format!("telemetry.{}.{}", self.instance_id, format.extension());
+        let sink = format
+            .create(&path)
+            .map_err(|source| ProfileError::Io { path, source })?;
+        let telemetry = self.telemetry.take().unwrap_or_else(Telemetry::legacy);
+        self.telemetry = Some(telemetry.boxed_sink(sink));
+        Ok(())
+    }
+
+    pub fn set_control_socket<P: AsRef<Path>>(&mut self, path: P) {
+        self.control_socket = Some(path.as_ref().to_path_buf());
+    }
+
+    /// The configured control socket, unless `CONTROL_SOCKET` names a
+    /// directory to create `apto.<instance>.sock` in.
+    pub(crate) fn resolved_control_socket(&self) -> Option<PathBuf> {
+        match std::env::var("CONTROL_SOCKET") {
+            Ok(dir) if self.env_overrides => {
+                Some(Path::new(&dir).join(format!("apto.{}.sock", self.instance_id)))
+            }
+            _ => self.control_socket.clone(),
+        }
+    }
+
+    pub fn set_goal_timeline(&mut self, timeline: GoalTimeline) {
+        self.timeline = Some(timeline);
+    }
+
+    /// Replaces the goal timeline with the one in the file `GOAL_TIMELINE`
+    /// names, if set, and checks every goal it switches to.
+    pub(crate) fn add_env_timeline(&mut self) -> Result<(), ProfileError> {
+        match std::env::var("GOAL_TIMELINE") {
+            Ok(path) if self.env_overrides => self.timeline = Some(GoalTimeline::load(path)?),
+            _ => {}
+        }
+        let mut goal = self.goal.clone();
+        for entry in self.timeline.iter().flat_map(|timeline| timeline.entries()) {
+            goal = entry.change.apply_to(&goal);
+            self.check_goal_measures(&goal)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_aggregation(&mut self, measure: &str, aggregation: Aggregation) {
+        self.aggregations.insert(measure.to_string(), aggregation);
+    }
+
+    /// Adds a derived measure, replacing one with the same name.
+    pub fn add_derived_measure(&mut self, measure: DerivedMeasure) {
+        match self
+            .derived
+            .iter_mut()
+            .find(|derived| derived.name() == measure.name())
+        {
+            Some(derived) => *derived = measure,
+            None => self.derived.push(measure),
+        }
+    }
+
+    pub fn set_outlier_policy(&mut self, measure: &str, policy: OutlierPolicy) {
+        self.outlier_policies.insert(measure.to_string(), policy);
+    }
+
+    pub(crate) fn outlier_policy(&self, measure: &str) -> Option<OutlierPolicy> {
+        self.outlier_policies.get(measure).copied()
+    }
+
+    /// Adds the outlier policies in `OUTLIERS`, a comma-separated list of
+    /// `<measure>=<policy>` such as `latency=mad:3.5,quality=range:0:1`.
+    pub(crate) fn add_env_outliers(&mut self) -> Result<(), ProfileError> {
+        let Some(policies) = self.env_setting::<String>("OUTLIERS")? else {
+            return Ok(());
+        };
+        let invalid = |message: String| ProfileError::InvalidSetting {
+            setting: "OUTLIERS",
+            message,
+        };
+        for entry in policies.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (measure, policy) = entry.split_once('=').ok_or_else(|| {
+                invalid(format!("expected <measure>=<policy> but got '{}'", entry))
+            })?;
+            let policy = policy.parse().map_err(invalid)?;
+            self.set_outlier_policy(measure.trim(), policy);
+        }
+        Ok(())
+    }
+
+    pub fn set_prune_dominated(&mut self, enabled: bool) {
+        self.prune_dominated = enabled;
+    }
+
+    /// Reads `name` from the environment, unless overrides are disabled.
+    fn env_setting<V>(&self, name: &'static str) -> Result<Option<V>, ProfileError>
+    where
+        V: FromStr,
+        V::Err: Display,
+    {
+        match std::env::var(name) {
+            Ok(value) if self.env_overrides => {
+                value
+                    .parse()
+                    .map(Some)
+                    .map_err(|e| ProfileError::InvalidSetting {
+                        setting: name,
+                        message: format!("'{}': {}", value, e),
+                    })
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Applies the model and profiling settings of the environment:
+    /// `PRUNE_DOMINATED` and `PREDICT_MISSING` enable pruning and prediction,
+    /// `EXCLUDE_PREDICTED` drops the predicted configurations again,
+    /// `ONLINE_LEARNING` enables learning with that weight, every
+    /// `ONLINE_LEARNING_PERIOD` windows, and `PROFILE_REPETITIONS` and
+    /// `PROFILE_WARMUP` set the runs and warmup iterations of profiling.
+    pub(crate) fn add_env_settings(&mut self) -> Result<(), ProfileError> {
+        if self.env_overrides && env_flag("PRUNE_DOMINATED") {
+            self.prune_dominated = true;
+        }
+        if self.env_overrides && env_flag("PREDICT_MISSING") {
+            self.predict_missing = true;
+        }
+        if self.env_overrides && env_flag("EXCLUDE_PREDICTED") {
+            self.exclude_predicted = true;
+        }
+        if let Some(weight) = self.env_setting("ONLINE_LEARNING")? {
+            let policy = self.online_learning.unwrap_or(LearningPolicy::new(weight));
+            self.online_learning = Some(LearningPolicy { weight, ..policy });
+        }
+        if let Some(period) = self.env_setting("ONLINE_LEARNING_PERIOD")? {
+            self.online_learning = self.online_learning.map(|policy| policy.period(period));
+        }
+        self.online_learning = self
+            .online_learning
+            .map(LearningPolicy::check)
+            .transpose()?;
+        if let Some(repetitions) = self.env_setting::<usize>("PROFILE_REPETITIONS")? {
+            self.profile_repetitions = repetitions.max(1);
+        }
+        if let Some(warmup) = self.env_setting("PROFILE_WARMUP")? {
+            self.profile_warmup = warmup;
+        }
+        Ok(())
+    }
+
+    /// How `measure` is aggregated: as the goal says, else as configured,
+    /// else by default.
+    pub(crate) fn aggregation(&self, measure: &str) -> Aggregation {
+        self.goal
+            .aggregations
+            .get(measure)
+            .or_else(|| self.aggregations.get(measure))
+            .copied()
+            .unwrap_or_else(|| Aggregation::default_for(measure))
+    }
+
+    /// Checks that the measure table and knob table agree with each other
+    /// and with the registered knobs.
+    pub fn validate(&self) -> ValidationReport {
+        validate_profile(&self.measure_table, &self.knob_table, self.knobs.keys())
+    }
+
+    /// Possible values of every registered knob.
+    pub(crate) fn knob_domains(&self) -> BTreeMap<String, Vec<T>> {
+        self.knobs
+            .iter()
+            .map(|(name, knob)| (name.clone(), knob.possible_values().to_vec()))
+            .collect()
+    }
+
+    /// Makes sure every measure the goal refers to is present in the measure table.
+    pub(crate) fn check_goal(&self) -> Result<(), ProfileError> {
+        self.check_goal_measures(&self.goal)
+    }
+
+    pub(crate) fn check_goal_measures(&self, goal: &Goal) -> Result<(), ProfileError> {
+        self.measure_table.try_constraint_idx(&goal.constraint)?;
+        self.measure_table.objective_measures(&goal.opt_func)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Configurations, LearningPolicy, OperatingMode};
+    use crate::profile::ProfileError;
+    use crate::testing::{builder, env_lock, goal, step, tables};
+    use crate::{Apto, SamplingPlan};
+    use std::sync::Arc;
+
+    #[test]
+    fn build_configuration() {
+        let step = step();
+        let profiling = OperatingMode::Profile {
+            iterations: 10,
+            plan: SamplingPlan::CornersAndCenter,
+        };
+        let config = builder(&step)
+            .instance_id(3)
+            .mode(profiling.clone())
+            .output_dir("/tmp/apto_builder")
+            .build()
+            .unwrap();
+        assert_eq!(config.resolved_mode().unwrap(), profiling);
+        assert_eq!(
+            config.output.measure_table,
+            "/tmp/apto_builder/measuretable"
+        );
+        assert!(Apto::try_new(config).is_ok());
+
+        let (measure_table, knob_table) = tables();
+        let missing = Configurations::builder()
+            .profile(measure_table, knob_table)
+            .knob(step)
+            .goal(goal())
+            .build();
+        assert!(matches!(
+            missing,
+            Err(ProfileError::MissingSetting("window size"))
+        ));
+    }
+
+    #[test]
+    fn share_tables_between_instances() {
+        let step = step();
+        let (measure_table, knob_table) = tables();
+        let (measure_table, knob_table) = (Arc::new(measure_table), Arc::new(knob_table));
+        let configs: Vec<Configurations<u64>> = (0..2)
+            .map(|instance_id| {
+                Configurations::from_tables(
+                    instance_id,
+                    measure_table.clone(),
+                    knob_table.clone(),
+                    vec![step.clone()],
+                    goal(),
+                    20,
+                )
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&measure_table), 3);
+        assert!(Arc::ptr_eq(&configs[0].knob_table, &configs[1].knob_table));
+    }
+
+    #[test]
+    fn settings_from_builder_and_environment() {
+        let invalid = builder(&step())
+            .online_learning(LearningPolicy::new(2.0))
+            .build();
+        assert!(matches!(
+            invalid,
+            Err(ProfileError::InvalidSetting {
+                setting: "online learning",
+                ..
+            })
+        ));
+
+        let _env = env_lock();
+        std::env::set_var("PROFILE_WARMUP", "2");
+        std::env::set_var("ONLINE_LEARNING_PERIOD", "3");
+        let mut config = builder(&step())
+            .online_learning(LearningPolicy::new(0.5))
+            .profile_repetitions(4)
+            .env_overrides(true)
+            .build()
+            .unwrap();
+        config.add_env_settings().unwrap();
+        assert_eq!(config.profile_repetitions, 4);
+        assert_eq!(config.profile_warmup, 2);
+        assert_eq!(
+            config.online_learning,
+            Some(LearningPolicy::new(0.5).period(3))
+        );
+
+        std::env::set_var("PROFILE_WARMUP", "some");
+        let config = builder(&step()).env_overrides(true).build().unwrap();
+        assert!(matches!(
+            Apto::try_new(config),
+            Err(ProfileError::InvalidSetting {
+                setting: "PROFILE_WARMUP",
+                ..
+            })
+        ));
+        std::env::remove_var("PROFILE_WARMUP");
+        std::env::remove_var("ONLINE_LEARNING_PERIOD");
+    }
+
+    #[test]
+    fn reject_invalid_profiling_from_environment() {
+        let _env = env_lock();
+        for (name, value) in [
+            ("PROFILE", "many"),
+            ("PROFILE_PLAN", "everything"),
+            ("PROFILE_FLUSH", "sometimes"),
+        ] {
+            std::env::set_var(name, value);
+            let config = builder(&step()).env_overrides(true).build().unwrap();
+            assert!(matches!(
+                Apto::try_new(config),
+                Err(ProfileError::InvalidSetting { setting, .. }) if setting == name
+            ));
+            std::env::remove_var(name);
         }
     }
 }
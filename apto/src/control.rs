@@ -0,0 +1,346 @@
+use crate::Goal;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An operation on a running instance. Sent to its control socket as one
+/// JSON object per line, e.g. `{"op":"set_controller_gain","value":0.5}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Goal,
+    Schedule,
+    Configuration,
+    /// Aggregates of the measures over the window in progress.
+    Window,
+    Controller,
+    ChangeGoal {
+        goal: Goal,
+    },
+    /// Keeps the goal but changes the target of its constraint.
+    Retarget {
+        target: f64,
+    },
+    Freeze,
+    Unfreeze,
+    SetControllerGain {
+        value: f64,
+    },
+    SetErrorMultiplier {
+        value: f64,
+    },
+    SetDerivativeMultiplier {
+        value: f64,
+    },
+}
+
+/// The answer to a `ControlRequest`, also one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlReply {
+    Done,
+    Goal(Goal),
+    Schedule(ScheduleState),
+    Configuration(ConfigurationState),
+    Window(BTreeMap<String, f64>),
+    Controller(ControllerState),
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleState {
+    pub lower: BTreeMap<String, u64>,
+    pub upper: BTreeMap<String, u64>,
+    /// Share of every window spent in `lower`.
+    pub lower_share: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigurationState {
+    /// `adaptive`, `non_adaptive` or `profile`.
+    pub mode: String,
+    /// Id of the configuration in the knob table, if the knobs match one.
+    pub id: Option<u64>,
+    pub knobs: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControllerState {
+    pub target: f64,
+    /// Speedup the controller asked for in the last window.
+    pub xup: f64,
+    /// Base workload estimated by the Kalman filter.
+    pub workload: f64,
+    pub error: f64,
+    pub error_derivative: f64,
+    pub pole: f64,
+    pub error_multiplier: f64,
+}
+
+type Pending = (ControlRequest, Sender<ControlReply>);
+
+/// How long a connection waits for the instance to answer a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a `ControlClient` waits for a reply unless told otherwise.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts `ControlRequest`s on a Unix domain socket. The requests are
+/// answered by the instance between two iterations; a request that is not
+/// answered within `REPLY_TIMEOUT`, or that reaches a stopped instance, gets
+/// an error.
+pub struct ControlServer {
+    path: PathBuf,
+    requests: Receiver<Pending>,
+    shutdown: Arc<AtomicBool>,
+    serving: Arc<AtomicBool>,
+}
+
+impl ControlServer {
+    /// Listens on `path`, replacing a socket left behind by an earlier run.
+    pub fn bind<P: AsRef<Path>>(path: P) -> std::io::Result<ControlServer> {
+        let path = path.as_ref().to_path_buf();
+        if UnixStream::connect(&path).is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path)?;
+        let (sender, requests) = channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        let serving = Arc::new(AtomicBool::new(true));
+        let open = serving.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        let open = open.clone();
+                        std::thread::spawn(move || serve_connection(stream, sender, open));
+                    }
+                    Err(e) => warn!("Could not accept control connection: {}", e),
+                }
+            }
+        });
+        info!("Listening for control requests on {}", path.display());
+        Ok(ControlServer {
+            path,
+            requests,
+            shutdown,
+            serving,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Requests received since the last call, each with the channel its
+    /// reply goes to.
+    pub(crate) fn pending(&self) -> impl Iterator<Item = Pending> + '_ {
+        self.requests.try_iter()
+    }
+
+    /// Whether the instance answers requests. While it does not, the
+    /// requests still waiting and every new one are refused right away.
+    pub(crate) fn set_serving(&self, serving: bool) {
+        self.serving.store(serving, Ordering::SeqCst);
+        if !serving {
+            for (_, reply) in self.pending() {
+                let _ = reply.send(stopped());
+            }
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wakes the listener up so it sees the shutdown
+        let _ = UnixStream::connect(&self.path);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn stopped() -> ControlReply {
+    ControlReply::Error("instance stopped".to_string())
+}
+
+fn serve_connection(stream: UnixStream, requests: Sender<Pending>, serving: Arc<AtomicBool>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str(&line) {
+            Ok(_) if !serving.load(Ordering::SeqCst) => stopped(),
+            Ok(request) => {
+                let (sender, reply) = channel();
+                match requests.send((request, sender)) {
+                    Ok(()) => match reply.recv_timeout(REPLY_TIMEOUT) {
+                        Ok(reply) => reply,
+                        Err(RecvTimeoutError::Timeout) => ControlReply::Error(format!(
+                            "no answer within {:?}, the instance is not iterating",
+                            REPLY_TIMEOUT
+                        )),
+                        Err(RecvTimeoutError::Disconnected) => stopped(),
+                    },
+                    Err(_) => stopped(),
+                }
+            }
+            Err(e) => ControlReply::Error(format!("invalid request: {}", e)),
+        };
+        let Ok(reply) = serde_json::to_string(&reply) else {
+            return;
+        };
+        if writeln!(writer, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends requests to the control server of an instance.
+pub struct ControlClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl ControlClient {
+    /// Connects to the socket at `path`, waiting up to `CLIENT_TIMEOUT` for
+    /// every reply.
+    pub fn connect<P: AsRef<Path>>(path: P) -> std::io::Result<ControlClient> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let client = ControlClient { reader, writer };
+        client.set_timeout(Some(CLIENT_TIMEOUT))?;
+        Ok(client)
+    }
+
+    /// How long `send` waits for the instance, `None` to wait forever.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)
+    }
+
+    pub fn send(&mut self, request: &ControlRequest) -> std::io::Result<ControlReply> {
+        let request = serde_json::to_string(request)?;
+        writeln!(self.writer, "{}", request)?;
+        let mut reply = String::new();
+        if self.reader.read_line(&mut reply)? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&reply)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControlClient, ControlReply, ControlRequest, ControlServer};
+    use crate::testing::{builder, goal, step};
+    use crate::{Apto, Goal, OperatingMode};
+
+    #[test]
+    fn round_trip_requests() {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"op":"set_controller_gain","value":0.5}"#).unwrap();
+        assert_eq!(request, ControlRequest::SetControllerGain { value: 0.5 });
+
+        let path = "/tmp/apto_control_test.sock";
+        let server = ControlServer::bind(path).unwrap();
+        let answer = std::thread::spawn(move || {
+            let mut answered = 0;
+            while answered < 2 {
+                for (request, reply) in server.pending() {
+                    let answer = match request {
+                        ControlRequest::Freeze => ControlReply::Done,
+                        _ => ControlReply::Error("unexpected".to_string()),
+                    };
+                    reply.send(answer).unwrap();
+                    answered += 1;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+
+        let mut client = ControlClient::connect(path).unwrap();
+        assert_eq!(
+            client.send(&ControlRequest::Freeze).unwrap(),
+            ControlReply::Done
+        );
+        assert!(matches!(
+            client.send(&ControlRequest::Goal).unwrap(),
+            ControlReply::Error(_)
+        ));
+        answer.join().unwrap();
+        assert!(!std::path::Path::new(path).exists());
+    }
+
+    #[test]
+    fn control_running_instance() {
+        let socket = "/tmp/apto_control_instance.sock";
+        let config = builder(&step())
+            .mode(OperatingMode::Adaptive)
+            .control_socket(socket)
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+
+        let operator = std::thread::spawn(move || {
+            let mut client = ControlClient::connect(socket).unwrap();
+            let retargeted = Goal {
+                target: 0.75,
+                ..goal()
+            };
+            let replies = [
+                ControlRequest::Freeze,
+                ControlRequest::ChangeGoal {
+                    goal: retargeted.clone(),
+                },
+                ControlRequest::Goal,
+                ControlRequest::Configuration,
+            ]
+            .map(|request| client.send(&request).unwrap());
+            assert_eq!(replies[0], ControlReply::Done);
+            assert_eq!(replies[1], ControlReply::Done);
+            assert_eq!(replies[2], ControlReply::Goal(retargeted));
+            match &replies[3] {
+                ControlReply::Configuration(state) => {
+                    assert_eq!(state.mode, "non_adaptive");
+                    assert_eq!(state.knobs["step"], 1);
+                }
+                reply => panic!("Unexpected reply {:?}", reply),
+            }
+        });
+
+        apto.start();
+        while !operator.is_finished() {
+            apto.record("operations", 100.0);
+            apto.end_iteration();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        apto.stop();
+        operator.join().unwrap();
+
+        // A stopped instance refuses requests instead of leaving them waiting
+        let mut client = ControlClient::connect(socket).unwrap();
+        assert_eq!(
+            client.send(&ControlRequest::Goal).unwrap(),
+            ControlReply::Error("instance stopped".to_string())
+        );
+        drop(apto);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::NAME_REGEX;
+use std::collections::BTreeMap;
+use OptimizingController::ObjectiveFunction;
+
+/// Functions and constants of the expression language, which are not measures.
+const BUILTINS: [&str; 23] = [
+    "abs", "sign", "int", "ceil", "floor", "round", "min", "max", "log", "e", "pi", "sin", "cos",
+    "tan", "asin", "acos", "atan", "sinh", "cosh", "tanh", "asinh", "acosh", "atanh",
+];
+
+/// A measure computed from other measures with a fasteval expression, e.g.
+/// `performance = 1 / latency`. When a window ends, it is evaluated with the
+/// window aggregates of its inputs and reported like a measure; when
+/// profiling, it is evaluated with their aggregates over the run. Inputs
+/// that were not measured count as NaN.
+pub struct DerivedMeasure {
+    name: String,
+    expression: String,
+    function: ObjectiveFunction,
+    inputs: Vec<String>,
+}
+
+impl DerivedMeasure {
+    pub fn new(name: &str, expression: &str) -> Result<DerivedMeasure, String> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            format!("invalid expression for {} '{}': {}", name, expression, e)
+        };
+        let function = ObjectiveFunction::try_new(expression).map_err(|e| invalid(&e))?;
+        let inputs: Vec<String> = NAME_REGEX
+            .find_iter(expression)
+            .map(|input| input.as_str())
+            .filter(|input| !BUILTINS.contains(input))
+            .map(String::from)
+            .collect();
+        if inputs.iter().any(|input| input == name) {
+            return Err(invalid(&"a measure cannot be derived from itself"));
+        }
+        let mut values = inputs.iter().map(|input| (input.clone(), 1.0)).collect();
+        function.evaluate(&mut values).map_err(|e| invalid(&e))?;
+        Ok(DerivedMeasure {
+            name: name.to_string(),
+            expression: expression.to_string(),
+            function,
+            inputs,
+        })
+    }
+
+    /// What profiling always derived: the performance from the mean latency
+    /// and the power from the energy and duration of the windows.
+    pub(crate) fn profile_defaults() -> Vec<DerivedMeasure> {
+        [
+            ("performance", "1 / latency"),
+            ("powerConsumption", "energyDelta / windowLatency"),
+        ]
+        .into_iter()
+        .map(|(name, expression)| DerivedMeasure::new(name, expression).unwrap())
+        .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Measures the expression reads.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub(crate) fn evaluate<F: Fn(&str) -> Option<f64>>(&self, value: F) -> f64 {
+        let mut values: BTreeMap<String, f64> = self
+            .inputs
+            .iter()
+            .map(|input| (input.clone(), value(input).unwrap_or(f64::NAN)))
+            .collect();
+        self.function.evaluate(&mut values).unwrap_or(f64::NAN)
+    }
+}
+
+impl std::fmt::Debug for DerivedMeasure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{} = {}", self.name, self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DerivedMeasure;
+    use crate::testing::{builder, column, profile, profiling_tables, step};
+    use crate::{OperatingMode, SamplingPlan};
+
+    #[test]
+    fn derive_measures() {
+        let harmonic =
+            DerivedMeasure::new("harmonicMeanPerf", "2 / (1 / perf0 + 1 / abs(perf1))").unwrap();
+        assert_eq!(harmonic.inputs(), ["perf0", "perf1"]);
+        let value = harmonic.evaluate(|input| match input {
+            "perf0" => Some(1.0),
+            "perf1" => Some(-3.0),
+            _ => None,
+        });
+        assert!((value - 1.5).abs() < f64::EPSILON);
+        assert!(harmonic.evaluate(|_| None).is_nan());
+
+        assert!(DerivedMeasure::new("performance", "1 / ").is_err());
+        assert!(DerivedMeasure::new("performance", "2 * performance").is_err());
+        assert_eq!(DerivedMeasure::profile_defaults().len(), 2);
+    }
+
+    #[test]
+    fn profile_derived_measures() {
+        let output_dir = "/tmp/apto_derived_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .mode(OperatingMode::Profile {
+                iterations: 3,
+                plan: SamplingPlan::Full,
+            })
+            .output_dir(output_dir)
+            .derived_measure(DerivedMeasure::new("work", "operations / quality").unwrap())
+            .build()
+            .unwrap();
+        let profiled = profile(config, |apto, _| {
+            apto.record("quality", 0.5);
+            apto.record("operations", 100.0);
+        });
+        assert_eq!(profiled.profile.len(), 2);
+        let work = column(&profiled, "work");
+        assert!(profiled
+            .profile
+            .iter()
+            .all(|row| (row[work] - 200.0).abs() < 1e-9));
+    }
+}
@@ -1,11 +1,13 @@
+use crate::aggregation::Aggregation;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::{io::BufReader, path::Path};
 use OptimizingController::OptimizationType;
 
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "OptimizationType")]
-enum OptimizationTypeDef {
+pub(crate) enum OptimizationTypeDef {
     Minimize,
     Maximize,
 }
@@ -19,6 +21,11 @@ pub struct Goal {
     pub(crate) opt_type: OptimizationType,
 
     pub(crate) opt_func: String,
+
+    /// How measures are aggregated over a window, e.g. `latency: p99`.
+    /// Takes precedence over the aggregations in `AptoConfig`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) aggregations: BTreeMap<String, Aggregation>,
 }
 
 impl Goal {
@@ -33,8 +40,16 @@ impl Goal {
             target,
             opt_type,
             opt_func,
+            aggregations: BTreeMap::new(),
         }
     }
+
+    /// Aggregates `measure` with `aggregation`, so that e.g. the p99 of the
+    /// latency in a window is held to the target.
+    pub fn aggregation(mut self, measure: &str, aggregation: Aggregation) -> Goal {
+        self.aggregations.insert(measure.to_string(), aggregation);
+        self
+    }
 }
 
 impl std::fmt::Display for Goal {
@@ -105,6 +120,7 @@ mod tests {
     use OptimizingController::OptimizationType;
 
     use super::{Goal, Perturbation};
+    use crate::aggregation::Aggregation;
 
     #[test]
     fn parse_goal_from_yaml() {
@@ -143,6 +159,30 @@ opt_func: performance
         );
     }
 
+    #[test]
+    fn parse_goal_with_aggregations() {
+        let goal_str = r#"---
+constraint: latency
+target: 0.002
+opt_type: Minimize
+opt_func: powerConsumption
+aggregations:
+  latency: p99
+"#;
+        let parsed_goal: Goal = serde_yaml::from_str(goal_str).unwrap();
+        assert_eq!(
+            Goal::new(
+                "latency".to_string(),
+                0.002,
+                OptimizationType::Minimize,
+                "powerConsumption".to_string()
+            )
+            .aggregation("latency", Aggregation::Percentile(99.0)),
+            parsed_goal
+        );
+        assert!(serde_yaml::from_str::<Goal>(&goal_str.replace("p99", "p999")).is_err());
+    }
+
     #[test]
     #[should_panic]
     fn parse_incorrect_goal_from_yaml() {
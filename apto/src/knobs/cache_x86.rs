@@ -1,19 +1,14 @@
 use super::utilities::*;
 use super::{BorrowedValues, GenericKnob, Tunable};
-use std::cell::RefCell;
 
 pub struct CacheCOS {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
 }
 
 impl CacheCOS {
     pub fn new(values: Vec<u64>, initial_value: u64) -> CacheCOS {
         let knob = CacheCOS {
-            knob: RefCell::new(GenericKnob::new(
-                "cacheCOS".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("cacheCOS".to_string(), values, initial_value),
         };
         knob.set_cos();
         knob
@@ -31,17 +26,17 @@ impl CacheCOS {
     }
 
     fn apply(&self, val: u64) {
-        if self.knob.borrow().current_value == val {
+        if self.knob.get() == val {
             return;
         }
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.set_cos();
     }
 }
 
 impl Tunable<u64> for CacheCOS {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -49,13 +44,20 @@ impl Tunable<u64> for CacheCOS {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.to_string()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
+    }
+
+    /// The class of service is written to every CPU the process may run on.
+    fn apply_after(&self) -> Vec<String> {
+        vec![
+            "utilizedPhysicalCores".to_string(),
+            "utilizedPhysicalThreads".to_string(),
+            "hyperthreading".to_string(),
+        ]
     }
 }
 
@@ -2,10 +2,9 @@ use super::utilities::*;
 use super::BorrowedValues;
 use super::GenericKnob;
 use super::Tunable;
-use std::cell::RefCell;
 
 pub struct AvailablePhysicalThreads {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
     valid_cores: Vec<usize>,
 }
 
@@ -15,11 +14,7 @@ impl AvailablePhysicalThreads {
         let valid_cores = (0..nr_cores).filter(|id| core_online(*id)).collect();
 
         let knob = AvailablePhysicalThreads {
-            knob: RefCell::new(GenericKnob::new(
-                "utilizedPhysicalThreads".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("utilizedPhysicalThreads".to_string(), values, initial_value),
             valid_cores,
         };
         knob.set_affinity();
@@ -27,10 +22,10 @@ impl AvailablePhysicalThreads {
     }
 
     fn apply(&self, val: u64) {
-        if self.knob.borrow().current_value == val {
+        if self.knob.get() == val {
             return;
         }
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.set_affinity();
     }
 
@@ -50,7 +45,7 @@ impl AvailablePhysicalThreads {
 
 impl Tunable<u64> for AvailablePhysicalThreads {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -58,18 +53,16 @@ impl Tunable<u64> for AvailablePhysicalThreads {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.clone()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 }
 
 pub struct AvailablePhysicalCores {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
     valid_cores: Vec<usize>,
 }
 
@@ -79,11 +72,7 @@ impl AvailablePhysicalCores {
         let valid_cores = (0..nr_cores).filter(|id| core_online(*id)).collect();
 
         let knob = AvailablePhysicalCores {
-            knob: RefCell::new(GenericKnob::new(
-                "utilizedPhysicalCores".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("utilizedPhysicalCores".to_string(), values, initial_value),
             valid_cores,
         };
         knob.set_affinity();
@@ -91,10 +80,10 @@ impl AvailablePhysicalCores {
     }
 
     fn apply(&self, val: u64) {
-        if self.knob.borrow().current_value == val {
+        if self.knob.get() == val {
             return;
         }
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.set_affinity();
     }
 
@@ -129,7 +118,7 @@ impl AvailablePhysicalCores {
 
 impl Tunable<u64> for AvailablePhysicalCores {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -137,18 +126,16 @@ impl Tunable<u64> for AvailablePhysicalCores {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.clone()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 }
 
 pub struct CoreFrequency {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
     online_cpus: Vec<usize>,
 }
 
@@ -158,11 +145,7 @@ impl CoreFrequency {
         let online_cpus = (0..nr_cpus).filter(|c| core_online(*c)).collect();
 
         let knob = CoreFrequency {
-            knob: RefCell::new(GenericKnob::new(
-                "utilizedCoreFrequency".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("utilizedCoreFrequency".to_string(), values, initial_value),
             online_cpus,
         };
         knob.apply_frequency();
@@ -170,10 +153,10 @@ impl CoreFrequency {
     }
 
     fn apply(&self, val: u64) {
-        if self.knob.borrow().current_value == val {
+        if self.knob.get() == val {
             return;
         }
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.apply_frequency();
     }
 
@@ -184,7 +167,7 @@ impl CoreFrequency {
 
 impl Tunable<u64> for CoreFrequency {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -192,18 +175,16 @@ impl Tunable<u64> for CoreFrequency {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.to_string()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 }
 
 pub struct UncoreFrequency {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
     socket_cores: Vec<usize>,
 }
 
@@ -215,11 +196,7 @@ impl UncoreFrequency {
             .collect();
 
         let knob = UncoreFrequency {
-            knob: RefCell::new(GenericKnob::new(
-                "uncoreFrequency".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("uncoreFrequency".to_string(), values, initial_value),
             socket_cores: active_socket_cores,
         };
         knob.apply_uncore_frequency();
@@ -227,7 +204,7 @@ impl UncoreFrequency {
     }
 
     fn apply_uncore_frequency(&self) {
-        let target_freq = self.knob.borrow().current_value;
+        let target_freq = self.knob.get();
         let register_value = ((target_freq << 8) + target_freq).to_ne_bytes();
         for core_num in self.socket_cores.iter() {
             write_msr(*core_num as i64, 0x620, &register_value);
@@ -235,17 +212,17 @@ impl UncoreFrequency {
     }
 
     fn apply(&self, val: u64) {
-        if self.knob.borrow().current_value == val {
+        if self.knob.get() == val {
             return;
         }
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.apply_uncore_frequency();
     }
 }
 
 impl Tunable<u64> for UncoreFrequency {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -253,18 +230,16 @@ impl Tunable<u64> for UncoreFrequency {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.to_string()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 }
 
 pub struct Hyperthreading {
-    knob: RefCell<GenericKnob<u64>>,
+    knob: GenericKnob<u64>,
     nr_physical_cpus: usize,
     first_ht_id: usize,
 }
@@ -272,11 +247,7 @@ pub struct Hyperthreading {
 impl Hyperthreading {
     pub fn new(values: Vec<u64>, initial_value: u64) -> Hyperthreading {
         let knob = Hyperthreading {
-            knob: RefCell::new(GenericKnob::new(
-                "hyperthreading".to_string(),
-                values,
-                initial_value,
-            )),
+            knob: GenericKnob::new("hyperthreading".to_string(), values, initial_value),
             nr_physical_cpus: get_num_physical_cpus(),
             first_ht_id: (unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) } as usize) / 2,
         };
@@ -303,14 +274,14 @@ impl Hyperthreading {
     }
 
     fn apply(&self, val: u64) {
-        self.knob.borrow_mut().current_value = val;
+        self.knob.set(val);
         self.apply_hyperthreading_mask()
     }
 }
 
 impl Tunable<u64> for Hyperthreading {
     fn get(&self) -> u64 {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: u64) {
@@ -318,13 +289,19 @@ impl Tunable<u64> for Hyperthreading {
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.to_string()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, u64> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
+    }
+
+    /// The mask is derived from the physical cores currently in use.
+    fn apply_after(&self) -> Vec<String> {
+        vec![
+            "utilizedPhysicalCores".to_string(),
+            "utilizedPhysicalThreads".to_string(),
+        ]
     }
 }
 
@@ -1,4 +1,6 @@
-use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 mod cache_x86;
 mod homogenous;
@@ -15,7 +17,7 @@ pub struct BorrowedValues<'a, T>
 where
     T: Copy,
 {
-    borrowed_knob: Ref<'a, GenericKnob<T>>,
+    permitted_values: &'a [T],
 }
 
 impl<T> std::ops::Deref for BorrowedValues<'_, T>
@@ -25,7 +27,7 @@ where
     type Target = [T];
 
     fn deref(&self) -> &'_ Self::Target {
-        &self.borrowed_knob.permitted_values
+        self.permitted_values
     }
 }
 
@@ -37,12 +39,106 @@ where
     fn set(&self, val: T);
     fn name(&self) -> String;
     fn possible_values(&'_ self) -> BorrowedValues<'_, T>;
+
+    /// Names of the knobs that have to be set before this one whenever a new
+    /// configuration is applied. Knobs that are not registered are ignored.
+    fn apply_after(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A knob that can be moved to and used from other threads together with the
+/// Apto instance it is registered with.
+pub type SharedKnob<T> = Arc<dyn Tunable<T> + Send + Sync>;
+
+// Lets `AptoConfig` hold `Rc<dyn Tunable<T>>` as well as `SharedKnob<T>`.
+impl<T: Copy, K: Tunable<T> + ?Sized> Tunable<T> for Rc<K> {
+    fn get(&self) -> T {
+        (**self).get()
+    }
+
+    fn set(&self, val: T) {
+        (**self).set(val)
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn possible_values(&self) -> BorrowedValues<'_, T> {
+        (**self).possible_values()
+    }
+
+    fn apply_after(&self) -> Vec<String> {
+        (**self).apply_after()
+    }
+}
+
+impl<T: Copy, K: Tunable<T> + ?Sized> Tunable<T> for Arc<K> {
+    fn get(&self) -> T {
+        (**self).get()
+    }
+
+    fn set(&self, val: T) {
+        (**self).set(val)
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn possible_values(&self) -> BorrowedValues<'_, T> {
+        (**self).possible_values()
+    }
+
+    fn apply_after(&self) -> Vec<String> {
+        (**self).apply_after()
+    }
+}
+
+/// Orders the knobs so that each comes after the ones it declares in
+/// `apply_after`, otherwise alphabetically. Returns the knobs caught in a
+/// dependency cycle if there is one.
+pub(crate) fn apply_order<T: Copy, H: Tunable<T>>(
+    knobs: &HashMap<String, H>,
+) -> Result<Vec<String>, Vec<String>> {
+    let mut pending: BTreeMap<&String, BTreeSet<String>> = knobs
+        .iter()
+        .map(|(name, knob)| {
+            let after = knob
+                .apply_after()
+                .into_iter()
+                .filter(|dependency| dependency != name && knobs.contains_key(dependency))
+                .collect();
+            (name, after)
+        })
+        .collect();
+
+    let mut order = Vec::with_capacity(knobs.len());
+    while !pending.is_empty() {
+        let ready: Vec<&String> = pending
+            .iter()
+            .filter(|(_, after)| after.is_empty())
+            .map(|(&name, _)| name)
+            .collect();
+        if ready.is_empty() {
+            return Err(pending.into_keys().cloned().collect());
+        }
+        for name in ready {
+            pending.remove(name);
+            for after in pending.values_mut() {
+                after.remove(name);
+            }
+            order.push(name.clone());
+        }
+    }
+    Ok(order)
 }
 
 struct GenericKnob<T: Copy> {
     name: String,
     permitted_values: Vec<T>,
-    current_value: T,
+    current_value: Mutex<T>,
 }
 
 impl<T: Copy> GenericKnob<T> {
@@ -50,16 +146,41 @@ impl<T: Copy> GenericKnob<T> {
         GenericKnob {
             name,
             permitted_values,
-            current_value,
+            current_value: Mutex::new(current_value),
+        }
+    }
+
+    fn get(&self) -> T {
+        *self.current_value.lock().unwrap()
+    }
+
+    fn set(&self, val: T) {
+        *self.current_value.lock().unwrap() = val;
+    }
+
+    fn values(&self) -> BorrowedValues<'_, T> {
+        BorrowedValues {
+            permitted_values: &self.permitted_values,
         }
     }
 }
 
-pub struct ApplicationKnob<T: Copy> {
-    knob: RefCell<GenericKnob<T>>,
-    application_func: Option<Box<dyn Fn(Option<T>, T)>>,
+/// A knob whose values are applied by a callback. `F` is the type of the
+/// callback, by default any `Fn`; with a `Send + Sync` callback (see
+/// `SyncApplicationKnob`) the knob can be registered as a `SharedKnob`.
+pub struct ApplicationKnob<T: Copy, F: ?Sized = dyn Fn(Option<T>, T)> {
+    knob: GenericKnob<T>,
+    application_func: Option<Box<F>>,
+    apply_after: Vec<String>,
 }
 
+pub type ApplicationFn<T> = Box<dyn Fn(Option<T>, T)>;
+
+pub type SyncApplicationFn<T> = Box<dyn Fn(Option<T>, T) + Send + Sync>;
+
+/// An `ApplicationKnob` that is `Send + Sync`.
+pub type SyncApplicationKnob<T> = ApplicationKnob<T, dyn Fn(Option<T>, T) + Send + Sync>;
+
 impl<T> ApplicationKnob<T>
 where
     T: Copy + Eq,
@@ -68,12 +189,42 @@ where
         name: String,
         values: Vec<T>,
         initial_value: T,
-        application_func: Option<Box<dyn Fn(Option<T>, T)>>,
+        application_func: Option<ApplicationFn<T>>,
     ) -> ApplicationKnob<T> {
-        let knob = RefCell::new(GenericKnob::new(name, values, initial_value));
+        ApplicationKnob::with_func(name, values, initial_value, application_func)
+    }
+}
+
+impl<T> SyncApplicationKnob<T>
+where
+    T: Copy + Eq,
+{
+    pub fn new_sync(
+        name: String,
+        values: Vec<T>,
+        initial_value: T,
+        application_func: Option<SyncApplicationFn<T>>,
+    ) -> SyncApplicationKnob<T> {
+        ApplicationKnob::with_func(name, values, initial_value, application_func)
+    }
+}
+
+impl<T, F> ApplicationKnob<T, F>
+where
+    T: Copy + Eq,
+    F: Fn(Option<T>, T) + ?Sized,
+{
+    fn with_func(
+        name: String,
+        values: Vec<T>,
+        initial_value: T,
+        application_func: Option<Box<F>>,
+    ) -> ApplicationKnob<T, F> {
+        let knob = GenericKnob::new(name, values, initial_value);
         let app_knob = ApplicationKnob {
             knob,
             application_func,
+            apply_after: Vec::new(),
         };
         if let Some(func) = app_knob.application_func.as_ref() {
             func(None, initial_value);
@@ -82,25 +233,37 @@ where
     }
 
     pub fn possible_values(&self) -> BorrowedValues<'_, T> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 
+    /// Declares knobs that have to be set before this one.
+    pub fn after<I, S>(mut self, names: I) -> ApplicationKnob<T, F>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.apply_after.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Holds the knob for the whole application, so concurrent `set`s of a
+    /// shared knob are applied one after the other.
     pub fn apply(&self, val: T) {
+        let mut current_value = self.knob.current_value.lock().unwrap();
         if let Some(func) = self.application_func.as_ref() {
-            func(Some(self.get()), val);
+            func(Some(*current_value), val);
         }
-        self.knob.borrow_mut().current_value = val;
+        *current_value = val;
     }
 }
 
-impl<T> Tunable<T> for ApplicationKnob<T>
+impl<T, F> Tunable<T> for ApplicationKnob<T, F>
 where
     T: Copy + Eq,
+    F: Fn(Option<T>, T) + ?Sized,
 {
     fn get(&self) -> T {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: T) {
@@ -108,18 +271,20 @@ where
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.clone()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, T> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
+    }
+
+    fn apply_after(&self) -> Vec<String> {
+        self.apply_after.clone()
     }
 }
 
 pub struct ConstantKnob<T: Copy> {
-    knob: RefCell<GenericKnob<T>>,
+    knob: GenericKnob<T>,
 }
 
 impl<T> ConstantKnob<T>
@@ -127,7 +292,7 @@ where
     T: Copy,
 {
     pub fn new(name: String, value: T) -> ConstantKnob<T> {
-        let knob = RefCell::new(GenericKnob::new(name, vec![value], value));
+        let knob = GenericKnob::new(name, vec![value], value);
         ConstantKnob { knob }
     }
 }
@@ -137,7 +302,7 @@ where
     T: Copy + Eq + std::fmt::Display,
 {
     fn get(&self) -> T {
-        self.knob.borrow().current_value
+        self.knob.get()
     }
 
     fn set(&self, val: T) {
@@ -152,19 +317,19 @@ where
     }
 
     fn name(&self) -> String {
-        self.knob.borrow().name.clone()
+        self.knob.name.clone()
     }
 
     fn possible_values(&self) -> BorrowedValues<'_, T> {
-        BorrowedValues {
-            borrowed_knob: self.knob.borrow(),
-        }
+        self.knob.values()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ApplicationKnob, ConstantKnob, Tunable};
+    use super::{apply_order, ApplicationKnob, ConstantKnob, Tunable};
+    use std::collections::HashMap;
+    use std::rc::Rc;
 
     #[test]
     fn test_app_knob() {
@@ -199,4 +364,39 @@ mod tests {
         assert_eq!(knob.get(), 10);
         assert_eq!(*knob.possible_values(), vec![10u64]);
     }
+
+    #[test]
+    fn knob_apply_order() {
+        let knob = |name: &str, after: &[&str]| -> Rc<dyn Tunable<u64>> {
+            Rc::new(
+                ApplicationKnob::new(name.to_string(), vec![0, 1], 0, None)
+                    .after(after.iter().copied()),
+            )
+        };
+        let mut knobs: HashMap<String, Rc<dyn Tunable<u64>>> = [
+            knob("cacheCOS", &["hyperthreading", "utilizedPhysicalCores"]),
+            knob("hyperthreading", &["utilizedPhysicalCores", "unregistered"]),
+            knob("utilizedPhysicalCores", &[]),
+            knob("uncoreFrequency", &[]),
+        ]
+        .into_iter()
+        .map(|knob| (knob.name(), knob))
+        .collect();
+        assert_eq!(
+            apply_order(&knobs).unwrap(),
+            vec![
+                "uncoreFrequency",
+                "utilizedPhysicalCores",
+                "hyperthreading",
+                "cacheCOS"
+            ]
+        );
+
+        let cyclic = knob("utilizedPhysicalCores", &["cacheCOS"]);
+        knobs.insert(cyclic.name(), cyclic);
+        assert_eq!(
+            apply_order(&knobs).unwrap_err(),
+            vec!["cacheCOS", "hyperthreading", "utilizedPhysicalCores"]
+        );
+    }
 }
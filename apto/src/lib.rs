@@ -1,22 +1,68 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
+mod aggregation;
+mod composition;
 mod configurations;
+mod control;
+mod derived;
 mod goal;
 mod knobs;
 mod measures;
 mod optimize;
+mod outliers;
+mod prediction;
 mod profile;
+mod profile_file;
+mod profile_output;
+mod projection;
+mod sampling;
+mod sketch;
 mod system_measures;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod timeline;
+mod validation;
+mod window;
 use goal::Perturbation;
 
+pub use aggregation::Aggregation;
+pub use composition::{PerfAggregate, ProfileComposer, Tenant};
 pub use configurations::Configurations as AptoConfig;
+pub use configurations::{
+    ConfigurationsBuilder as AptoConfigBuilder, LearningPolicy, OperatingMode,
+};
+pub use control::{
+    ConfigurationState, ControlClient, ControlReply, ControlRequest, ControlServer,
+    ControllerState, ScheduleState,
+};
+pub use derived::DerivedMeasure;
 pub use goal::Goal;
 pub use knobs::{
     ApplicationKnob, AvailablePhysicalCores, AvailablePhysicalThreads, CacheCOS, ConstantKnob,
-    CoreFrequency, Hyperthreading, Tunable, UncoreFrequency,
+    CoreFrequency, Hyperthreading, SharedKnob, SyncApplicationKnob, Tunable, UncoreFrequency,
+};
+pub use optimize::{Apto, KnobChange, ProfileRun};
+pub use outliers::OutlierPolicy;
+
+/// An Apto instance that can be created on one thread and driven from others.
+pub type SharedApto = Apto<SharedKnob<u64>>;
+pub type SharedAptoConfig = AptoConfig<u64, SharedKnob<u64>>;
+pub use prediction::{predict_missing, PredictedProfile};
+pub use profile::{ActiveModel, KnobTable, MeasureTable, ProfileError};
+pub use profile_file::{ProfileEntry, ProfileFile, ProfileFormat, ProfileMetadata};
+pub use profile_output::{FlushPolicy, ProfileOutput};
+pub use projection::{ProfileProjection, ProjectionAggregate};
+pub use sampling::SamplingPlan;
+pub use sketch::QuantileSketch;
+pub use telemetry::{
+    BinarySink, CsvSink, JsonLinesSink, Telemetry, TelemetryEvent, TelemetryFormat,
+    TelemetryRecord, TelemetrySink,
 };
-pub use optimize::Apto;
+pub use timeline::{GoalChange, GoalTimeline, TimelineEntry, Trigger};
+pub use validation::{validate_profile, ProfileIssue, ValidationReport};
+pub use window::WindowPolicy;
 pub use OptimizingController::OptimizationType;
 
 lazy_static! {
@@ -1,66 +1,364 @@
-use crate::optimize::AptoMode;
+use crate::aggregation::{AggregateFn, Aggregation};
+use crate::outliers::{OutlierFilter, OutlierPolicy};
+use crate::sketch::QuantileSketch;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What a measurement keeps of its values.
+enum Window {
+    /// Every value of the window, reduced to their mean or by `agg_func`.
+    Values {
+        values: Vec<f64>,
+        agg_func: Option<AggregateFn>,
+    },
+    /// Sketches of the window and of the whole run, reduced to `quantile`.
+    Sketch {
+        quantile: f64,
+        window: QuantileSketch,
+        run: QuantileSketch,
+        last: Option<f64>,
+    },
+    /// Totals over time of the window and of the whole run, reduced to a
+    /// sum per second or a time-weighted mean.
+    Timed {
+        per_second: bool,
+        window: TimedTotals,
+        run: TimedTotals,
+        last: Option<f64>,
+    },
+}
+
+/// Values folded over the time they were measured at. Every value holds from
+/// the sample before it, or the start, until its own timestamp.
+struct TimedTotals {
+    started: Instant,
+    last: Option<Instant>,
+    sum: f64,
+    weighted_sum: f64,
+}
+
+impl TimedTotals {
+    fn new() -> TimedTotals {
+        TimedTotals {
+            started: Instant::now(),
+            last: None,
+            sum: 0.0,
+            weighted_sum: 0.0,
+        }
+    }
+
+    fn add(&mut self, value: f64, at: Instant) {
+        let since = self.last.unwrap_or(self.started);
+        self.sum += value;
+        self.weighted_sum += value * at.saturating_duration_since(since).as_secs_f64();
+        self.last = Some(self.last.map_or(at, |last| last.max(at)));
+    }
+
+    fn seconds_to(&self, end: Instant) -> f64 {
+        end.saturating_duration_since(self.started).as_secs_f64()
+    }
+
+    /// The sum per second over the time from the start to `end`, usually
+    /// now, or the time-weighted mean over the time the samples cover. NaN
+    /// without any sample.
+    fn aggregate(&self, per_second: bool, end: Instant) -> f64 {
+        match self.last {
+            None => f64::NAN,
+            Some(last) if per_second => self.sum / self.seconds_to(last.max(end)),
+            Some(last) => self.weighted_sum / self.seconds_to(last),
+        }
+    }
+}
 
 pub(crate) struct Measurement {
-    values: Vec<f64>,
-    window_values: Vec<f64>,
+    window: Window,
     nr_values: u64,
     total_average: f64,
-    agg_func: Option<Box<dyn Fn(&[f64]) -> f64>>,
+    outliers: Option<OutlierFilter>,
+    /// Values the outlier policy rejected or clamped in the window and since
+    /// the last complete reset.
+    rejected: u64,
+    run_rejected: u64,
 }
 
 impl Measurement {
-    pub fn new(window_size: u64, agg_func: Option<Box<dyn Fn(&[f64]) -> f64>>) -> Measurement {
-        let mut window_values = Vec::new();
-        window_values.reserve(window_size as usize);
+    fn with_window(window: Window) -> Measurement {
         Measurement {
-            values: Vec::new(),
-            window_values,
+            window,
             nr_values: 0,
             total_average: 0.0,
+            outliers: None,
+            rejected: 0,
+            run_rejected: 0,
+        }
+    }
+
+    pub fn new(window_size: u64, agg_func: Option<AggregateFn>) -> Measurement {
+        Measurement::with_window(Window::Values {
+            values: Vec::with_capacity(window_size as usize),
             agg_func,
+        })
+    }
+
+    /// Reports `quantile` of the window, and of the whole run when profiling,
+    /// from sketches instead of keeping the values.
+    pub fn with_quantile(quantile: f64) -> Measurement {
+        Measurement::with_window(Window::Sketch {
+            quantile,
+            window: QuantileSketch::default(),
+            run: QuantileSketch::default(),
+            last: None,
+        })
+    }
+
+    /// Reports the sum per second (`per_second`) or the time-weighted mean of the
+    /// window, and of the whole run when profiling.
+    pub fn over_time(per_second: bool) -> Measurement {
+        Measurement::with_window(Window::Timed {
+            per_second,
+            window: TimedTotals::new(),
+            run: TimedTotals::new(),
+            last: None,
+        })
+    }
+
+    /// Percentiles are sketched and rates and time-weighted means are summed
+    /// over time; everything else keeps the window's values.
+    pub fn for_aggregation(
+        aggregation: Aggregation,
+        window_size: u64,
+        window_samples: &Arc<AtomicU64>,
+    ) -> Measurement {
+        match aggregation {
+            Aggregation::PerSecond => Measurement::over_time(true),
+            Aggregation::TimeWeightedMean => Measurement::over_time(false),
+            _ => match aggregation.quantile() {
+                Some(quantile) => Measurement::with_quantile(quantile),
+                None => Measurement::new(window_size, aggregation.function(window_samples)),
+            },
+        }
+    }
+
+    /// Switches to another aggregation, keeping what can be kept of the
+    /// current window.
+    pub fn set_aggregation(
+        &mut self,
+        aggregation: Aggregation,
+        window_size: u64,
+        window_samples: &Arc<AtomicU64>,
+    ) {
+        let current = match &self.window {
+            Window::Values { values, .. } => values.clone(),
+            Window::Sketch { last, .. } | Window::Timed { last, .. } => {
+                last.iter().copied().collect()
+            }
+        };
+        self.window = Measurement::for_aggregation(aggregation, window_size, window_samples).window;
+        if let Window::Timed { .. } = self.window {
+            // The times of the values are gone
+            return;
+        }
+        for value in current {
+            self.push(value, None);
+        }
+    }
+
+    /// Filters the values before they are aggregated, or stops filtering
+    /// them with `None`.
+    pub fn set_outlier_policy(&mut self, policy: Option<OutlierPolicy>) {
+        self.outliers = policy.map(OutlierFilter::new);
+    }
+
+    /// Number of values the outlier policy rejected or clamped in the window.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Number of values the outlier policy rejected or clamped since the last
+    /// complete reset.
+    pub fn run_rejected(&self) -> u64 {
+        self.run_rejected
+    }
+
+    /// Starts the time of the window and the run over, e.g. when the first
+    /// iteration begins.
+    pub fn restart_clock(&mut self) {
+        if let Window::Timed { window, run, .. } = &mut self.window {
+            window.started = Instant::now();
+            run.started = window.started;
         }
     }
 
     pub fn reset_window(&mut self) {
-        self.window_values.clear();
+        self.rejected = 0;
+        if let Some(outliers) = self.outliers.as_mut() {
+            outliers.new_window();
+        }
+        match &mut self.window {
+            Window::Values { values, .. } => values.clear(),
+            Window::Sketch { window, last, .. } => {
+                window.clear();
+                *last = None;
+            }
+            Window::Timed { window, last, .. } => {
+                *window = TimedTotals::new();
+                *last = None;
+            }
+        }
     }
 
     pub fn reset_complete(&mut self) {
-        self.values.clear();
-        self.window_values.clear();
+        self.reset_window();
+        match &mut self.window {
+            Window::Sketch { run, .. } => run.clear(),
+            Window::Timed { run, .. } => *run = TimedTotals::new(),
+            Window::Values { .. } => {}
+        }
         self.nr_values = 0;
         self.total_average = 0.0;
+        self.run_rejected = 0;
+        if let Some(outliers) = self.outliers.as_mut() {
+            outliers.clear();
+        }
     }
 
     pub fn aggregate(&self) -> f64 {
-        match self.agg_func.as_ref() {
-            None => {
-                self.window_values.iter().fold(0.0, |acc, x| acc + x)
-                    / (self.window_values.len() as f64)
-            }
-            Some(func) => func(&self.window_values),
+        match &self.window {
+            Window::Values {
+                values,
+                agg_func: None,
+            } => values.iter().fold(0.0, |acc, x| acc + x) / (values.len() as f64),
+            Window::Values {
+                values,
+                agg_func: Some(func),
+            } => func(values),
+            Window::Sketch {
+                quantile, window, ..
+            } => window.quantile(*quantile),
+            Window::Timed {
+                per_second, window, ..
+            } => window.aggregate(*per_second, Instant::now()),
+        }
+    }
+
+    /// The aggregate over every value since the last complete reset: the
+    /// quantile for sketched measurements, the sum per second or time-weighted
+    /// mean for timed ones and the mean for the others.
+    pub fn run_aggregate(&self) -> f64 {
+        match &self.window {
+            Window::Sketch { quantile, run, .. } => run.quantile(*quantile),
+            Window::Timed {
+                per_second, run, ..
+            } => run.aggregate(*per_second, Instant::now()),
+            Window::Values { .. } => self.total_average,
+        }
+    }
+
+    /// Sketch of every value since the last complete reset, if sketched.
+    pub fn run_sketch(&self) -> Option<&QuantileSketch> {
+        match &self.window {
+            Window::Sketch { run, .. } => Some(run),
+            Window::Values { .. } | Window::Timed { .. } => None,
         }
     }
 
     pub fn prev_value(&self) -> Option<f64> {
-        self.window_values.last().copied()
+        match &self.window {
+            Window::Values { values, .. } => values.last().copied(),
+            Window::Sketch { last, .. } | Window::Timed { last, .. } => *last,
+        }
     }
 
-    pub fn register_value(&mut self, value: f64, apto_mode: &AptoMode) {
-        if let AptoMode::Profile(_) = apto_mode {
-            self.values.push(value);
+    /// Only timed measurements read `at`, and take the current time without.
+    fn push(&mut self, value: f64, at: Option<Instant>) {
+        match &mut self.window {
+            Window::Values { values, .. } => values.push(value),
+            Window::Sketch {
+                window, run, last, ..
+            } => {
+                window.insert(value);
+                run.insert(value);
+                *last = Some(value);
+            }
+            Window::Timed {
+                window, run, last, ..
+            } => {
+                let at = at.unwrap_or_else(Instant::now);
+                window.add(value, at);
+                run.add(value, at);
+                *last = Some(value);
+            }
         }
+    }
+
+    pub fn register_value(&mut self, value: f64) {
+        self.register(value, None);
+    }
+
+    /// Registers a value that was measured at `at` rather than now.
+    pub fn register_value_at(&mut self, value: f64, at: Instant) {
+        self.register(value, Some(at));
+    }
 
-        self.window_values.push(value);
+    fn register(&mut self, value: f64, at: Option<Instant>) {
+        let value = match self.outliers.as_mut() {
+            Some(outliers) => {
+                let kept = outliers.filter(value);
+                if kept != Some(value) {
+                    self.rejected += 1;
+                    self.run_rejected += 1;
+                }
+                match kept {
+                    Some(kept) => kept,
+                    None => return,
+                }
+            }
+            None => value,
+        };
+        self.push(value, at);
         self.nr_values += 1;
 
         let nr_vals = self.nr_values as f64;
         self.total_average =
             ((nr_vals - 1.0) / nr_vals) * self.total_average + (1.0 / nr_vals) * value;
     }
+}
+
+/// Mean and spread of a measure over repeated profiling runs of the same
+/// configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RunSummary {
+    pub mean: f64,
+    pub stddev: f64,
+    /// Half-width of the 95% confidence interval of the mean.
+    pub ci95: f64,
+}
+
+// Two-sided 95% critical values of Student's t for 1 to 30 degrees of freedom.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
 
-    pub fn total_average(&self) -> f64 {
-        self.total_average
+pub(crate) fn summarize_runs(values: &[f64]) -> RunSummary {
+    let n = values.len();
+    let mean = values.iter().sum::<f64>() / (n as f64);
+    if n < 2 {
+        return RunSummary {
+            mean,
+            stddev: 0.0,
+            ci95: 0.0,
+        };
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / ((n - 1) as f64);
+    let stddev = variance.sqrt();
+    let t = T_95.get(n - 2).copied().unwrap_or(1.96);
+    RunSummary {
+        mean,
+        stddev,
+        ci95: t * stddev / (n as f64).sqrt(),
     }
 }
 
@@ -70,6 +368,7 @@ mod tests {
     use rand::Rng;
 
     use super::*;
+    use std::time::Duration;
 
     #[test]
     fn total_average() {
@@ -79,7 +378,7 @@ mod tests {
         for _ in 0..5000 {
             let new_val = rng.gen_range(0.0..10000.0);
             values.push(new_val);
-            m.register_value(new_val, &AptoMode::Profile(10));
+            m.register_value(new_val);
         }
         let expected = values.iter().fold(0.0, |acc, v| acc + v) / (values.len() as f64);
         assert!(expected - m.aggregate() <= f64::EPSILON);
@@ -93,7 +392,7 @@ mod tests {
         for idx in 0..100 {
             let new_val = rng.gen_range(0.0..10000.0);
             values.push(new_val);
-            m.register_value(new_val, &AptoMode::Adaptive);
+            m.register_value(new_val);
             if idx % 40 == 0 {
                 let expected = values.iter().fold(0.0, |acc, v| acc + v) / (values.len() as f64);
                 assert!(expected - m.aggregate() <= f64::EPSILON);
@@ -107,18 +406,109 @@ mod tests {
     fn measures_aggregation_percentile() {
         let mut m = Measurement::new(5, Some(make_percentile_function(0.4)));
         for number in [15, 20, 35, 40, 50] {
-            m.register_value(number as f64, &AptoMode::Adaptive);
+            m.register_value(number as f64);
         }
         assert!((m.aggregate() - 29.0).abs() < f64::EPSILON);
 
         let mut m = Measurement::new(5, Some(make_percentile_function(0.75)));
         for number in [1, 2, 3, 4] {
-            m.register_value(number as f64, &AptoMode::Adaptive);
+            m.register_value(number as f64);
         }
         assert!((m.aggregate() - 3.25).abs() < f64::EPSILON);
     }
 
-    fn make_percentile_function(ptile: f64) -> Box<dyn Fn(&[f64]) -> f64> {
+    #[test]
+    fn sketched_percentile() {
+        let window_samples = Arc::new(AtomicU64::new(100));
+        let mut m =
+            Measurement::for_aggregation(Aggregation::Percentile(99.0), 100, &window_samples);
+        for value in 1..=100 {
+            m.register_value(value as f64);
+        }
+        assert!((m.aggregate() - 99.0).abs() <= 0.99);
+        assert_eq!(m.prev_value(), Some(100.0));
+        m.reset_window();
+        for value in 1..=100 {
+            m.register_value(value as f64 * 10.0);
+        }
+        assert!((m.aggregate() - 990.0).abs() <= 9.9);
+        // The run holds both windows
+        assert!((m.run_aggregate() - 980.0).abs() <= 9.8);
+        assert_eq!(m.run_sketch().unwrap().count(), 200);
+
+        m.set_aggregation(Aggregation::Max, 100, &window_samples);
+        assert_eq!(m.aggregate(), 1000.0);
+        assert!(m.run_sketch().is_none());
+    }
+
+    #[test]
+    fn aggregate_over_time() {
+        let started = Instant::now();
+        let totals = || TimedTotals {
+            started,
+            last: None,
+            sum: 0.0,
+            weighted_sum: 0.0,
+        };
+        // One request every 250ms for two seconds of a four second window
+        let mut requests = totals();
+        for idx in 1..=8 {
+            requests.add(1.0, started + Duration::from_millis(250 * idx));
+        }
+        let end = started + Duration::from_secs(4);
+        assert!((requests.aggregate(true, end) - 2.0).abs() < 1e-9);
+        assert!((requests.aggregate(true, started) - 4.0).abs() < 1e-9);
+
+        // 10 for one second, then 40 for three
+        let mut queue = totals();
+        queue.add(10.0, started + Duration::from_secs(1));
+        queue.add(40.0, started + Duration::from_secs(4));
+        assert!((queue.aggregate(false, end + Duration::from_secs(1)) - 32.5).abs() < 1e-9);
+
+        let mut m = Measurement::over_time(true);
+        assert!(m.aggregate().is_nan());
+        m.register_value_at(1.0, Instant::now() + Duration::from_secs(1));
+        m.register_value(2.0);
+        assert_eq!(m.prev_value(), Some(2.0));
+        assert!(m.aggregate() > 0.0 && m.aggregate() <= 3.0);
+        m.reset_window();
+        assert_eq!(m.prev_value(), None);
+        assert!(m.run_aggregate() > 0.0);
+    }
+
+    #[test]
+    fn reject_outliers() {
+        let mut m = Measurement::new(40, None);
+        m.set_outlier_policy(Some(OutlierPolicy::Range {
+            min: 0.0,
+            max: 10.0,
+        }));
+        for value in [2.0, 4.0, 250.0, -1.0, 6.0] {
+            m.register_value(value);
+        }
+        assert_eq!(m.aggregate(), 4.0);
+        assert_eq!(m.prev_value(), Some(6.0));
+        assert_eq!((m.rejected(), m.run_rejected()), (2, 2));
+        m.reset_window();
+        m.register_value(11.0);
+        assert_eq!((m.rejected(), m.run_rejected()), (1, 3));
+        assert_eq!(m.run_aggregate(), 4.0);
+        m.reset_complete();
+        assert_eq!(m.run_rejected(), 0);
+    }
+
+    #[test]
+    fn summarize_repeated_runs() {
+        let summary = summarize_runs(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert!((summary.mean - 5.0).abs() < f64::EPSILON);
+        assert!((summary.stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert!((summary.ci95 - 2.365 * summary.stddev / 8.0f64.sqrt()).abs() < 1e-12);
+
+        let single = summarize_runs(&[3.0]);
+        assert_eq!((single.mean, single.stddev, single.ci95), (3.0, 0.0, 0.0));
+    }
+
+    fn make_percentile_function(ptile: f64) -> Box<dyn Fn(&[f64]) -> f64 + Send + Sync> {
         Box::new(move |values: &[f64]| {
             let index = ((values.len() as f64) - 1.0) * ptile;
             let values: Vec<f64> = values
@@ -1,19 +1,86 @@
-use crate::measures::Measurement;
-use crate::profile::ActiveModel;
+use crate::aggregation::Aggregation;
+use crate::configurations::{LearningPolicy, OperatingMode};
+use crate::control::{
+    ConfigurationState, ControlReply, ControlRequest, ControlServer, ControllerState, ScheduleState,
+};
+use crate::derived::DerivedMeasure;
+use crate::measures::{summarize_runs, Measurement, RunSummary};
+use crate::outliers::rejected_measure;
+use crate::prediction::predict_missing;
+use crate::profile::{write_atomic, ActiveModel, KnobTable, MeasureTable, ProfileError};
+use crate::profile_file::{ProfileFile, ProfileMetadata};
+use crate::profile_output::TableAppender;
+use crate::sampling::SamplingPlan;
+use crate::sketch::QuantileSketch;
 use crate::system_measures::Energymon;
+use crate::window::WindowClock;
 use crate::AptoConfig as Configurations;
-use crate::NAME_REGEX;
-use crate::{Goal, Perturbation};
+use crate::{Goal, Perturbation, Tunable};
 use itertools::Itertools;
 use log::{info, trace, warn};
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Instant;
+use OptimizingController::controller::SchedType;
 use OptimizingController::Controller;
 
-type Schedule = (u64, u64, u64);
+/// Runs `lower` for the first `lower_share` of every window and `upper` for
+/// the rest of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Schedule {
+    lower: u64,
+    upper: u64,
+    lower_share: f64,
+}
+
+impl Schedule {
+    fn single(config: u64) -> Schedule {
+        Schedule {
+            lower: config,
+            upper: config,
+            lower_share: 1.0,
+        }
+    }
+
+    /// Converts the controller's split of a window of `resolution` iterations.
+    fn from_controller(
+        (lower, upper, nr_lower_iterations): (u64, u64, u64),
+        resolution: u64,
+    ) -> Schedule {
+        Schedule {
+            lower,
+            upper,
+            lower_share: (nr_lower_iterations as f64 / resolution as f64).min(1.0),
+        }
+    }
+
+    /// The configuration to run once `progress` of the window has passed.
+    fn config_at(&self, progress: f64) -> u64 {
+        if progress >= self.lower_share {
+            self.upper
+        } else {
+            self.lower
+        }
+    }
+}
+/// A measuring device for `name`, aggregated and filtered as configured.
+fn new_measurement<H: Tunable<u64>>(
+    config: &Configurations<u64, H>,
+    name: &str,
+    window: &WindowClock,
+) -> Measurement {
+    let mut measurement = Measurement::for_aggregation(
+        config.aggregation(name),
+        config.window_size(),
+        &window.completed_samples(),
+    );
+    measurement.set_outlier_policy(config.outlier_policy(name));
+    measurement
+}
+
+type MainLoop<'a, H> = Box<dyn FnMut(&mut Apto<H>) -> bool + 'a>;
 
 pub(crate) enum AptoMode {
     Profile(u64),
@@ -26,11 +93,34 @@ struct AptoState {
     active_model: ActiveModel,
     controller: Controller,
     sched: Schedule,
+    window: WindowClock,
     mode: AptoMode,
+    learning: Option<OnlineLearning>,
+}
+
+/// Folds what the active configuration achieved in every window back into
+/// the model, so that it follows a machine that drifted since profiling.
+struct OnlineLearning {
+    /// Weight of a new observation in the moving average.
+    weight: f64,
+    /// Number of learned windows after which the controller is handed the
+    /// refined model.
+    period: u64,
+    nr_windows: u64,
+}
+
+impl OnlineLearning {
+    fn new(policy: LearningPolicy) -> OnlineLearning {
+        OnlineLearning {
+            weight: policy.weight,
+            period: policy.period,
+            nr_windows: 0,
+        }
+    }
 }
 
 impl AptoState {
-    fn new(config: &Configurations<u64>) -> AptoState {
+    fn new<H: Tunable<u64>>(config: &Configurations<u64, H>) -> Result<AptoState, ProfileError> {
         // We don't really need this because
         // energyDelta, powerConsumption and windowLatency
         // will be reported only once per window any ways
@@ -40,39 +130,28 @@ impl AptoState {
         println!("Profile: {:?}", config.measure_table.profile);
 
 
-        let window_size = config.window_size;
+        let window = WindowClock::new(config.window);
         let measurements = config
             .measure_table
             .names
             .iter()
-            .filter_map(|name| match name.as_str() {
-                "id" => None,
-                "powerConsumption" | "harmonicMean" | "geometricMean" | "harmonicMeanABS" => {
-                    Some((
-                        String::from(name),
-                        Measurement::new(
-                            config.window_size,
-                            Some(Box::new(|vals| *vals.last().unwrap())),
-                        ),
-                    ))
-                }
-                "windowLatency" => Some((
-                    String::from(name),
-                    Measurement::new(config.window_size, None),
-                )),
-                "energyDelta" => Some((
-                    String::from(name),
-                    Measurement::new(
-                        config.window_size,
-                        Some(Box::new(move |vals| {
-                            vals.last().unwrap() / (window_size as f64)
-                        })),
-                    ),
-                )),
-                name => Some((
-                    String::from(name),
-                    Measurement::new(config.window_size, None),
-                )),
+            .filter(|name| name.as_str() != "id")
+            .cloned()
+            .chain(
+                config
+                    .derived
+                    .iter()
+                    .map(|measure| measure.name().to_string()),
+            )
+            .chain(
+                config
+                    .outlier_policies
+                    .keys()
+                    .map(|name| rejected_measure(name)),
+            )
+            .map(|name| {
+                let measurement = new_measurement(config, &name, &window);
+                (name, measurement)
             })
             .collect();
         trace!(
@@ -81,211 +160,624 @@ impl AptoState {
         );
         println!(" Checking constraint '{}' in {:?}", config.goal.constraint, config.measure_table.names);
 
-        let constraint_idx = config.measure_table.constraint_idx(&config.goal.constraint);
+        let constraint_idx = config
+            .measure_table
+            .try_constraint_idx(&config.goal.constraint)?;
 
-        let mut active_model = ActiveModel::new(&config.measure_table, &config.knob_table);
-        
         println!("Config Knobs:");
         for (key, _value) in &config.knobs {
             println!("Knob Name: {}", key);
         }
 
-        let nr_configs_removed = active_model.restrict_model(&config.knobs);
-        warn!(
-            "{} configs were filtered out (remaining {}) for instance {}",
-            nr_configs_removed,
-            active_model.configs.len(),
-            config.instance_id
-        );
-        active_model.sort_by_constraint(constraint_idx);
+        let active_model = AptoState::build_model(config, &config.goal, constraint_idx)?;
+        let initial_config_idx = AptoState::current_config_idx(config, &active_model)?;
 
-        let mode = if let Ok(num_iterations) = std::env::var("PROFILE") {
-            let num_iterations = num_iterations.parse().unwrap_or_else(|e| {
-                panic!(
-                    "Could not convert profiling iterations ({}) : {:?}",
-                    num_iterations, e
-                )
-            });
-            AptoMode::Profile(num_iterations)
-        } else {
-            AptoMode::Adaptive
+        let mode = match config.resolved_mode()? {
+            OperatingMode::Profile { iterations, .. } => AptoMode::Profile(iterations),
+            OperatingMode::Adaptive => AptoMode::Adaptive,
+            OperatingMode::NonAdaptive => AptoMode::NonAdaptive,
         };
 
-        let controller = AptoState::init_controller(config, &active_model, constraint_idx);
+        let controller = AptoState::init_controller(
+            config,
+            &config.goal,
+            &active_model,
+            constraint_idx,
+            initial_config_idx,
+        )?;
+
+        let sched = Schedule::single(initial_config_idx);
 
-        let initial_config_idx = active_model.find_id(&config.knobs).unwrap_or(0) as u64;
-        let sched = (initial_config_idx, initial_config_idx, config.window_size);
+        let learning = config.online_learning.map(OnlineLearning::new);
+        if let Some(learning) = learning.as_ref() {
+            info!(
+                "Learning online with weight {} every {} windows (instance {})",
+                learning.weight, learning.period, config.instance_id
+            );
+        }
 
-        AptoState {
+        Ok(AptoState {
             measurements,
             active_model,
             controller,
             sched,
+            window,
             mode,
+            learning,
+        })
+    }
+
+    /// Joins the profile into the active model, restricted to the values the
+    /// knobs support and, if pruning is enabled, without the configurations
+    /// the controller would never pick for `goal`. If enabled, knob
+    /// combinations that were not profiled are predicted from the profiled
+    /// ones.
+    fn build_model<H: Tunable<u64>>(
+        config: &Configurations<u64, H>,
+        goal: &Goal,
+        constraint_idx: usize,
+    ) -> Result<ActiveModel, ProfileError> {
+        let mut active_model = if config.predict_missing {
+            let prediction = predict_missing(
+                &config.measure_table,
+                &config.knob_table,
+                &config.knob_domains(),
+            );
+            info!(
+                "Predicted {} unprofiled configs for instance {}",
+                prediction.predicted.len(),
+                config.instance_id
+            );
+            let mut active_model =
+                ActiveModel::new(&prediction.measure_table, &prediction.knob_table);
+            active_model.mark_predicted(prediction.predicted);
+            if config.exclude_predicted {
+                let nr_configs_excluded = active_model.exclude_predicted();
+                info!(
+                    "{} predicted configs were excluded for instance {}",
+                    nr_configs_excluded, config.instance_id
+                );
+            }
+            active_model
+        } else {
+            ActiveModel::new(&config.measure_table, &config.knob_table)
+        };
+
+        let nr_configs_removed = active_model.restrict_model(&config.knobs);
+        warn!(
+            "{} configs were filtered out (remaining {}) for instance {}",
+            nr_configs_removed,
+            active_model.configs.len(),
+            config.instance_id
+        );
+
+        // A controller that runs a single configuration per window does not
+        // interpolate between neighbours, so any configuration may be its best.
+        let single_conf = matches!(
+            SchedType::new(config.instance_id as u64),
+            SchedType::RLSingleConf
+        );
+        if config.prune_dominated && single_conf {
+            info!(
+                "Not pruning dominated configs for a single configuration controller (instance {})",
+                config.instance_id
+            );
+        } else if config.prune_dominated {
+            let (_, obj_measure_indices) =
+                config.measure_table.objective_measures(&goal.opt_func)?;
+            // The controller only interpolates the objective linearly when it
+            // is a single measure, so expressions keep the full model.
+            if let [objective_idx] = obj_measure_indices[..] {
+                let nr_configs_pruned =
+                    active_model.prune_dominated(constraint_idx, objective_idx, goal.opt_type);
+                info!(
+                    "{} dominated configs were pruned (remaining {}) for instance {}",
+                    nr_configs_pruned,
+                    active_model.configs.len(),
+                    config.instance_id
+                );
+            } else {
+                info!(
+                    "Not pruning dominated configs for objective {} (instance {})",
+                    goal.opt_func, config.instance_id
+                );
+            }
         }
+
+        active_model.sort_by_constraint(constraint_idx);
+        Ok(active_model)
     }
 
-    fn init_controller(
-        config: &Configurations<u64>,
+    /// Index in `active_model` of the configuration the knobs are set to.
+    fn current_config_idx<H: Tunable<u64>>(
+        config: &Configurations<u64, H>,
         active_model: &ActiveModel,
-        constraint_idx: usize,
-    ) -> Controller {
-        let obj_measures: Vec<String> = NAME_REGEX
-            .find_iter(&config.goal.opt_func)
-            .map(|f| String::from(f.as_str()))
-            .collect();
-        let obj_measure_indices: Vec<usize> = obj_measures
-            .iter()
-            .map(|needle| {
+    ) -> Result<u64, ProfileError> {
+        match active_model.find_id(&config.knobs) {
+            Some(idx) => Ok(idx as u64),
+            None => Err(ProfileError::UnknownConfiguration(
                 config
-                    .measure_table
-                    .names
+                    .apply_order
                     .iter()
-                    .position(|haystack| needle == haystack)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Measure ({}) not found in measure table header",
-                            needle.as_str()
-                        )
-                    })
-            })
-            .collect();
+                    .map(|name| (name.clone(), config.knobs[name].get()))
+                    .collect(),
+            )),
+        }
+    }
+
+    fn init_controller<H: Tunable<u64>>(
+        config: &Configurations<u64, H>,
+        goal: &Goal,
+        active_model: &ActiveModel,
+        constraint_idx: usize,
+        initial_config_idx: u64,
+    ) -> Result<Controller, ProfileError> {
+        let (obj_measures, obj_measure_indices) =
+            config.measure_table.objective_measures(&goal.opt_func)?;
 
         let filtered_model = active_model.cost_model(&obj_measure_indices);
-        let initial_config_idx = active_model.find_id(&config.knobs).unwrap_or(0) as u64;
 
-        
-        Controller::new(
+        Ok(Controller::new(
             config.instance_id as u64,
             active_model.measure_values(),
             filtered_model,
-            config.goal.target,
+            goal.target,
             constraint_idx,
-            config.window_size as usize,
-            config.goal.opt_type,
-            &config.goal.opt_func,
+            config.window_size() as usize,
+            goal.opt_type,
+            &goal.opt_func,
             obj_measures,
             initial_config_idx as usize,
-        )
+        ))
     }
 }
 
-pub struct Apto {
+/// The model and controller for a new goal, see `Apto::try_change_goal`.
+struct RebuiltModel {
+    model: ActiveModel,
+    controller: Controller,
+    config_idx: u64,
+}
+
+/// A knob that Apto moved when an iteration began.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnobChange {
+    pub knob: String,
+    pub previous: u64,
+    pub value: u64,
+}
+
+/// One configuration to profile, as handed out by `Apto::profile_runs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileRun {
+    pub id: u64,
+    /// Which of the repeated runs of this configuration it is.
+    pub repetition: usize,
+    pub knobs: Vec<(String, u64)>,
+    /// Iterations run before the measures are reset.
+    pub warmup: usize,
+    pub iterations: usize,
+}
+
+/// Progress of an instance driven through `start` / `end_iteration`.
+struct RunState {
+    energy_monitor: Energymon,
+    started: Instant,
+    iteration: u64,
+    current_config: u64,
+    /// Set while an iteration is in progress.
+    iteration_start: Option<Instant>,
+}
+
+/// The configurations of a profiling session and the table their rows are
+/// appended to.
+struct PreparedProfile {
+    configurations: Vec<Vec<(String, u64)>>,
+    /// Ids that a previous session already profiled.
+    profiled_ids: HashSet<u64>,
+    measure_names: Vec<String>,
+    measure_table: TableAppender,
+}
+
+struct ProfileSession {
+    measure_names: Vec<String>,
+    measure_table: TableAppender,
+    stats: Option<TableAppender>,
+    runs: Vec<ProfileRun>,
+    num_iterations: usize,
+    repetitions: usize,
+    warmup: usize,
+    /// Values of every repetition of the configuration being profiled.
+    values: HashMap<String, Vec<f64>>,
+    /// Iterations done, warmup and total iterations of the active run.
+    active: Option<(usize, usize, usize)>,
+}
+
+impl ProfileSession {
+    /// Writes the averages (and statistics) over all repetitions of `id`.
+    fn write_rows(&mut self, id: u64) {
+        let values = std::mem::take(&mut self.values);
+        let summaries: HashMap<&str, RunSummary> = values
+            .iter()
+            .map(|(name, values)| (name.as_str(), summarize_runs(values)))
+            .collect();
+
+        info!("Writing measuretable line.");
+        // Write averages of observed measures over all runs
+        let row = std::iter::once(id.to_string())
+            .chain(
+                self.measure_names
+                    .iter()
+                    .map(|name| summaries[name.as_str()].mean.to_string()),
+            )
+            .join(",");
+        self.measure_table
+            .append(&row)
+            .unwrap_or_else(|e| panic!("Could not write measure table row: {}", e));
+
+        if let Some(stats_file) = self.stats.as_mut() {
+            let row = std::iter::once(id.to_string())
+                .chain(self.measure_names.iter().map(|name| {
+                    let summary = summaries[name.as_str()];
+                    format!("{},{}", summary.stddev, summary.ci95)
+                }))
+                .join(",");
+            stats_file
+                .append(&row)
+                .unwrap_or_else(|e| panic!("Could not write profiling stats row: {}", e));
+        }
+    }
+}
+
+pub struct Apto<H = Rc<dyn Tunable<u64>>> {
     state: AptoState,
-    configurations: Configurations<u64>,
-    outfiles: HashMap<&'static str, BufWriter<File>>,
+    configurations: Configurations<u64, H>,
+    run: Option<RunState>,
+    profiling: Option<ProfileSession>,
+    control: Option<ControlServer>,
 }
 
-impl Apto {
-    pub fn new(configs: Configurations<u64>) -> Apto {
-        let state = AptoState::new(&configs);
+impl<H: Tunable<u64>> Apto<H> {
+    pub fn new(configs: Configurations<u64, H>) -> Apto<H> {
+        let instance_id = configs.instance_id;
+        Self::try_new(configs).unwrap_or_else(|e| {
+            panic!(
+                "Could not initialize Apto (instance {}): {}",
+                instance_id, e
+            )
+        })
+    }
+
+    /// Same as `Apto::new` but reports a profile that does not fit the goal
+    /// instead of panicking, so a multi-tenant run can skip the offending instance.
+    pub fn try_new(mut configs: Configurations<u64, H>) -> Result<Apto<H>, ProfileError> {
+        configs.check_goal()?;
+        configs.add_env_settings()?;
+        configs.add_env_window()?;
+        configs.add_env_outliers()?;
+        configs.add_env_telemetry()?;
+        configs.add_env_timeline()?;
+        // Read again once profiling starts
+        configs.resolved_flush()?;
+        let state = AptoState::new(&configs)?;
+        let control = configs
+            .resolved_control_socket()
+            .map(|path| {
+                ControlServer::bind(&path).map_err(|source| ProfileError::Io {
+                    path: path.display().to_string(),
+                    source,
+                })
+            })
+            .transpose()?;
         info!(
             "Initialized Apto (instance {}) to {} with window {}",
-            configs.instance_id, configs.goal, configs.window_size
+            configs.instance_id, configs.goal, configs.window
         );
         let mut new_apto = Apto {
             state,
             configurations: configs,
-            outfiles: HashMap::new(),
+            run: None,
+            profiling: None,
+            control,
         };
         new_apto.apply_knob_settings(u64::MAX, 0);
-        new_apto
+        Ok(new_apto)
     }
 
+    /// Runs the application until `main_loop` returns false, or through every
+    /// configuration when profiling.
     pub fn optimize<'a>(
         &'a mut self,
         mut stream_initializer: Option<Box<dyn Fn() + 'a>>,
         mut stream_deinitializer: Option<Box<dyn Fn() + 'a>>,
-        mut main_loop: Box<dyn FnMut(&mut Apto) -> bool + 'a>,
+        mut main_loop: MainLoop<'a, H>,
     ) -> bool {
-        if let AptoMode::Profile(num_iterations) = self.state.mode {
-            return self.profile(
-                num_iterations as usize,
-                stream_initializer,
-                stream_deinitializer,
-                main_loop,
-            );
-        }
-
-        let mut energy_monitor = Energymon::new();
+        if let AptoMode::Profile(_) = self.state.mode {
+            self.start();
+            for run in self.profile_runs() {
+                println!(
+                    "Profiling: ({}, run {}) {:?}",
+                    run.id, run.repetition, run.knobs
+                );
 
-        let mut iteration = 0u64;
-        let mut current_config = u64::MAX;
+                // Re-initialize stream for every configuration
+                if let Some(stream_init) = stream_initializer.as_mut() {
+                    stream_init();
+                }
+                self.start_profile_run(&run);
+                for _ in 0..(run.warmup + run.iterations) {
+                    main_loop(self);
+                    self.end_iteration();
+                }
+                if let Some(stream_deinit) = stream_deinitializer.as_mut() {
+                    stream_deinit();
+                }
+                self.end_profile_run(&run);
+            }
+            self.stop();
+            return true;
+        }
 
         if let Some(stream_init) = stream_initializer.as_mut() {
             stream_init();
         }
+        self.start();
+        while main_loop(self) {
+            self.end_iteration();
+        }
+        self.stop();
+        if let Some(stream_deinit) = stream_deinitializer.as_mut() {
+            stream_deinit();
+        }
 
-        loop {
-            let output = self.run_application_body(
-                iteration,
-                current_config,
-                &mut energy_monitor,
-                &mut main_loop,
-            );
+        true
+    }
 
-            let should_terminate = output.0;
-            iteration = output.1;
-            current_config = output.2;
+    /// Starts driving the instance from an external loop and begins the first
+    /// iteration, returning the knobs it changed. The application reports its
+    /// measures with `record` and calls `end_iteration` after every iteration.
+    /// When profiling, the iterations are instead run per configuration of
+    /// `profile_runs`.
+    pub fn start(&mut self) -> Vec<KnobChange> {
+        if let Some(control) = self.control.as_ref() {
+            control.set_serving(true);
+        }
+        self.run = Some(RunState {
+            energy_monitor: Energymon::new(),
+            started: Instant::now(),
+            iteration: 0,
+            current_config: u64::MAX,
+            iteration_start: None,
+        });
+        match self.state.mode {
+            AptoMode::Profile(num_iterations) => {
+                self.profiling = Some(self.start_profiling(num_iterations as usize));
+                Vec::new()
+            }
+            AptoMode::Adaptive | AptoMode::NonAdaptive => self.begin_iteration(),
+        }
+    }
 
-            if should_terminate {
-                break;
+    /// Reports a measure for the current iteration, same as `measure`.
+    pub fn record(&mut self, name: &str, value: f64) {
+        self.measure(name, value);
+    }
+
+    /// Reports a measure taken at `at`, same as `measure_at`.
+    pub fn record_at(&mut self, name: &str, value: f64, at: Instant) {
+        self.measure_at(name, value, at);
+    }
+
+    /// Closes the current iteration and begins the next one, returning the
+    /// knobs that were changed for it. When profiling, nothing begins after
+    /// the last iteration of a run.
+    pub fn end_iteration(&mut self) -> Vec<KnobChange> {
+        self.finish_iteration();
+        if let Some(session) = self.profiling.as_mut() {
+            let Some((done, warmup, total)) = session.active.as_mut() else {
+                return Vec::new();
+            };
+            *done += 1;
+            let (done, warmup, total) = (*done, *warmup, *total);
+            if done == warmup {
+                for device in self.state.measurements.values_mut() {
+                    device.reset_complete();
+                }
+            }
+            if done >= total {
+                return Vec::new();
             }
         }
+        self.begin_iteration()
+    }
 
-        if let Some(stream_deinit) = stream_deinitializer.as_mut() {
-            stream_deinit();
+    /// Closes the current iteration and, when profiling, writes the combined
+    /// profile. Control requests are refused until the next `start`.
+    pub fn stop(&mut self) {
+        self.finish_iteration();
+        if let Some(session) = self.profiling.take() {
+            self.write_profile_file(
+                session.num_iterations as u64,
+                session.repetitions as u64,
+                session.warmup as u64,
+            );
         }
+        if let Some(telemetry) = self.configurations.telemetry.as_mut() {
+            telemetry.flush();
+        }
+        if let Some(control) = self.control.as_ref() {
+            control.set_serving(false);
+        }
+        self.run = None;
+    }
 
-        true
+    /// The configurations that are still to be profiled, each once per
+    /// repetition. Empty unless the instance was started in profile mode.
+    pub fn profile_runs(&self) -> impl Iterator<Item = ProfileRun> {
+        self.profiling
+            .as_ref()
+            .map(|session| session.runs.clone())
+            .unwrap_or_default()
+            .into_iter()
+    }
+
+    /// Applies the configuration of `run` and begins its first iteration.
+    pub fn start_profile_run(&mut self, run: &ProfileRun) -> Vec<KnobChange> {
+        let before = self.knob_values();
+        self.apply_static_profiling_config(&run.knobs);
+        let session = self
+            .profiling
+            .as_mut()
+            .expect("Apto is not profiling, call start first");
+        session.active = Some((0, run.warmup, run.warmup + run.iterations));
+        self.run.as_mut().expect("Apto was not started").iteration = 0;
+        let mut changes = self.knob_changes(before);
+        changes.extend(self.begin_iteration());
+        changes
     }
 
-    fn write_knob_table(&self, knob_names: &[String], configs: &[Vec<(String, u64)>]) {
-        if Path::new("knobtable").exists() {
-            let _ = std::fs::remove_file("knobtable");
+    /// Collects the measures of `run` and writes the row of its configuration
+    /// once every repetition has run.
+    pub fn end_profile_run(&mut self, run: &ProfileRun) {
+        self.finish_iteration();
+        let mut session = self
+            .profiling
+            .take()
+            .expect("Apto is not profiling, call start first");
+        session.active = None;
+        session.runs.retain(|other| other != run);
+        for (name, value) in self.profiled_values(&session.measure_names) {
+            session
+                .values
+                .entry(name.to_string())
+                .or_default()
+                .push(value);
+        }
+
+        // Reset all averages
+        println!("Resetting measures");
+        for device in self.state.measurements.values_mut() {
+            device.reset_complete();
+        }
+
+        if run.repetition + 1 >= session.repetitions {
+            session.write_rows(run.id);
         }
-        let mut kt_file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open("knobtable")
-            .map(BufWriter::new)
-            .unwrap_or_else(|e| panic!("Could not open knobtable: {:?}", e));
+        self.profiling = Some(session);
+    }
+
+    fn write_knob_table(
+        &self,
+        plan: &SamplingPlan,
+        knob_names: &[String],
+        configs: &[Vec<(String, u64)>],
+    ) {
+        let path = &self.configurations.output.knob_table;
+        // Record the plan so an interrupted session can be resumed
+        let mut content = plan.header_line();
+        content.push('\n');
         // Write knob table header
-        let kt_header: String = knob_names.iter().join(",");
-        let _ = kt_file.write(b"id,");
-        let _ = kt_file.write(kt_header.as_bytes());
-        let _ = kt_file.write(b"\n");
+        content.push_str("id,");
+        content.push_str(&knob_names.iter().join(","));
+        content.push('\n');
 
         for (idx, config) in configs.iter().enumerate() {
-            let _ = kt_file.write(format!("{},", idx).as_bytes());
-            let _ = kt_file.write(
-                config
+            content.push_str(&format!("{},", idx));
+            content.push_str(&config.iter().map(|(_, val)| val.to_string()).join(","));
+            content.push('\n');
+        }
+        write_atomic(path, content.as_bytes())
+            .unwrap_or_else(|e| panic!("Could not write knob table: {}", e));
+    }
+
+    /// Reads the configurations of the knob table an earlier session wrote,
+    /// with or without the plan it recorded, if it has the same knobs.
+    fn recorded_knob_table(path: &str, knob_names: &[String]) -> Option<Vec<Vec<(String, u64)>>> {
+        let knob_table = KnobTable::try_new(path).ok()?;
+        if knob_table.names.split_first() != Some((&"id".to_string(), knob_names)) {
+            return None;
+        }
+        let configs = knob_table
+            .configurations
+            .iter()
+            .sorted_by_key(|config| config["id"])
+            .map(|config| {
+                knob_names
                     .iter()
-                    .map(|(_, val)| val.to_string())
-                    .join(",")
-                    .as_bytes(),
-            );
-            let _ = kt_file.write(b"\n");
+                    .map(|name| (name.clone(), config[name]))
+                    .collect()
+            })
+            .collect();
+        Some(configs)
+    }
+
+    /// Ids of the configurations that already have a row in `path`.
+    fn profiled_ids(path: &str) -> HashSet<u64> {
+        if !Path::new(path).exists() {
+            return HashSet::new();
         }
-        let _ = kt_file.flush();
+        let measure_table = MeasureTable::try_new(path)
+            .unwrap_or_else(|e| panic!("Cannot resume profiling: {}", e));
+        let id_idx = measure_table
+            .names
+            .iter()
+            .position(|name| name == "id")
+            .unwrap_or_else(|| panic!("Cannot resume profiling: {} has no id column", path));
+        measure_table
+            .profile
+            .iter()
+            .map(|row| row[id_idx] as u64)
+            .collect()
     }
 
-    fn prepare_profile_tables(&self) -> (Vec<Vec<(String, u64)>>, Vec<String>, BufWriter<File>) {
+    fn prepare_profile_tables(&self) -> PreparedProfile {
+        let output = &self.configurations.output;
+        output
+            .create_dirs()
+            .unwrap_or_else(|e| panic!("Could not create profile output directory: {}", e));
         let knob_names: Vec<String> = self.configurations.knobs.keys().cloned().sorted().collect();
 
-        let all_knob_values_iter = knob_names.iter().map(|name| {
-            self.configurations.knobs[name]
-                .possible_values()
-                .iter()
-                .map(|&value| (name.clone(), value))
-                .collect::<Vec<(String, u64)>>()
-        });
-        let all_configurations: Vec<Vec<(String, u64)>> = all_knob_values_iter
-            .map(|e| e.into_iter())
-            .multi_cartesian_product()
+        // The environment was checked when the instance was created
+        let mode = self
+            .configurations
+            .resolved_mode()
+            .unwrap_or_else(|e| panic!("{}", e));
+        let plan = match mode {
+            OperatingMode::Profile { plan, .. } => plan,
+            _ => SamplingPlan::Full,
+        };
+        let domains: Vec<(String, Vec<u64>)> = knob_names
+            .iter()
+            .map(|name| {
+                let values = self.configurations.knobs[name].possible_values().to_vec();
+                (name.clone(), values)
+            })
             .collect();
-
-        self.write_knob_table(&knob_names, &all_configurations);
+        let configurations = plan
+            .configurations(&domains)
+            .unwrap_or_else(|e| panic!("Could not build profiling plan {}: {}", plan, e));
+
+        // Incase the application has been partially profiled, the ids of its
+        // rows have to refer to the same configurations as the new knob table
+        let profiled_ids = Self::profiled_ids(&output.measure_table);
+        if profiled_ids.is_empty() {
+            info!(
+                "Profiling plan {} selected {} configurations",
+                plan,
+                configurations.len()
+            );
+        } else if Self::recorded_knob_table(&output.knob_table, &knob_names).as_ref()
+            == Some(&configurations)
+        {
+            info!("Resuming profiling plan {}", plan);
+        } else {
+            panic!(
+                "Cannot resume profiling: {} was profiled for other configurations than plan {} \
+                 selects, remove it to start over",
+                output.measure_table, plan
+            );
+        }
+        self.write_knob_table(&plan, &knob_names, &configurations);
 
         let measure_names: Vec<String> = self
             .configurations
@@ -294,182 +786,257 @@ impl Apto {
             .iter()
             .filter(|&name| name != "id")
             .cloned()
+            .chain(
+                self.configurations
+                    .derived
+                    .iter()
+                    .map(|measure| measure.name().to_string()),
+            )
+            .chain(
+                self.configurations
+                    .outlier_policies
+                    .keys()
+                    .map(|name| rejected_measure(name)),
+            )
+            .unique()
             .sorted()
             .collect();
 
-        let mt_file = if Path::new("measuretable").exists() {
-            OpenOptions::new()
-                .append(true)
-                .open("measuretable")
-                .map(BufWriter::new)
-                .unwrap_or_else(|e| panic!("Could not open measuretable: {:?}", e))
-        } else {
-            let mut mt_file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open("measuretable")
-                .map(BufWriter::new)
-                .unwrap_or_else(|e| panic!("Could not open measuretable: {:?}", e));
-
-            // Write measure table header
-            let _ = mt_file.write(b"id,");
-            let _ = mt_file.write(measure_names.iter().join(",").as_bytes());
-            let _ = mt_file.write(b"\n");
-            let _ = mt_file.flush();
-
-            mt_file
-        };
-
-        (all_configurations, measure_names, mt_file)
-    }
-
-    fn apply_static_profiling_config(&'_ mut self, config: &Vec<(String, u64)>) {
-        for (name, value) in config {
-            if name == "hyperthreading" || name == "cacheCOS" {
-                continue;
-            }
-            self.configurations.knobs.get(name).unwrap().set(*value);
-        }
-        let special_knobs = config.iter().fold([None, None], |mut acc, (name, value)| {
-            match name.as_str() {
-                "hyperthreading" => acc[0] = Some((name, value)),
-                "cacheCOS" => acc[1] = Some((name, value)),
-                _ => (),
-            };
-            acc
+        let mt_file = self.open_profile_table(&output.measure_table, &measure_names, |name| {
+            name.to_string()
         });
-        for &(name, value) in special_knobs.iter().flatten() {
-            self.configurations.knobs.get(name).unwrap().set(*value);
+
+        PreparedProfile {
+            configurations,
+            profiled_ids,
+            measure_names,
+            measure_table: mt_file,
         }
     }
 
-    fn profile<'a>(
-        &'a mut self,
-        num_iterations: usize,
-        mut stream_initializer: Option<Box<dyn Fn() + 'a>>,
-        mut stream_deinitializer: Option<Box<dyn Fn() + 'a>>,
-        mut main_loop: Box<dyn FnMut(&mut Apto) -> bool + 'a>,
-    ) -> bool {
-        let (configurations, ordered_measure_names, mut mt_file) = self.prepare_profile_tables();
-
-        // Incase the application has been partially profiled
-        let nr_to_skip = std::env::var("PROFILE_SKIP").map(|nr| nr.parse::<usize>().unwrap());
-
-        let mut energy_monitor = Energymon::new();
+    /// Opens a profiling table for appending, writing its header if it does
+    /// not exist yet. Each measure contributes the columns named by `columns`.
+    fn open_profile_table<F>(
+        &self,
+        path: &str,
+        measure_names: &[String],
+        columns: F,
+    ) -> TableAppender
+    where
+        F: Fn(&str) -> String,
+    {
+        let header: Vec<String> = std::iter::once("id".to_string())
+            .chain(measure_names.iter().flat_map(|name| {
+                columns(name)
+                    .split(',')
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            }))
+            .collect();
+        // The environment was checked when the instance was created
+        let flush = self
+            .configurations
+            .resolved_flush()
+            .unwrap_or_else(|e| panic!("{}", e));
+        TableAppender::open(path, &header, flush)
+            .unwrap_or_else(|e| panic!("Cannot append to profiling table: {}", e))
+    }
 
-        for (idx, config) in configurations.iter().enumerate() {
-            if let Ok(nr) = nr_to_skip {
-                if idx <= nr {
-                    println!("Continuing for configuration {}", idx);
-                    continue;
-                }
+    fn apply_static_profiling_config(&'_ mut self, config: &[(String, u64)]) {
+        for name in self.configurations.apply_order.iter() {
+            if let Some((_, value)) = config.iter().find(|(knob, _)| knob == name) {
+                self.configurations.knobs[name].set(*value);
             }
+        }
+    }
 
-            println!("Profiling: ({}) {:?}", idx, config);
+    fn start_profiling(&self, num_iterations: usize) -> ProfileSession {
+        let PreparedProfile {
+            configurations,
+            profiled_ids,
+            measure_names,
+            measure_table,
+        } = self.prepare_profile_tables();
+
+        // Repeat every configuration to average out noisy runs, optionally
+        // discarding the first iterations after each reconfiguration.
+        let repetitions = self.configurations.profile_repetitions;
+        let warmup = self.configurations.profile_warmup;
+        let stats = (repetitions > 1).then(|| {
+            let path = &self.configurations.output.stats;
+            self.open_profile_table(path, &measure_names, |name| {
+                format!("{}_stddev,{}_ci95", name, name)
+            })
+        });
 
-            // Re-initialize stream for every configuration
-            if let Some(stream_init) = stream_initializer.as_mut() {
-                stream_init();
+        let mut runs = Vec::new();
+        for (idx, config) in configurations.into_iter().enumerate() {
+            if profiled_ids.contains(&(idx as u64)) {
+                println!("Continuing for configuration {}", idx);
+                continue;
             }
+            runs.extend((0..repetitions).map(|repetition| ProfileRun {
+                id: idx as u64,
+                repetition,
+                knobs: config.clone(),
+                warmup,
+                iterations: num_iterations,
+            }));
+        }
 
-            // MAYBE: We should read start and end energy here and then compute power consumption separately
-            //        we're not doing this up front because we want any bias to be included in the readings we
-            //        give to the controller.
-
-            self.apply_static_profiling_config(config);
-
-            for i in 0..num_iterations {
-                let _ = self.run_application_body(i as u64, 0, &mut energy_monitor, &mut main_loop);
-            }
+        ProfileSession {
+            measure_names,
+            measure_table,
+            stats,
+            runs,
+            num_iterations,
+            repetitions,
+            warmup,
+            values: HashMap::new(),
+            active: None,
+        }
+    }
 
-            if let Some(stream_deinit) = stream_deinitializer.as_mut() {
-                stream_deinit();
-            }
+    /// Reads the values of one profiling run from the measuring devices.
+    fn profiled_values<'a>(&self, ordered_measure_names: &'a [String]) -> HashMap<&'a str, f64> {
+        // Configured derived measures replace the defaults of the same name
+        let defaults = DerivedMeasure::profile_defaults();
+        let configured = &self.configurations.derived;
+        let derived: Vec<&DerivedMeasure> = defaults
+            .iter()
+            .filter(|default| configured.iter().all(|m| m.name() != default.name()))
+            .chain(configured.iter())
+            .collect();
 
-            let mut measured_values: HashMap<&str, f64> = ordered_measure_names
-                .iter()
-                .map(|name| {
-                    (
-                        name.as_str(),
-                        self.state
-                            .measurements
-                            .get(name)
-                            .unwrap_or_else(|| panic!("No measure found for {}", name))
-                            .total_average(),
-                    )
-                })
-                .collect();
-            // Update performance and power consumption from total averages
-            *measured_values.get_mut("performance").unwrap() = 1.0 / measured_values["latency"];
-            *measured_values.get_mut("powerConsumption").unwrap() =
-                measured_values["energyDelta"] / measured_values["windowLatency"];
-
-            // Only take the last value for the harmonic and geometric means
-            if measured_values.contains_key("harmonicMean") {
-                *measured_values.get_mut("harmonicMean").unwrap() = self
-                    .state
-                    .measurements
-                    .get("harmonicMean")
-                    .unwrap()
-                    .aggregate();
-                *measured_values.get_mut("geometricMean").unwrap() = self
-                    .state
-                    .measurements
-                    .get("geometricMean")
-                    .unwrap()
-                    .aggregate();
-                *measured_values.get_mut("geometricMean").unwrap() = self
+        let mut measured_values: HashMap<&str, f64> = ordered_measure_names
+            .iter()
+            .filter(|name| derived.iter().all(|m| m.name() != name.as_str()))
+            .map(|name| {
+                // Rejected values are counted over the whole run
+                if let Some(measure) = self
+                    .configurations
+                    .outlier_policies
+                    .keys()
+                    .find(|measure| rejected_measure(measure) == *name)
+                {
+                    let rejected = self
+                        .state
+                        .measurements
+                        .get(measure)
+                        .map_or(0, |device| device.run_rejected());
+                    return (name.as_str(), rejected as f64);
+                }
+                let device = self
                     .state
                     .measurements
-                    .get("harmonicMeanABS")
-                    .unwrap()
-                    .aggregate();
+                    .get(name)
+                    .unwrap_or_else(|| panic!("No measure found for {}", name));
+                // The window is not reset while profiling, so it aggregates the
+                // whole run the way it is configured to. `energyDelta` is
+                // measured once per window and profiled as its mean, from which
+                // `powerConsumption` is derived.
+                let value = match self.configurations.aggregation(name) {
+                    Aggregation::Rate => device.run_aggregate(),
+                    _ => device.aggregate(),
+                };
+                (name.as_str(), value)
+            })
+            .collect();
+        for measure in derived {
+            let value = measure.evaluate(|input| {
+                measured_values.get(input).copied().or_else(|| {
+                    self.state
+                        .measurements
+                        .get(input)
+                        .map(|device| device.run_aggregate())
+                })
+            });
+            if let Some(name) = ordered_measure_names
+                .iter()
+                .find(|name| name.as_str() == measure.name())
+            {
+                measured_values.insert(name.as_str(), value);
             }
+        }
+        measured_values
+    }
 
-            info!("Writing measuretable line.");
-            // Write total averages of observed measures
-            let _ = mt_file.write(format!("{},", idx).as_bytes());
-            let _ = mt_file.write(
-                ordered_measure_names
-                    .iter()
-                    .map(|name| measured_values[name.as_str()])
-                    .join(",")
-                    .as_bytes(),
-            );
-            let _ = mt_file.write(b"\n");
-            let _ = mt_file.flush();
-
-            // Reset all averages
-            println!("Resetting measures");
-            for device in self.state.measurements.values_mut() {
-                device.reset_complete();
+    /// Bundles the knob and measure tables with a description of this machine
+    /// so the profile cannot silently be reused on a different one.
+    fn write_profile_file(&self, num_iterations: u64, repetitions: u64, warmup: u64) {
+        let output = &self.configurations.output;
+        let tables = MeasureTable::try_new(output.measure_table.as_str())
+            .and_then(|mt| KnobTable::try_new(output.knob_table.as_str()).map(|kt| (mt, kt)));
+        let (measure_table, knob_table) = match tables {
+            Ok(tables) => tables,
+            Err(e) => {
+                warn!("Could not bundle profile: {}", e);
+                return;
             }
+        };
+
+        let mut metadata = ProfileMetadata::current(
+            self.configurations.window_size(),
+            num_iterations,
+            self.configurations.knob_domains(),
+        );
+        metadata.repetitions = repetitions;
+        metadata.warmup = warmup;
+        let profile = ProfileFile::from_tables(metadata, &measure_table, &knob_table);
+        match profile.save(output.profile.as_str()) {
+            Ok(()) => info!("Wrote combined profile to {}", output.profile),
+            Err(e) => warn!("Could not write combined profile: {}", e),
         }
+    }
 
-        let _ = mt_file.flush();
-        true
+    fn knob_values(&self) -> Vec<(String, u64)> {
+        self.configurations
+            .apply_order
+            .iter()
+            .map(|name| (name.clone(), self.configurations.knobs[name].get()))
+            .collect()
     }
 
-    fn run_application_body<F>(
-        &mut self,
-        mut iteration: u64,
-        mut current_config: u64,
-        energy_monitor: &mut Energymon,
-        main_loop: &mut F,
-    ) -> (bool, u64, u64)
-    where
-        F: FnMut(&mut Apto) -> bool,
-    {
+    fn knob_changes(&self, before: Vec<(String, u64)>) -> Vec<KnobChange> {
+        before
+            .into_iter()
+            .filter_map(|(knob, previous)| {
+                let value = self.configurations.knobs[&knob].get();
+                (value != previous).then_some(KnobChange {
+                    knob,
+                    previous,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    /// Samples energy at window boundaries and actuates the knobs for the
+    /// iteration that begins.
+    fn begin_iteration(&mut self) -> Vec<KnobChange> {
+        self.handle_control_requests();
+        self.apply_goal_timeline();
         let start_instant = Instant::now();
+        let before = self.knob_values();
+        let mut run = self.run.take().expect("Apto was not started");
+        let energy_monitor = &mut run.energy_monitor;
 
         // Update knobs
-        iteration = iteration.wrapping_add(1);
+        run.iteration = run.iteration.wrapping_add(1);
+        let iteration = run.iteration;
+        if iteration == 1 {
+            self.state.window.restart();
+            for device in self.state.measurements.values_mut() {
+                device.restart_clock();
+            }
+        }
+        let window_closed = self.state.window.begin_iteration(iteration);
 
         // Record power numbers across windows during execution.
         // Energy values are saved and re-registered after actuate_knobs,
         // which calls reset_window() at window boundaries.
         let mut energy_snapshot: Option<(f64, f64, f64, f64)> = None;
-        if iteration == 1 || iteration % self.configurations.window_size == 0 {
+        if iteration == 1 || window_closed {
             if iteration > 1 {
                 energy_monitor.stop();
 
@@ -513,7 +1080,18 @@ impl Apto {
             energy_monitor.start();
         }
 
-        current_config = self.actuate_knobs(iteration, current_config);
+        let mut window_measures = Vec::new();
+        if window_closed {
+            window_measures.extend(self.report_rejected_values());
+            self.derive_window_measures(&mut window_measures);
+        }
+
+        run.current_config = self.actuate_knobs(
+            iteration,
+            run.current_config,
+            window_closed,
+            &window_measures,
+        );
 
         // Re-register energy values after window reset so they survive for log_state
         if let Some((energy, energy_delta, power_consumption, window_latency)) = energy_snapshot {
@@ -522,9 +1100,64 @@ impl Apto {
             self.measure("powerConsumption", power_consumption);
             self.measure("windowLatency", window_latency);
         }
+        for (name, value) in window_measures {
+            self.measure(&name, value);
+        }
+
+        run.iteration_start = Some(start_instant);
+        self.run = Some(run);
+        self.knob_changes(before)
+    }
+
+    /// Counts how many values of the window that just ended every outlier
+    /// policy rejected or clamped, as `<measure>Rejected`. Reported with the
+    /// derived measures once the next window has begun.
+    fn report_rejected_values(&self) -> Vec<(String, f64)> {
+        self.configurations
+            .outlier_policies
+            .keys()
+            .filter_map(|name| {
+                let device = self.state.measurements.get(name)?;
+                Some((rejected_measure(name), device.rejected() as f64))
+            })
+            .collect()
+    }
+
+    /// Evaluates the derived measures with the aggregates of the window that
+    /// just ended and adds them to `window_measures`. They are reported once
+    /// the next window has begun, so that they do not mix with its values.
+    fn derive_window_measures(&self, window_measures: &mut Vec<(String, f64)>) {
+        for measure in self.configurations.derived.iter() {
+            let value = measure.evaluate(|input| self.window_aggregate(input, window_measures));
+            window_measures.push((measure.name().to_string(), value));
+        }
+    }
+
+    /// The aggregate of `name` over the window that just ended, taken from
+    /// `window_measures` for the measures evaluated when it ended.
+    fn window_aggregate(&self, name: &str, window_measures: &[(String, f64)]) -> Option<f64> {
+        window_measures
+            .iter()
+            .rev()
+            .find(|(measure, _)| measure == name)
+            .map(|(_, value)| *value)
+            .or_else(|| {
+                self.state
+                    .measurements
+                    .get(name)
+                    .map(|device| device.aggregate())
+            })
+    }
 
-        // Execute application loop
-        let should_terminate = !main_loop(self);
+    /// Reports the measures of the iteration begun by `begin_iteration`.
+    fn finish_iteration(&mut self) {
+        let Some(run) = self.run.as_mut() else {
+            return;
+        };
+        let Some(start_instant) = run.iteration_start.take() else {
+            return;
+        };
+        let (iteration, current_config) = (run.iteration, run.current_config);
 
         self.measure("iteration", iteration as f64);
 
@@ -542,26 +1175,28 @@ impl Apto {
             );
         }
 
-        self.log_state();
-
-        (should_terminate, iteration, current_config)
+        self.log_state(iteration, current_config);
     }
 
 /*
  try to call the tool here
 */
 
-    fn actuate_knobs(&mut self, iteration: u64, current_config: u64) -> u64 {
+    fn actuate_knobs(
+        &mut self,
+        iteration: u64,
+        current_config: u64,
+        window_closed: bool,
+        window_measures: &[(String, f64)],
+    ) -> u64 {
         match self.state.mode {
             AptoMode::Profile(_) => 0,
             AptoMode::Adaptive => {
-                if iteration % self.configurations.window_size == 0 {
+                if window_closed {
                     let constraint_average = self
-                        .state
-                        .measurements
-                        .get(&self.configurations.goal.constraint)
-                        .expect("Could not read constraint measurement for computing schedule.")
-                        .aggregate();
+                        .window_aggregate(&self.configurations.goal.constraint, window_measures)
+                        .expect("Could not read constraint measurement for computing schedule.");
+                    self.learn_from_window(window_measures);
                     let measurement_difference = (self.state.controller.sched_xup*(1.0/self.state.controller.kf.x_hat)) - constraint_average;
                     let x_hat = self.state.controller.kf.x_hat;
                     let multiplier = self.state.controller.pole_adaptation.calculate_multiplier(measurement_difference, constraint_average, x_hat);
@@ -573,7 +1208,8 @@ impl Apto {
                         "Obtained new schedule {:?} for window average {} (instance {})",
                         sched, constraint_average, self.configurations.instance_id
                     );
-                    self.state.sched = sched;
+                    self.state.sched =
+                        Schedule::from_controller(sched, self.configurations.window_size());
                     for (_, device) in self.state.measurements.iter_mut() {
                         device.reset_window();
                     }
@@ -586,30 +1222,51 @@ impl Apto {
     
 
     pub fn measure(&mut self, name: &str, value: f64) {
+        self.register(name, value, None);
+    }
+
+    /// Reports a value that was measured at `at` rather than now, e.g. the
+    /// arrival of a request. Rates and time-weighted means use the time.
+    pub fn measure_at(&mut self, name: &str, value: f64, at: Instant) {
+        self.register(name, value, Some(at));
+    }
+
+    fn register(&mut self, name: &str, value: f64, at: Option<Instant>) {
         if !self.state.measurements.contains_key(name) {
             self.state.measurements.insert(
                 name.to_string(),
-                Measurement::new(self.configurations.window_size, None),
+                new_measurement(&self.configurations, name, &self.state.window),
             );
             warn!(
                 "Initialized measurement for {} in instance {}",
                 name, self.configurations.instance_id
             );
         }
-        self.state
+        let device = self
+            .state
             .measurements
             .get_mut(name)
-            .unwrap_or_else(|| panic!("Expected measure {} to be in measurements", name))
-            .register_value(value, &self.state.mode);
+            .unwrap_or_else(|| panic!("Expected measure {} to be in measurements", name));
+        match at {
+            Some(at) => device.register_value_at(value, at),
+            None => device.register_value(value),
+        }
+    }
+
+    /// Sketch of every value of `name` since the run started, if the measure
+    /// is aggregated to a percentile. Sketches of several instances can be
+    /// merged for percentiles over all of them.
+    pub fn quantile_sketch(&self, name: &str) -> Option<QuantileSketch> {
+        self.state
+            .measurements
+            .get(name)
+            .and_then(|device| device.run_sketch())
+            .cloned()
     }
 
     fn apply_knob_settings(&mut self, current: u64, iteration: u64) -> u64 {
         let sched = self.state.sched;
-        let idx = if iteration % self.configurations.window_size >= sched.2 {
-            sched.1
-        } else {
-            sched.0
-        };
+        let idx = sched.config_at(self.state.window.progress(iteration));
 
         if current == idx {
             return current;
@@ -621,91 +1278,227 @@ impl Apto {
             idx, knob_settings, sched, self.configurations.instance_id
         );
 
-        for (name, knob) in &self.configurations.knobs {
-            if name == "hyperthreading" || name == "cacheCOS" {
-                // Special case for hyperthreading and cacheCOS
-                continue;
+        // Knobs missing from the profile keep their value
+        for name in self.configurations.apply_order.iter() {
+            if let Some(&value) = knob_settings.get(name) {
+                self.configurations.knobs[name].set(value);
             }
-            knob.set(*knob_settings.get(name).unwrap());
         }
 
-        // Special case: If we have hyperthreading then we must readjust it
-        //               to make sure that affinity masks conforms with the
-        //               hyperthreading knob
-        // Special case: If we have cacheCos then we need to apply masks to cores
-        //               in use by the process
-        for name in ["hyperthreading", "cacheCOS"] {
-            // if let Some(knob) = self.configurations.knobs.get(name) {
-            //     knob.set(*knob_settings.get(name).unwrap());
-            // }
+        idx
+    }
+
+    /// Folds the aggregates of the window that just finished into the
+    /// configuration that ran it. Windows split between two configurations
+    /// cannot be attributed and are skipped. Only the measures of the goal are
+    /// refined since the remaining ones are mostly cumulative counters.
+    fn learn_from_window(&mut self, window_measures: &[(String, f64)]) {
+        let Some(weight) = self.state.learning.as_ref().map(|learning| learning.weight) else {
+            return;
+        };
+        let Schedule {
+            lower,
+            upper,
+            lower_share,
+        } = self.state.sched;
+        let config = if lower_share <= 0.0 {
+            upper
+        } else if lower == upper || lower_share >= 1.0 {
+            lower
+        } else {
+            return;
+        };
+        let idx = config as usize;
+
+        let measure_table = &self.configurations.measure_table;
+        let constraint_idx = measure_table.constraint_idx(&self.configurations.goal.constraint);
+        let (_, obj_measure_indices) = measure_table
+            .objective_measures(&self.configurations.goal.opt_func)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let observed: Vec<(usize, f64)> = std::iter::once(constraint_idx)
+            .chain(obj_measure_indices)
+            .unique()
+            .filter_map(|col| {
+                let value = self.window_aggregate(&measure_table.names[col], window_measures)?;
+                Some((col, value))
+            })
+            .collect();
+
+        self.state.active_model.refine(idx, &observed, weight);
+        if let Some(&id) = self.state.active_model.get_knob_settings(idx).get("id") {
+            Arc::make_mut(&mut self.configurations.measure_table).refine(id, &observed, weight);
+        }
+        trace!(
+            "Refined config {} with {:?} (instance {})",
+            idx,
+            observed,
+            self.configurations.instance_id
+        );
+
+        let Some(learning) = self.state.learning.as_mut() else {
+            return;
+        };
+        learning.nr_windows += 1;
+        if learning.nr_windows % learning.period == 0 {
+            self.refresh_controller_model();
         }
+    }
 
-        idx
+    /// Hands the refined model to the controller. The model is re-sorted by
+    /// the constraint, which may move the configurations of the schedule.
+    fn refresh_controller_model(&mut self) {
+        let measure_table = &self.configurations.measure_table;
+        let constraint_idx = measure_table.constraint_idx(&self.configurations.goal.constraint);
+        let (_, obj_measure_indices) = measure_table
+            .objective_measures(&self.configurations.goal.opt_func)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let sched = self.state.sched;
+        let lower = self.state.active_model.configs[sched.lower as usize].clone();
+        let upper = self.state.active_model.configs[sched.upper as usize].clone();
+        self.state.active_model.sort_by_constraint(constraint_idx);
+        let position = |config: &(Vec<f64>, HashMap<String, u64>)| {
+            self.state
+                .active_model
+                .configs
+                .iter()
+                .position(|other| other.1 == config.1)
+                .unwrap() as u64
+        };
+        self.state.sched = Schedule {
+            lower: position(&lower),
+            upper: position(&upper),
+            ..sched
+        };
+
+        self.state.controller.change_model(
+            self.state.active_model.measure_values(),
+            self.state.active_model.cost_model(&obj_measure_indices),
+        );
+        info!(
+            "Updated controller with the refined model (instance {})",
+            self.configurations.instance_id
+        );
+    }
+
+    /// Writes the profile as refined by online learning, so it can replace
+    /// the original one.
+    pub fn export_profile<P>(&self, path: P) -> Result<(), ProfileError>
+    where
+        P: AsRef<Path> + std::fmt::Display,
+    {
+        // No fixed number of iterations went into the refined measures
+        let metadata = ProfileMetadata::current(
+            self.configurations.window_size(),
+            0,
+            self.configurations.knob_domains(),
+        );
+        ProfileFile::from_tables(
+            metadata,
+            &self.configurations.measure_table,
+            &self.configurations.knob_table,
+        )
+        .save(path)
     }
 
     pub fn change_goal(&mut self, goal: Goal) {
+        self.try_change_goal(goal).unwrap_or_else(|e| {
+            panic!(
+                "Could not change the goal of instance {}: {}",
+                self.configurations.instance_id, e
+            )
+        });
+    }
+
+    /// Same as `change_goal`, but leaves the goal as it was if the model for
+    /// the new one does not contain the configuration the knobs are set to.
+    pub fn try_change_goal(&mut self, goal: Goal) -> Result<(), ProfileError> {
         let difference = &goal - &self.configurations.goal;
+        // Which configurations are dominated depends on the objective, so the
+        // model is rebuilt before anything is changed.
+        let rebuilt = match difference {
+            Perturbation::ChangeObjective(..) | Perturbation::ChangeEntireGoal(_) => {
+                Some(self.rebuild_model(&goal)?)
+            }
+            _ => None,
+        };
+        if goal.aggregations != self.configurations.goal.aggregations {
+            self.configurations.goal.aggregations = goal.aggregations.clone();
+            let window_samples = self.state.window.completed_samples();
+            let window_size = self.configurations.window_size();
+            for (name, device) in self.state.measurements.iter_mut() {
+                let aggregation = self.configurations.aggregation(name);
+                device.set_aggregation(aggregation, window_size, &window_samples);
+            }
+        }
         info!(
             "Perturbing goal to {} (instance {})",
             difference, self.configurations.instance_id
         );
-        match difference {
-            Perturbation::NoChange => info!(
+        if let Some(telemetry) = self.configurations.telemetry.as_mut() {
+            let iteration = self.run.as_ref().map_or(0, |run| run.iteration);
+            telemetry.event(
+                self.configurations.instance_id,
+                iteration,
+                self.state.window.index(),
+                &format!("goal perturbation: {}", difference),
+            );
+        }
+        match (difference, rebuilt) {
+            (Perturbation::NoChange, _) => info!(
                 "Change goal applied with the same goal (instance {})",
                 self.configurations.instance_id
             ),
-            Perturbation::ChangeObjective(opt_type, opt_func) => {
+            (Perturbation::ChangeObjective(opt_type, opt_func), Some(rebuilt)) => {
                 self.configurations.goal.opt_func = opt_func;
                 self.configurations.goal.opt_type = opt_type;
-                let obj_measures: Vec<String> = NAME_REGEX
-                    .find_iter(&self.configurations.goal.opt_func)
-                    .map(|f| String::from(f.as_str()))
-                    .collect();
-                let obj_measure_indices: Vec<usize> = obj_measures
-                    .iter()
-                    .map(|needle| {
-                        self.configurations
-                            .measure_table
-                            .names
-                            .iter()
-                            .position(|haystack| needle == haystack)
-                            .unwrap_or_else(|| {
-                                panic!(
-                                    "Measure ({}) not found in measure table header",
-                                    needle.as_str()
-                                )
-                            })
-                    })
-                    .collect();
+                let (obj_measures, obj_measure_indices) = self
+                    .configurations
+                    .measure_table
+                    .objective_measures(&self.configurations.goal.opt_func)?;
+                self.state.active_model = rebuilt.model;
+                self.state.sched = Schedule::single(rebuilt.config_idx);
                 let filtered_cost_model = self.state.active_model.cost_model(&obj_measure_indices);
                 self.state.controller.change_objective(
                     self.configurations.goal.opt_type,
                     &self.configurations.goal.opt_func,
                     obj_measures,
+                    filtered_cost_model.clone(),
+                );
+                self.state.controller.change_model(
+                    self.state.active_model.measure_values(),
                     filtered_cost_model,
                 );
             }
-            Perturbation::ChangeConstraintValue(new_value) => {
+            (Perturbation::ChangeConstraintValue(new_value), _) => {
                 self.configurations.goal.target = new_value;
                 self.state.controller.change_target(new_value);
             }
-            Perturbation::ChangeEntireGoal(new_goal) => {
+            (Perturbation::ChangeEntireGoal(new_goal), Some(rebuilt)) => {
                 self.configurations.goal = new_goal;
-                let constraint_idx = self
-                    .configurations
-                    .measure_table
-                    .constraint_idx(&self.configurations.goal.constraint);
-                let mut active_model = ActiveModel::new(
-                    &self.configurations.measure_table,
-                    &self.configurations.knob_table,
-                );
-                let _ = active_model.restrict_model(&self.configurations.knobs);
-                active_model.sort_by_constraint(constraint_idx);
-
-                self.state.controller =
-                    AptoState::init_controller(&self.configurations, &active_model, constraint_idx);
+                self.state.active_model = rebuilt.model;
+                self.state.sched = Schedule::single(rebuilt.config_idx);
+                self.state.controller = rebuilt.controller;
             }
+            (_, None) => unreachable!("The model is rebuilt for every new objective"),
         }
+        Ok(())
+    }
+
+    /// Builds the active model and controller for `goal`, with the schedule
+    /// pointing at the configuration the knobs are currently set to.
+    fn rebuild_model(&self, goal: &Goal) -> Result<RebuiltModel, ProfileError> {
+        let config = &self.configurations;
+        let constraint_idx = config.measure_table.try_constraint_idx(&goal.constraint)?;
+        let model = AptoState::build_model(config, goal, constraint_idx)?;
+        let config_idx = AptoState::current_config_idx(config, &model)?;
+        let controller =
+            AptoState::init_controller(config, goal, &model, constraint_idx, config_idx)?;
+        Ok(RebuiltModel {
+            model,
+            controller,
+            config_idx,
+        })
     }
 
     pub fn freeze_adaptation(&mut self) {
@@ -748,69 +1541,628 @@ impl Apto {
         );
     }
 
-    fn log_state(&mut self) {
-        let mut log_line = String::new();
-        for (name, device) in self.state.measurements.iter() {
-            if let Some(value) = device.prev_value() {
-                log_line.push_str(&format!("{}:{},", name, value));
-            } else {
-                log_line.push_str(&format!("{}:none,", name));
+    /// Applies the changes of the goal timeline that are due.
+    fn apply_goal_timeline(&mut self) {
+        if let AptoMode::Profile(_) = self.state.mode {
+            return;
+        }
+        let (Some(timeline), Some(run)) =
+            (self.configurations.timeline.as_mut(), self.run.as_ref())
+        else {
+            return;
+        };
+        let due = timeline.due(
+            run.started.elapsed(),
+            self.state.window.index(),
+            run.iteration,
+        );
+        for change in due {
+            let goal = change.apply_to(&self.configurations.goal);
+            self.change_goal(goal);
+        }
+    }
+
+    /// Answers the requests that reached the control server since the last
+    /// iteration.
+    fn handle_control_requests(&mut self) {
+        let Some(control) = self.control.take() else {
+            return;
+        };
+        for (request, reply) in control.pending() {
+            info!(
+                "Control request {:?} (instance {})",
+                request, self.configurations.instance_id
+            );
+            let _ = reply.send(self.control_reply(request));
+        }
+        self.control = Some(control);
+    }
+
+    fn control_reply(&mut self, request: ControlRequest) -> ControlReply {
+        let knobs = |settings: &HashMap<String, u64>| -> BTreeMap<String, u64> {
+            settings
+                .iter()
+                .filter(|(name, _)| name.as_str() != "id")
+                .map(|(name, &value)| (name.clone(), value))
+                .collect()
+        };
+        let profiling = matches!(self.state.mode, AptoMode::Profile(_));
+        match request {
+            ControlRequest::Goal => ControlReply::Goal(self.configurations.goal.clone()),
+            ControlRequest::Schedule => ControlReply::Schedule(ScheduleState {
+                lower: knobs(
+                    self.state
+                        .active_model
+                        .get_knob_settings(self.state.sched.lower as usize),
+                ),
+                upper: knobs(
+                    self.state
+                        .active_model
+                        .get_knob_settings(self.state.sched.upper as usize),
+                ),
+                lower_share: self.state.sched.lower_share,
+            }),
+            ControlRequest::Configuration => ControlReply::Configuration(ConfigurationState {
+                mode: match self.state.mode {
+                    AptoMode::Profile(_) => "profile",
+                    AptoMode::Adaptive => "adaptive",
+                    AptoMode::NonAdaptive => "non_adaptive",
+                }
+                .to_string(),
+                id: self
+                    .state
+                    .active_model
+                    .find_id(&self.configurations.knobs)
+                    .and_then(|idx| self.state.active_model.config_id(idx)),
+                knobs: self
+                    .configurations
+                    .knobs
+                    .iter()
+                    .map(|(name, knob)| (name.clone(), knob.get()))
+                    .collect(),
+            }),
+            ControlRequest::Window => ControlReply::Window(
+                self.state
+                    .measurements
+                    .iter()
+                    .map(|(name, device)| (name.clone(), device.aggregate()))
+                    .filter(|(_, value)| value.is_finite())
+                    .collect(),
+            ),
+            ControlRequest::Controller => {
+                let controller = &self.state.controller;
+                ControlReply::Controller(ControllerState {
+                    target: self.configurations.goal.target,
+                    xup: controller.sched_xup,
+                    workload: controller.kf.x_hat,
+                    error: controller.xs.e,
+                    error_derivative: controller.xs.ed,
+                    pole: controller.xs.p1,
+                    error_multiplier: controller.xs.get_multiplier(),
+                })
+            }
+            _ if profiling => ControlReply::Error("instance is profiling".to_string()),
+            ControlRequest::ChangeGoal { goal } => {
+                match self
+                    .configurations
+                    .check_goal_measures(&goal)
+                    .and_then(|_| self.try_change_goal(goal))
+                {
+                    Ok(_) => ControlReply::Done,
+                    Err(e) => ControlReply::Error(e.to_string()),
+                }
+            }
+            ControlRequest::Retarget { target } => {
+                let goal = Goal {
+                    target,
+                    ..self.configurations.goal.clone()
+                };
+                self.change_goal(goal);
+                ControlReply::Done
+            }
+            ControlRequest::Freeze => {
+                self.freeze_adaptation();
+                ControlReply::Done
+            }
+            ControlRequest::Unfreeze => {
+                self.unfreeze_adaptation();
+                ControlReply::Done
+            }
+            ControlRequest::SetControllerGain { value } => {
+                self.set_controller_gain(value);
+                ControlReply::Done
+            }
+            ControlRequest::SetErrorMultiplier { value } => {
+                self.set_error_multiplier(value);
+                ControlReply::Done
+            }
+            ControlRequest::SetDerivativeMultiplier { value } => {
+                self.set_derivative_multiplier(value);
+                ControlReply::Done
             }
         }
+    }
 
-        for (name, knob) in self.configurations.knobs.iter() {
-            log_line.push_str(&format!("{}:{},", name, knob.get()));
+    fn log_state(&mut self, iteration: u64, current_config: u64) {
+        if self.configurations.log_state {
+            let mut log_line = String::new();
+            for (name, device) in self.state.measurements.iter() {
+                if let Some(value) = device.prev_value() {
+                    log_line.push_str(&format!("{}:{},", name, value));
+                } else {
+                    log_line.push_str(&format!("{}:none,", name));
+                }
+            }
+
+            for (name, knob) in self.configurations.knobs.iter() {
+                log_line.push_str(&format!("{}:{},", name, knob.get()));
+            }
+            log_line.remove(log_line.len() - 1);
+            info!("instance:{},{}", self.configurations.instance_id, log_line);
         }
-        log_line.remove(log_line.len() - 1);
-        info!("instance:{},{}", self.configurations.instance_id, log_line);
 
         match self.state.mode {
-            AptoMode::Adaptive | AptoMode::NonAdaptive => self.write_to_binary_files(),
+            AptoMode::Adaptive | AptoMode::NonAdaptive => {
+                self.record_telemetry(iteration, current_config)
+            }
             _ => (),
         };
     }
 
-    fn write_to_binary_files(&mut self) {
-        let mut write = |name, value: f64| {
-            let writer = self.outfiles.entry(name).or_insert_with(|| {
-                let newfile = OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(format!("{}.{}", name, self.configurations.instance_id))
-                    .unwrap();
-                BufWriter::new(newfile)
-            });
-            let _ = writer.write(&value.to_ne_bytes()).unwrap();
+    fn record_telemetry(&mut self, iteration: u64, current_config: u64) {
+        let Some(telemetry) = self.configurations.telemetry.as_mut() else {
+            return;
         };
-
-        let relevant_measures = [
-            "appLatency",
-            "numCores",
-            "powerConsumption",
-            "err0",
-            "err1",
-            "perf0",
-            "perf1",
-            "harmonicMeanPerf",
-        ];
-        for name in relevant_measures {
-            let _ = self
-                .state
+        let measures = telemetry.measures.iter().map(|name| {
+            self.state
                 .measurements
                 .get(name)
                 .and_then(|m| m.prev_value())
-                .map(|v| write(name, v));
+                .unwrap_or(f64::NAN)
+        });
+        let knobs = telemetry.knobs.iter().map(|name| {
+            self.configurations
+                .knobs
+                .get(name)
+                .map(|knob| knob.get() as f64)
+                .unwrap_or(f64::NAN)
+        });
+        let values: Vec<f64> = measures.chain(knobs).collect();
+        telemetry.write(
+            self.configurations.instance_id,
+            iteration,
+            self.state.window.index(),
+            self.state.active_model.config_id(current_config as usize),
+            &values,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configurations::{ConfigurationsBuilder, LearningPolicy};
+    use crate::derived::DerivedMeasure;
+    use crate::knobs::{ApplicationKnob, SharedKnob, SyncApplicationKnob};
+    use crate::profile::{KnobTable, MeasureTable, ProfileError};
+    use crate::profile_file::ProfileFile;
+    use crate::testing::{
+        builder, column, env_lock, goal, profile, profiling_tables, step, tables,
+    };
+    use crate::{Aggregation, Apto, AptoConfig, OperatingMode, SamplingPlan, Tunable};
+    use itertools::Itertools;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Instance `instance_id` with `step` at `initial` out of 1, 2 and 4,
+    /// where 2 is dominated by mixing 1 and 4.
+    fn dominated_instance(
+        instance_id: usize,
+        initial: u64,
+        prune: bool,
+    ) -> Result<Apto, ProfileError> {
+        let step: Rc<dyn Tunable<u64>> = Rc::new(ApplicationKnob::new(
+            "step".to_string(),
+            vec![1, 2, 4],
+            initial,
+            None,
+        ));
+        let knob_table = KnobTable::from_rows(
+            vec!["id".to_string(), "step".to_string()],
+            vec![vec![0, 1], vec![1, 2], vec![2, 4]],
+        )
+        .unwrap();
+        let measure_table = MeasureTable::from_rows(
+            ["id", "quality", "operations"].map(String::from).to_vec(),
+            vec![
+                vec![0.0, 1.0, 400.0],
+                vec![1.0, 0.5, 150.0],
+                vec![2.0, 0.25, 100.0],
+            ],
+        )
+        .unwrap();
+        let config = builder(&step)
+            .instance_id(instance_id)
+            .profile(measure_table, knob_table)
+            .prune_dominated(prune)
+            .build()
+            .unwrap();
+        Apto::try_new(config)
+    }
+
+    #[test]
+    fn drive_instance_step_by_step() {
+        let step = step();
+        let config = builder(&step)
+            .mode(OperatingMode::NonAdaptive)
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+        assert!(apto.start().is_empty());
+        for iteration in 0..5 {
+            apto.record("operations", 100.0 * iteration as f64);
+            assert!(apto.end_iteration().is_empty());
         }
+        apto.stop();
+        assert_eq!(step.get(), 1);
+
+        let output_dir = "/tmp/apto_step_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step)
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .mode(OperatingMode::Profile {
+                iterations: 3,
+                plan: SamplingPlan::Full,
+            })
+            .output_dir(output_dir)
+            .build()
+            .unwrap();
+        let mut applied = Vec::new();
+        let profiled = profile(config, |apto, run| {
+            assert_eq!(step.get(), run.knobs[0].1);
+            applied.push(step.get());
+            apto.record("quality", 1.0);
+            apto.record("operations", 100.0);
+        });
+        applied.dedup();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(profiled.profile.len(), 2);
+    }
 
-        let knobs = ["utilizedCoreFrequency", "uncoreFrequency", "hyperthreading"];
-        for name in knobs {
-            let _ = self
-                .state
-                .measurements
-                .get(name)
-                .and_then(|m| m.prev_value())
-                .map(|v| write(name, v as f64));
+    #[test]
+    fn drive_shared_instance_from_threads() {
+        let applied = Arc::new(AtomicU64::new(0));
+        let step: SharedKnob<u64> = {
+            let applied = applied.clone();
+            Arc::new(SyncApplicationKnob::new_sync(
+                "step".to_string(),
+                vec![1, 4],
+                1,
+                Some(Box::new(move |_, new| applied.store(new, Ordering::SeqCst))),
+            ))
+        };
+
+        let builder = {
+            let step = step.clone();
+            std::thread::spawn(move || {
+                let (measure_table, knob_table) = tables();
+                let config = AptoConfig::shared_builder()
+                    .profile(measure_table, knob_table)
+                    .knob(step)
+                    .goal(goal())
+                    .window_size(20)
+                    .mode(OperatingMode::NonAdaptive)
+                    .env_overrides(false)
+                    .log_state(false)
+                    .build()
+                    .unwrap();
+                Apto::try_new(config).unwrap()
+            })
+        };
+        let apto = Arc::new(Mutex::new(builder.join().unwrap()));
+
+        let worker = {
+            let apto = apto.clone();
+            let step = step.clone();
+            std::thread::spawn(move || {
+                apto.lock().unwrap().measure("operations", 100.0);
+                step.set(4);
+            })
+        };
+        worker.join().unwrap();
+        apto.lock().unwrap().freeze_adaptation();
+        assert_eq!(step.get(), 4);
+        assert_eq!(applied.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn prune_dominated_on_request() {
+        let apto = dominated_instance(0, 2, false).unwrap();
+        assert_eq!(apto.state.active_model.configs.len(), 3);
+        let apto = dominated_instance(0, 1, true).unwrap();
+        assert_eq!(apto.state.active_model.configs.len(), 2);
+        assert!(matches!(
+            dominated_instance(0, 2, true),
+            Err(ProfileError::UnknownConfiguration(knobs)) if knobs == [("step".to_string(), 2)]
+        ));
+
+        // Picks single configurations, which need not be on the frontier.
+        // The controller only reads its scheduler type from the environment
+        let _env = env_lock();
+        std::env::set_var("LEARNING_BASED_41", "1");
+        std::env::set_var("CONF_TYPE_41", "single");
+        let apto = dominated_instance(41, 2, true);
+        std::env::remove_var("LEARNING_BASED_41");
+        std::env::remove_var("CONF_TYPE_41");
+        assert_eq!(apto.unwrap().state.active_model.configs.len(), 3);
+    }
+
+    /// Profiles both configurations of `step` into `output_dir`, or what
+    /// `plan` selects, and returns the ids of the runs.
+    fn profile_ids(output_dir: &str, plan: SamplingPlan) -> Vec<u64> {
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .mode(OperatingMode::Profile {
+                iterations: 1,
+                plan,
+            })
+            .output_dir(output_dir)
+            .build()
+            .unwrap();
+        let mut ids = Vec::new();
+        profile(config, |apto, run| {
+            ids.push(run.id);
+            apto.record("quality", 1.0);
+            apto.record("operations", 100.0);
+        });
+        ids
+    }
+
+    #[test]
+    fn resume_interrupted_profiling() {
+        let output_dir = "/tmp/apto_resume_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        assert_eq!(profile_ids(output_dir, SamplingPlan::Full), [0, 1]);
+
+        // Interrupted after the first configuration, by a version that did
+        // not record the plan in the knob table
+        let measure_table = format!("{}/measuretable", output_dir);
+        let content = std::fs::read_to_string(&measure_table).unwrap();
+        std::fs::write(&measure_table, content.lines().take(2).join("\n") + "\n").unwrap();
+        let knob_table = format!("{}/knobtable", output_dir);
+        let content = std::fs::read_to_string(&knob_table).unwrap();
+        std::fs::write(&knob_table, content.lines().skip(1).join("\n") + "\n").unwrap();
+
+        assert_eq!(profile_ids(output_dir, SamplingPlan::Full), [1]);
+        let profiled = MeasureTable::try_new(&measure_table).unwrap();
+        assert_eq!(profiled.profile.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot resume profiling")]
+    fn refuse_to_resume_other_plan() {
+        let output_dir = "/tmp/apto_resume_other_plan";
+        let _ = std::fs::remove_dir_all(output_dir);
+        profile_ids(output_dir, SamplingPlan::Full);
+        profile_ids(output_dir, SamplingPlan::Random { size: 1, seed: 0 });
+    }
+
+    #[test]
+    fn profile_repeated_runs_after_warmup() {
+        let output_dir = "/tmp/apto_repeated_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .mode(OperatingMode::Profile {
+                iterations: 2,
+                plan: SamplingPlan::Full,
+            })
+            .output_dir(output_dir)
+            .profile_repetitions(2)
+            .profile_warmup(1)
+            .build()
+            .unwrap();
+        let mut iteration = 0;
+        let profiled = profile(config, |apto, run| {
+            // The first iteration after every reconfiguration is far off
+            let operations = match iteration % 3 {
+                0 => 1e6,
+                _ => 100.0 * (run.repetition + 1) as f64,
+            };
+            iteration += 1;
+            apto.record("quality", 1.0);
+            apto.record("operations", operations);
+        });
+        assert_eq!(profiled.profile.len(), 2);
+        let operations = column(&profiled, "operations");
+        assert!(profiled
+            .profile
+            .iter()
+            .all(|row| (row[operations] - 150.0).abs() < 1e-9));
+
+        let stats = MeasureTable::try_new(format!("{}/measuretable.stats", output_dir)).unwrap();
+        let stddev = column(&stats, "operations_stddev");
+        assert!(stats
+            .profile
+            .iter()
+            .all(|row| (row[stddev] - 50.0 * 2f64.sqrt()).abs() < 1e-9));
+    }
+
+    /// Instance with `step` at `initial` out of 1, 2 and 4, where 2 was not
+    /// profiled.
+    fn predicted_instance(
+        initial: u64,
+        configure: impl FnOnce(ConfigurationsBuilder<u64>) -> ConfigurationsBuilder<u64>,
+    ) -> Apto {
+        let step: Rc<dyn Tunable<u64>> = Rc::new(ApplicationKnob::new(
+            "step".to_string(),
+            vec![1, 2, 4],
+            initial,
+            None,
+        ));
+        let config = configure(builder(&step).predict_missing(true))
+            .build()
+            .unwrap();
+        Apto::try_new(config).unwrap()
+    }
+
+    #[test]
+    fn control_with_predicted_configurations() {
+        let apto = predicted_instance(1, |builder| builder);
+        let model = &apto.state.active_model;
+        assert_eq!(model.configs.len(), 3);
+        let predicted: Vec<u64> = (0..model.configs.len())
+            .filter(|&idx| model.is_predicted(idx))
+            .map(|idx| model.get_knob_settings(idx)["step"])
+            .collect();
+        assert_eq!(predicted, [2]);
+
+        let apto = predicted_instance(1, |builder| builder.exclude_predicted(true));
+        assert_eq!(apto.state.active_model.configs.len(), 2);
+
+        // The first window replaces the prediction instead of being averaged
+        // into it
+        let mut apto = predicted_instance(2, |builder| {
+            let (measure_table, knob_table) = profiling_tables();
+            builder
+                .profile(measure_table, knob_table)
+                .window_size(2)
+                .online_learning(LearningPolicy::new(0.1))
+        });
+        apto.start();
+        for _ in 0..2 {
+            apto.record("quality", 0.3);
+            apto.record("operations", 123.0);
+            apto.end_iteration();
+        }
+        apto.stop();
+        let model = &apto.state.active_model;
+        let idx = (0..model.configs.len())
+            .find(|&idx| model.get_knob_settings(idx)["step"] == 2)
+            .unwrap();
+        assert!(!model.is_predicted(idx));
+        let measure_table = &apto.configurations.measure_table;
+        let observed = [("operations", 123.0), ("quality", 0.3)];
+        for (name, value) in observed {
+            let col = column(measure_table, name);
+            assert_eq!(model.configs[idx].0[col], value);
+        }
+    }
+
+    #[test]
+    fn learn_from_windows() {
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .online_learning(LearningPolicy::new(0.5).period(2))
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+        let quality = column(&apto.configurations.measure_table, "quality");
+        let operations = column(&apto.configurations.measure_table, "operations");
+        let profiled = apto.state.controller.ctx.model.clone();
+
+        // The first window refines the configuration the knobs start at
+        apto.start();
+        for _ in 0..2 {
+            apto.record("quality", 0.6);
+            apto.record("operations", 300.0);
+            apto.end_iteration();
         }
+        let refined = &apto.state.active_model.configs[0].0;
+        assert_eq!((refined[quality], refined[operations]), (0.3, 150.0));
+        assert_eq!(apto.configurations.measure_table.profile[0][quality], 0.3);
+        // The controller is handed the refined model every second window
+        assert_eq!(apto.state.controller.ctx.model, profiled);
+        for _ in 0..2 {
+            apto.record("quality", 0.6);
+            apto.record("operations", 300.0);
+            apto.end_iteration();
+        }
+        apto.stop();
+        assert_eq!(
+            apto.state.controller.ctx.model,
+            apto.state.active_model.measure_values()
+        );
+
+        let path = "/tmp/apto_learned_profile.yaml";
+        apto.export_profile(path).unwrap();
+        let exported = ProfileFile::load(path).unwrap().measure_table();
+        assert!((exported.profile[0][quality] - 0.45).abs() < 1e-9);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn derive_measures_per_window() {
+        let knob_table =
+            KnobTable::from_rows(vec!["id".to_string(), "step".to_string()], vec![vec![0, 1]])
+                .unwrap();
+        let (profiled, _) = profiling_tables();
+        let measure_table =
+            MeasureTable::from_rows(profiled.names, vec![profiled.profile[0].clone()]).unwrap();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .derived_measure(DerivedMeasure::new("quality", "progress / 400").unwrap())
+            .online_learning(LearningPolicy::new(1.0).period(1))
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+        let quality = column(&apto.configurations.measure_table, "quality");
+
+        // Every window learns the quality derived from its own progress only.
+        // The first window ends when the second iteration begins.
+        apto.start();
+        for (iterations, progress) in [(1, 100.0), (2, 300.0), (2, 200.0)] {
+            for _ in 0..iterations {
+                apto.record("progress", progress);
+                apto.record("operations", 400.0);
+                apto.end_iteration();
+            }
+            let learned = apto.state.active_model.configs[0].0[quality];
+            assert_eq!(learned, progress / 400.0);
+            assert_eq!(
+                apto.state.measurements["quality"].aggregate(),
+                progress / 400.0
+            );
+        }
+        apto.stop();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn count_rejected_values_per_window() {
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .mode(OperatingMode::NonAdaptive)
+            .window_size(2)
+            .outlier_policy("operations", "range:0:1000".parse().unwrap())
+            .aggregation("operationsRejected", Aggregation::Count)
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+
+        // Nothing resets the windows of a non-adaptive instance, so every
+        // window that ended has added one count
+        apto.start();
+        for windows in 1..=3 {
+            for _ in 0..2 {
+                apto.record("quality", 0.5);
+                apto.record("operations", 1e6);
+                apto.end_iteration();
+            }
+            assert_eq!(
+                apto.state.measurements["operationsRejected"].aggregate(),
+                windows as f64
+            );
+        }
+        apto.stop();
+    }
+}
@@ -0,0 +1,302 @@
+use crate::aggregation::percentile;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Recent values of a measure its percentile bounds and MAD are taken from.
+const HISTORY: usize = 256;
+/// Recent values needed before the bounds and the MAD are trusted.
+const MIN_HISTORY: usize = 10;
+/// Scales the MAD to the standard deviation of normally distributed values.
+const MAD_SCALE: f64 = 1.4826;
+
+/// What happens to values of a measure that lie far from the others before
+/// they are aggregated. Written as `winsorize:<lower>:<upper>` with two
+/// percentiles (e.g. `winsorize:1:99`), `mad:<threshold>` (e.g. `mad:3.5`) or
+/// `range:<min>:<max>`, where an empty bound is unbounded (e.g. `range:0:`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlierPolicy {
+    /// Clamps values to the percentiles `lower` and `upper` of the recent
+    /// values.
+    Winsorize { lower: f64, upper: f64 },
+    /// Rejects values more than `threshold` times the median absolute
+    /// deviation, scaled to a standard deviation, away from the median of the
+    /// recent values.
+    Mad { threshold: f64 },
+    /// Rejects values outside `[min, max]`.
+    Range { min: f64, max: f64 },
+}
+
+/// Name of the measure that counts the values of `measure` its outlier
+/// policy rejected or clamped.
+pub(crate) fn rejected_measure(measure: &str) -> String {
+    format!("{}Rejected", measure)
+}
+
+/// Applies an `OutlierPolicy` to the values of one measure as they arrive.
+pub(crate) struct OutlierFilter {
+    policy: OutlierPolicy,
+    recent: VecDeque<f64>,
+    /// Values outside are clamped or rejected. Taken from the recent values
+    /// once per window.
+    bounds: Option<(f64, f64)>,
+}
+
+impl OutlierFilter {
+    pub fn new(policy: OutlierPolicy) -> OutlierFilter {
+        OutlierFilter {
+            policy,
+            recent: VecDeque::with_capacity(HISTORY),
+            bounds: None,
+        }
+    }
+
+    /// The value to aggregate in place of `value`, or `None` if it is
+    /// rejected. NaN is always rejected. Until enough values were seen,
+    /// winsorization and MAD-based rejection keep every value.
+    pub fn filter(&mut self, value: f64) -> Option<f64> {
+        if value.is_nan() {
+            return None;
+        }
+        let kept = match (self.policy, self.bounds()) {
+            (OutlierPolicy::Range { min, max }, _) => {
+                return (min..=max).contains(&value).then_some(value)
+            }
+            (_, None) => Some(value),
+            (OutlierPolicy::Winsorize { .. }, Some((low, high))) => Some(value.clamp(low, high)),
+            (OutlierPolicy::Mad { .. }, Some((low, high))) => {
+                (low..=high).contains(&value).then_some(value)
+            }
+        };
+        if self.recent.len() == HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+        kept
+    }
+
+    /// Takes the bounds from the recent values again with the next value.
+    pub fn new_window(&mut self) {
+        self.bounds = None;
+    }
+
+    /// Forgets the recent values, e.g. when another configuration is run.
+    pub fn clear(&mut self) {
+        self.recent.clear();
+        self.bounds = None;
+    }
+
+    /// The bounds of the window, `None` until enough values were seen and
+    /// for ranges, which have fixed bounds.
+    fn bounds(&mut self) -> Option<(f64, f64)> {
+        if self.bounds.is_some() || self.recent.len() < MIN_HISTORY {
+            return self.bounds;
+        }
+        let recent = sorted(self.recent.iter().copied());
+        self.bounds = match self.policy {
+            OutlierPolicy::Range { .. } => None,
+            OutlierPolicy::Winsorize { lower, upper } => {
+                Some((percentile(&recent, lower), percentile(&recent, upper)))
+            }
+            OutlierPolicy::Mad { threshold } => {
+                let median = percentile(&recent, 50.0);
+                let deviations = sorted(recent.iter().map(|recent| (recent - median).abs()));
+                let limit = threshold * MAD_SCALE * percentile(&deviations, 50.0);
+                // Without any spread, e.g. for repeated integers, nothing
+                // stands out
+                if limit == 0.0 {
+                    Some((f64::NEG_INFINITY, f64::INFINITY))
+                } else {
+                    Some((median - limit, median + limit))
+                }
+            }
+        };
+        self.bounds
+    }
+}
+
+fn sorted<I: Iterator<Item = f64>>(values: I) -> Vec<f64> {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+impl Display for OutlierPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            OutlierPolicy::Winsorize { lower, upper } => write!(f, "winsorize:{}:{}", lower, upper),
+            OutlierPolicy::Mad { threshold } => write!(f, "mad:{}", threshold),
+            OutlierPolicy::Range { min, max } => write!(f, "range:{}:{}", min, max),
+        }
+    }
+}
+
+impl FromStr for OutlierPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = |reason: &str| format!("{} in outlier policy '{}'", reason, s);
+        let parse = |value: &str, unbounded: f64| match value.trim() {
+            "" => Ok(unbounded),
+            value => value
+                .parse::<f64>()
+                .map_err(|_| invalid(&format!("invalid number '{}'", value))),
+        };
+        let (kind, args) = s.split_once(':').unwrap_or((s, ""));
+        match (kind, args.split_once(':')) {
+            ("winsorize", Some((lower, upper))) => {
+                let (lower, upper) = (parse(lower, 0.0)?, parse(upper, 100.0)?);
+                if !(0.0 <= lower && lower < upper && upper <= 100.0) {
+                    return Err(invalid("percentiles must satisfy 0 <= lower < upper <= 100"));
+                }
+                Ok(OutlierPolicy::Winsorize { lower, upper })
+            }
+            ("mad", None) => match parse(args, f64::NAN)? {
+                threshold if threshold > 0.0 => Ok(OutlierPolicy::Mad { threshold }),
+                _ => Err(invalid("MAD threshold must be positive")),
+            },
+            ("range", Some((min, max))) => {
+                let (min, max) = (parse(min, f64::NEG_INFINITY)?, parse(max, f64::INFINITY)?);
+                if min.is_nan() || max.is_nan() || min > max {
+                    return Err(invalid("minimum must not exceed maximum"));
+                }
+                Ok(OutlierPolicy::Range { min, max })
+            }
+            _ => Err(format!(
+                "unknown outlier policy '{}', expected winsorize:<lower>:<upper>, mad:<threshold> or range:<min>:<max>",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutlierFilter, OutlierPolicy};
+    use crate::profile::ProfileError;
+    use crate::testing::{builder, column, env_lock, profile, profiling_tables, step};
+    use crate::{Apto, OperatingMode, SamplingPlan};
+
+    fn filter(policy: &str, values: &[f64]) -> Vec<Option<f64>> {
+        let mut filter = OutlierFilter::new(policy.parse().unwrap());
+        values.iter().map(|value| filter.filter(*value)).collect()
+    }
+
+    #[test]
+    fn filter_outliers() {
+        let mut latencies: Vec<f64> = (1..=20).map(|idx| 1.0 + (idx % 5) as f64 * 0.1).collect();
+        latencies.push(50.0);
+
+        let winsorized = filter("winsorize:5:95", &latencies);
+        assert_eq!(
+            winsorized[..20],
+            latencies[..20].iter().map(|v| Some(*v)).collect::<Vec<_>>()
+        );
+        assert_eq!(winsorized[20], Some(latencies[3]));
+
+        let rejected = filter("mad:3.5", &latencies);
+        assert!(rejected[..20].iter().all(Option::is_some));
+        assert_eq!(rejected[20], None);
+        // Repeated values have no spread to judge by
+        let mut flat = vec![2.0; 12];
+        flat.push(3.0);
+        assert_eq!(filter("mad:3", &flat)[12], Some(3.0));
+
+        assert_eq!(
+            filter("range:0:", &[-1.0, 0.0, 1e9, f64::NAN]),
+            [None, Some(0.0), Some(1e9), None]
+        );
+
+        // The bounds are taken once per window
+        let mut winsorize = OutlierFilter::new("winsorize:0:100".parse().unwrap());
+        for value in 0..10 {
+            winsorize.filter(value as f64);
+        }
+        assert_eq!(winsorize.filter(20.0), Some(9.0));
+        assert_eq!(winsorize.filter(20.0), Some(9.0));
+        winsorize.new_window();
+        assert_eq!(winsorize.filter(30.0), Some(20.0));
+    }
+
+    #[test]
+    fn parse_outlier_policies() {
+        assert_eq!(
+            "winsorize:1:99".parse(),
+            Ok(OutlierPolicy::Winsorize {
+                lower: 1.0,
+                upper: 99.0
+            })
+        );
+        assert_eq!(
+            "range::0.5".parse(),
+            Ok(OutlierPolicy::Range {
+                min: f64::NEG_INFINITY,
+                max: 0.5
+            })
+        );
+        let mad = OutlierPolicy::Mad { threshold: 3.5 };
+        assert_eq!(mad.to_string().parse(), Ok(mad));
+        let range: OutlierPolicy = "range:0:".parse().unwrap();
+        assert_eq!(range.to_string().parse(), Ok(range));
+        assert!("winsorize:99:1".parse::<OutlierPolicy>().is_err());
+        assert!("mad:0".parse::<OutlierPolicy>().is_err());
+        assert!("mad".parse::<OutlierPolicy>().is_err());
+        assert!("range:2:1".parse::<OutlierPolicy>().is_err());
+        assert!("trim:5".parse::<OutlierPolicy>().is_err());
+    }
+
+    #[test]
+    fn profile_rejected_values() {
+        let output_dir = "/tmp/apto_outlier_profile";
+        let _ = std::fs::remove_dir_all(output_dir);
+        let (measure_table, knob_table) = profiling_tables();
+        let config = builder(&step())
+            .profile(measure_table, knob_table)
+            .window_size(2)
+            .mode(OperatingMode::Profile {
+                iterations: 4,
+                plan: SamplingPlan::Full,
+            })
+            .output_dir(output_dir)
+            .outlier_policy(
+                "operations",
+                OutlierPolicy::Range {
+                    min: 0.0,
+                    max: 1000.0,
+                },
+            )
+            .build()
+            .unwrap();
+        let mut iteration = 0;
+        let profiled = profile(config, |apto, _| {
+            iteration += 1;
+            apto.record("quality", 1.0);
+            apto.record("operations", if iteration % 4 == 0 { 1e6 } else { 100.0 });
+        });
+        assert_eq!(profiled.profile.len(), 2);
+        let operations = column(&profiled, "operations");
+        let rejected = column(&profiled, "operationsRejected");
+        for row in &profiled.profile {
+            assert!((row[operations] - 100.0).abs() < 1e-9);
+            assert_eq!(row[rejected], 1.0);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_policies_from_environment() {
+        let _env = env_lock();
+        for policies in ["latency", "latency=trim:5"] {
+            std::env::set_var("OUTLIERS", policies);
+            let config = builder(&step()).env_overrides(true).build().unwrap();
+            assert!(matches!(
+                Apto::try_new(config),
+                Err(ProfileError::InvalidSetting {
+                    setting: "OUTLIERS",
+                    ..
+                })
+            ));
+        }
+        std::env::remove_var("OUTLIERS");
+    }
+}
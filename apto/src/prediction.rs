@@ -0,0 +1,317 @@
+use crate::profile::{KnobTable, MeasureTable};
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// A profile extended with predictions for the knob combinations that were
+/// never measured.
+pub struct PredictedProfile {
+    pub measure_table: MeasureTable,
+    pub knob_table: KnobTable,
+    /// Ids of the rows that were predicted rather than profiled.
+    pub predicted: HashSet<u64>,
+}
+
+/// Predicts the measures of every combination of `knob_domains` that is
+/// missing from the profile. A combination surrounded by profiled grid points
+/// is interpolated multilinearly between them; anything else falls back to a
+/// least-squares linear fit over all profiled configurations. Knobs in the
+/// knob table but not in `knob_domains` keep their profiled values.
+pub fn predict_missing(
+    measure_table: &MeasureTable,
+    knob_table: &KnobTable,
+    knob_domains: &BTreeMap<String, Vec<u64>>,
+) -> PredictedProfile {
+    let mut measure_table = measure_table.clone();
+    let mut knob_table = knob_table.clone();
+    let mut predicted = HashSet::new();
+
+    let (Some(mt_id_idx), true) = (
+        measure_table.names.iter().position(|name| name == "id"),
+        knob_table.names.iter().any(|name| name == "id"),
+    ) else {
+        return PredictedProfile {
+            measure_table,
+            knob_table,
+            predicted,
+        };
+    };
+    let knob_names: Vec<String> = knob_table
+        .names
+        .iter()
+        .filter(|&name| name != "id")
+        .cloned()
+        .collect();
+
+    let settings: HashMap<u64, Vec<u64>> = knob_table
+        .configurations
+        .iter()
+        .map(|config| {
+            let values = knob_names.iter().map(|name| config[name]).collect();
+            (config["id"], values)
+        })
+        .collect();
+    let profiled: HashMap<Vec<u64>, &Vec<f64>> = measure_table
+        .profile
+        .iter()
+        .filter_map(|row| Some((settings.get(&(row[mt_id_idx] as u64))?.clone(), row)))
+        .collect();
+    if profiled.is_empty() {
+        return PredictedProfile {
+            measure_table,
+            knob_table,
+            predicted,
+        };
+    }
+
+    // The grid is spanned by the values each knob was profiled with
+    let grid: Vec<BTreeSet<u64>> = (0..knob_names.len())
+        .map(|dim| profiled.keys().map(|values| values[dim]).collect())
+        .collect();
+    let domains: Vec<Vec<u64>> = knob_names
+        .iter()
+        .zip(grid.iter())
+        .map(|(name, profiled_values)| match knob_domains.get(name) {
+            Some(domain) => domain.clone(),
+            None => profiled_values.iter().copied().collect(),
+        })
+        .collect();
+
+    let regression = LinearFit::new(&profiled, mt_id_idx);
+    let mut next_id = settings.keys().max().map(|id| id + 1).unwrap_or(0);
+    let mut new_rows = Vec::new();
+    for combination in domains
+        .iter()
+        .map(|domain| domain.iter().copied())
+        .multi_cartesian_product()
+    {
+        if profiled.contains_key(&combination) {
+            continue;
+        }
+        let mut row = interpolate(&profiled, &grid, &combination)
+            .unwrap_or_else(|| regression.predict(&combination));
+        row[mt_id_idx] = next_id as f64;
+
+        knob_table.configurations.push(
+            std::iter::once(("id".to_string(), next_id))
+                .chain(knob_names.iter().cloned().zip(combination))
+                .collect(),
+        );
+        new_rows.push(row);
+        predicted.insert(next_id);
+        next_id += 1;
+    }
+    measure_table.profile.extend(new_rows);
+
+    PredictedProfile {
+        measure_table,
+        knob_table,
+        predicted,
+    }
+}
+
+/// Multilinear interpolation between the profiled grid points enclosing
+/// `point`. Fails if `point` lies outside the profiled range of a knob or if
+/// any of the enclosing points was not profiled.
+fn interpolate(
+    profiled: &HashMap<Vec<u64>, &Vec<f64>>,
+    grid: &[BTreeSet<u64>],
+    point: &[u64],
+) -> Option<Vec<f64>> {
+    // For every knob: (lower, upper, weight of upper)
+    let brackets: Vec<(u64, u64, f64)> = grid
+        .iter()
+        .zip(point.iter())
+        .map(|(values, &value)| {
+            let lower = *values.range(..=value).next_back()?;
+            let upper = *values.range(value..).next()?;
+            let weight = if upper == lower {
+                0.0
+            } else {
+                (value - lower) as f64 / (upper - lower) as f64
+            };
+            Some((lower, upper, weight))
+        })
+        .collect::<Option<_>>()?;
+
+    let nr_measures = profiled.values().next()?.len();
+    let mut row = vec![0.0; nr_measures];
+    for corner in 0..(1usize << brackets.len()) {
+        let mut weight = 1.0;
+        let mut corner_point = Vec::with_capacity(brackets.len());
+        for (dim, &(lower, upper, upper_weight)) in brackets.iter().enumerate() {
+            if corner & (1 << dim) == 0 {
+                weight *= 1.0 - upper_weight;
+                corner_point.push(lower);
+            } else {
+                weight *= upper_weight;
+                corner_point.push(upper);
+            }
+        }
+        if weight == 0.0 {
+            continue;
+        }
+        let measures = profiled.get(&corner_point)?;
+        for (value, measure) in row.iter_mut().zip(measures.iter()) {
+            *value += weight * measure;
+        }
+    }
+    Some(row)
+}
+
+/// Ordinary least squares fit of every measure against the knob values.
+struct LinearFit {
+    // One row of coefficients (intercept first) per measure
+    coefficients: Vec<Vec<f64>>,
+}
+
+impl LinearFit {
+    fn new(profiled: &HashMap<Vec<u64>, &Vec<f64>>, id_idx: usize) -> LinearFit {
+        let nr_features = profiled.keys().next().map_or(0, |knobs| knobs.len()) + 1;
+        let nr_measures = profiled.values().next().map_or(0, |row| row.len());
+        let features = |knobs: &[u64]| -> Vec<f64> {
+            std::iter::once(1.0)
+                .chain(knobs.iter().map(|&v| v as f64))
+                .collect()
+        };
+
+        // Normal equations X^T X b = X^T y, slightly regularized so that
+        // constant knobs or too few samples still give a usable fit.
+        let mut xtx = vec![vec![0.0; nr_features]; nr_features];
+        let mut xty = vec![vec![0.0; nr_measures]; nr_features];
+        for (knobs, row) in profiled.iter() {
+            let x = features(knobs);
+            for i in 0..nr_features {
+                for j in 0..nr_features {
+                    xtx[i][j] += x[i] * x[j];
+                }
+                for (m, value) in row.iter().enumerate() {
+                    xty[i][m] += x[i] * value;
+                }
+            }
+        }
+        for (i, line) in xtx.iter_mut().enumerate().skip(1) {
+            line[i] += 1e-9;
+        }
+
+        let coefficients = (0..nr_measures)
+            .map(|m| {
+                if m == id_idx {
+                    return vec![0.0; nr_features];
+                }
+                let rhs: Vec<f64> = xty.iter().map(|line| line[m]).collect();
+                solve(xtx.clone(), rhs)
+            })
+            .collect();
+        LinearFit { coefficients }
+    }
+
+    fn predict(&self, knobs: &[u64]) -> Vec<f64> {
+        self.coefficients
+            .iter()
+            .map(|coefficients| {
+                coefficients[0]
+                    + coefficients[1..]
+                        .iter()
+                        .zip(knobs.iter())
+                        .map(|(c, &v)| c * v as f64)
+                        .sum::<f64>()
+            })
+            .collect()
+    }
+}
+
+/// Gaussian elimination with partial pivoting. Singular directions get a
+/// zero coefficient.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+            .unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        if a[col][col].abs() < f64::EPSILON {
+            continue;
+        }
+        let pivot_row = a[col].clone();
+        for row in (col + 1)..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot) in a[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        if a[row][row].abs() < f64::EPSILON {
+            continue;
+        }
+        let sum: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::predict_missing;
+    use crate::profile::{ActiveModel, KnobTable, MeasureTable};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn knob_table(rows: &[[u64; 3]]) -> KnobTable {
+        let names: Vec<String> = ["id", "cores", "step"].map(String::from).to_vec();
+        let configurations = rows
+            .iter()
+            .map(|row| names.iter().cloned().zip(row.iter().copied()).collect())
+            .collect();
+        KnobTable {
+            names,
+            configurations,
+        }
+    }
+
+    #[test]
+    fn predict_unprofiled_configurations() {
+        // latency = 10 * cores + step, profiled on the corners of the grid
+        let measure_table = MeasureTable {
+            names: vec!["id".to_string(), "latency".to_string()],
+            profile: vec![
+                vec![0.0, 11.0],
+                vec![1.0, 15.0],
+                vec![2.0, 41.0],
+                vec![3.0, 45.0],
+            ],
+        };
+        let knob_table = knob_table(&[[0, 1, 1], [1, 1, 5], [2, 4, 1], [3, 4, 5]]);
+        let mut domains = BTreeMap::new();
+        domains.insert("cores".to_string(), vec![1, 2, 4, 8]);
+        domains.insert("step".to_string(), vec![1, 5]);
+
+        let prediction = predict_missing(&measure_table, &knob_table, &domains);
+        assert_eq!(prediction.predicted.len(), 4);
+        assert_eq!(prediction.measure_table.profile.len(), 8);
+
+        let mut model = ActiveModel::new(&prediction.measure_table, &prediction.knob_table);
+        model.mark_predicted(prediction.predicted.iter().copied());
+        let latency: HashMap<(u64, u64), (f64, bool)> = (0..model.configs.len())
+            .map(|idx| {
+                let settings = model.get_knob_settings(idx);
+                (
+                    (settings["cores"], settings["step"]),
+                    (model.configs[idx].0[1], model.is_predicted(idx)),
+                )
+            })
+            .collect();
+        // Interpolated within the grid, extrapolated by the linear fit outside
+        for ((cores, step), expected) in [((2, 1), 21.0), ((2, 5), 25.0), ((8, 5), 85.0)] {
+            let (value, predicted) = latency[&(cores, step)];
+            assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+            assert!(predicted);
+        }
+        assert!(!latency[&(4, 5)].1);
+
+        assert_eq!(model.exclude_predicted(), 4);
+        assert_eq!(model.configs.len(), 4);
+    }
+}
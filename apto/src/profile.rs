@@ -1,104 +1,577 @@
 use crate::knobs::Tunable;
-use std::collections::HashMap;
+use crate::profile_file::{ProfileFile, ProfileFormat};
+use crate::validation::ValidationReport;
+use crate::NAME_REGEX;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
 use std::path::Path;
-use std::rc::Rc;
 use std::str::FromStr;
+use OptimizingController::OptimizationType;
 
-fn read_table<P, T>(path: P) -> (Vec<String>, Vec<Vec<T>>)
+#[derive(Debug)]
+pub enum ProfileError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    MissingHeader {
+        path: String,
+    },
+    RaggedRow {
+        path: String,
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    UnparsableCell {
+        path: String,
+        line: usize,
+        column: usize,
+        header: String,
+        value: String,
+    },
+    MissingMeasure {
+        role: &'static str,
+        measure: String,
+        header: Vec<String>,
+    },
+    Inconsistent {
+        measure_table: String,
+        knob_table: String,
+        report: ValidationReport,
+    },
+    Format {
+        path: String,
+        message: String,
+    },
+    ForeignHost {
+        path: String,
+        field: &'static str,
+        recorded: String,
+        current: String,
+    },
+    MissingKnob {
+        knob: String,
+        header: Vec<String>,
+    },
+    HeaderMismatch {
+        path: String,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    MissingSetting(&'static str),
+    InvalidSetting {
+        setting: &'static str,
+        message: String,
+    },
+    /// Knobs that have to be applied after each other.
+    KnobCycle(Vec<String>),
+    Timeline {
+        path: String,
+        message: String,
+    },
+    /// The knobs are set to a configuration that is not in the model.
+    UnknownConfiguration(Vec<(String, u64)>),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ProfileError::Io { path, source } => write!(f, "Could not read {}: {}", path, source),
+            ProfileError::MissingHeader { path } => write!(f, "{} has no header line", path),
+            ProfileError::RaggedRow {
+                path,
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}:{}: expected {} columns as in the header but found {}",
+                path, line, expected, found
+            ),
+            ProfileError::UnparsableCell {
+                path,
+                line,
+                column,
+                header,
+                value,
+            } => write!(
+                f,
+                "{}:{}:{}: could not parse '{}' in column '{}'",
+                path, line, column, value, header
+            ),
+            ProfileError::MissingMeasure {
+                role,
+                measure,
+                header,
+            } => write!(
+                f,
+                "{} measure '{}' not found in measure table header {:?}",
+                role, measure, header
+            ),
+            ProfileError::Inconsistent {
+                measure_table,
+                knob_table,
+                report,
+            } => write!(
+                f,
+                "{} and {} do not describe the same configurations: {}",
+                measure_table, knob_table, report
+            ),
+            ProfileError::Format { path, message } => {
+                write!(f, "{} is not a valid profile: {}", path, message)
+            }
+            ProfileError::ForeignHost {
+                path,
+                field,
+                recorded,
+                current,
+            } => write!(
+                f,
+                "{} was recorded with {} '{}' but this machine has '{}'",
+                path, field, recorded, current
+            ),
+            ProfileError::MissingKnob { knob, header } => {
+                write!(
+                    f,
+                    "knob '{}' not found in knob table header {:?}",
+                    knob, header
+                )
+            }
+            ProfileError::HeaderMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has header {:?} but {:?} was expected",
+                path, found, expected
+            ),
+            ProfileError::MissingSetting(setting) => {
+                write!(f, "no {} was given to the configuration builder", setting)
+            }
+            ProfileError::InvalidSetting { setting, message } => {
+                write!(f, "invalid {}: {}", setting, message)
+            }
+            ProfileError::KnobCycle(knobs) => {
+                write!(f, "knobs {:?} have to be applied after each other", knobs)
+            }
+            ProfileError::Timeline { path, message } => {
+                write!(f, "{} is not a valid goal timeline: {}", path, message)
+            }
+            ProfileError::UnknownConfiguration(knobs) => {
+                write!(
+                    f,
+                    "the knobs are set to {:?}, which the model does not contain",
+                    knobs
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProfileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn read_table<P, T>(path: P) -> Result<(Vec<String>, Vec<Vec<T>>), ProfileError>
 where
     P: AsRef<Path> + Display,
     T: FromStr,
 {
-    let file_content =
-        std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Could not read {}", &path));
-    let mut lines = file_content.trim().lines();
+    let file_content = std::fs::read_to_string(&path).map_err(|source| ProfileError::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    // Line numbers are 1-based and count blank lines so they match an editor.
+    // Lines starting with '#' carry annotations such as the profiling plan.
+    let mut lines = file_content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
     let header: Vec<String> = lines
         .next()
-        .unwrap()
-        .trim()
+        .ok_or_else(|| ProfileError::MissingHeader {
+            path: path.to_string(),
+        })?
+        .1
         .split(',')
-        .map(String::from)
+        .map(|name| String::from(name.trim()))
         .collect();
-    let remainder: Vec<Vec<T>> = lines
-        .map(|line| {
-            line.trim()
-                .split(',')
-                .map(|s| {
-                    s.parse()
-                        .unwrap_or_else(|_| panic!("Could not parse {}.", path))
+
+    let mut remainder: Vec<Vec<T>> = Vec::new();
+    for (line_nr, line) in lines {
+        let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+        if cells.len() != header.len() {
+            return Err(ProfileError::RaggedRow {
+                path: path.to_string(),
+                line: line_nr,
+                expected: header.len(),
+                found: cells.len(),
+            });
+        }
+        let row = cells
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| {
+                cell.parse().map_err(|_| ProfileError::UnparsableCell {
+                    path: path.to_string(),
+                    line: line_nr,
+                    column: column + 1,
+                    header: header[column].clone(),
+                    value: cell.to_string(),
                 })
-                .collect()
-        })
-        .collect();
+            })
+            .collect::<Result<Vec<T>, ProfileError>>()?;
+        remainder.push(row);
+    }
 
-    (header, remainder)
+    Ok((header, remainder))
 }
 
-#[derive(Clone)]
+fn write_table<P, T>(path: P, header: &[String], rows: &[Vec<T>]) -> Result<(), ProfileError>
+where
+    P: AsRef<Path> + Display,
+    T: Display,
+{
+    let mut content = header.join(",");
+    content.push('\n');
+    for row in rows.iter() {
+        content.push_str(&row.iter().map(|value| value.to_string()).join(","));
+        content.push('\n');
+    }
+    write_atomic(path, content.as_bytes())
+}
+
+/// Rejects rows that do not have a cell for every column of the header. Rows
+/// are numbered as lines of the equivalent CSV file.
+fn check_rows<T>(names: &[String], rows: &[Vec<T>]) -> Result<(), ProfileError> {
+    match rows.iter().position(|row| row.len() != names.len()) {
+        Some(idx) => Err(ProfileError::RaggedRow {
+            path: "<memory>".to_string(),
+            line: idx + 2,
+            expected: names.len(),
+            found: rows[idx].len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it, so
+/// `path` never holds a partially written table.
+pub(crate) fn write_atomic<P>(path: P, content: &[u8]) -> Result<(), ProfileError>
+where
+    P: AsRef<Path> + Display,
+{
+    let io_error = |source| ProfileError::Io {
+        path: path.to_string(),
+        source,
+    };
+    let tmp_path = format!("{}.tmp", path);
+    File::create(&tmp_path)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .map_err(io_error)?;
+    std::fs::rename(&tmp_path, path.as_ref()).map_err(io_error)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MeasureTable {
     pub names: Vec<String>,
     pub profile: Vec<Vec<f64>>,
 }
 
+/// Collects rows of (measure, value) pairs. The header follows the first row
+/// and gets an id column numbering the rows if that row has none; measures
+/// missing from a later row are NaN.
+impl FromIterator<Vec<(String, f64)>> for MeasureTable {
+    fn from_iter<I: IntoIterator<Item = Vec<(String, f64)>>>(iter: I) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        let mut profile = Vec::new();
+        for (idx, row) in iter.into_iter().enumerate() {
+            if idx == 0 {
+                if !row.iter().any(|(name, _)| name == "id") {
+                    names.push("id".to_string());
+                }
+                names.extend(row.iter().map(|(name, _)| name.clone()));
+            }
+            let values: HashMap<String, f64> = row.into_iter().collect();
+            profile.push(
+                names
+                    .iter()
+                    .map(|name| match (name.as_str(), values.get(name)) {
+                        (_, Some(&value)) => value,
+                        ("id", None) => idx as f64,
+                        _ => f64::NAN,
+                    })
+                    .collect(),
+            );
+        }
+        MeasureTable { names, profile }
+    }
+}
+
 impl MeasureTable {
     pub fn new<P>(path: P) -> MeasureTable
     where
         P: AsRef<Path> + Display,
     {
-        let (names, profile) = read_table(path);
-        MeasureTable { names, profile }
+        MeasureTable::try_new(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new<P>(path: P) -> Result<MeasureTable, ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        if ProfileFormat::from_path(&path).is_some() {
+            return Ok(ProfileFile::load(path)?.measure_table());
+        }
+        let (names, profile) = read_table(path)?;
+        Ok(MeasureTable { names, profile })
+    }
+
+    /// Builds a measure table from a header and one row per configuration.
+    pub fn from_rows(
+        names: Vec<String>,
+        profile: Vec<Vec<f64>>,
+    ) -> Result<MeasureTable, ProfileError> {
+        check_rows(&names, &profile)?;
+        Ok(MeasureTable { names, profile })
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        write_table(path, &self.names, &self.profile)
     }
 
     pub fn constraint_idx(&self, constraint: &str) -> usize {
+        self.try_constraint_idx(constraint)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_constraint_idx(&self, constraint: &str) -> Result<usize, ProfileError> {
+        self.measure_idx("Constraint", constraint)
+    }
+
+    /// Returns the measures referenced by `opt_func` along with their
+    /// column in the measure table.
+    pub fn objective_measures(
+        &self,
+        opt_func: &str,
+    ) -> Result<(Vec<String>, Vec<usize>), ProfileError> {
+        let obj_measures: Vec<String> = NAME_REGEX
+            .find_iter(opt_func)
+            .map(|f| String::from(f.as_str()))
+            .collect();
+        let obj_measure_indices = obj_measures
+            .iter()
+            .map(|needle| self.measure_idx("Objective", needle))
+            .collect::<Result<Vec<usize>, ProfileError>>()?;
+        Ok((obj_measures, obj_measure_indices))
+    }
+
+    /// Folds `observed` (measure column, value) into the row with the given id
+    /// and returns whether such a row exists.
+    pub fn refine(&mut self, id: u64, observed: &[(usize, f64)], weight: f64) -> bool {
+        let Some(id_idx) = self.names.iter().position(|name| name == "id") else {
+            return false;
+        };
+        match self.profile.iter_mut().find(|row| row[id_idx] as u64 == id) {
+            Some(row) => {
+                ewma(row, observed, weight);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn measure_idx(&self, role: &'static str, measure: &str) -> Result<usize, ProfileError> {
         self.names
             .iter()
-            .position(|name| name == constraint)
-            .expect("Constraint measure not in measure table.")
+            .position(|name| name == measure)
+            .ok_or_else(|| ProfileError::MissingMeasure {
+                role,
+                measure: measure.to_string(),
+                header: self.names.clone(),
+            })
     }
 }
 
-#[derive(Clone)]
+/// Moves the given measures of `row` towards their observed values with an
+/// exponentially weighted moving average. Non-finite observations are ignored.
+fn ewma(row: &mut [f64], observed: &[(usize, f64)], weight: f64) {
+    for &(idx, value) in observed.iter().filter(|(_, value)| value.is_finite()) {
+        row[idx] = (1.0 - weight) * row[idx] + weight * value;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KnobTable {
     pub names: Vec<String>,
     pub configurations: Vec<HashMap<String, u64>>,
 }
 
+/// Collects configurations as (knob, value) pairs, e.g. the ones a
+/// `SamplingPlan` lists. The header follows the first configuration and gets
+/// an id column numbering the configurations if it has none.
+impl FromIterator<Vec<(String, u64)>> for KnobTable {
+    fn from_iter<I: IntoIterator<Item = Vec<(String, u64)>>>(iter: I) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        let mut configurations = Vec::new();
+        for (idx, config) in iter.into_iter().enumerate() {
+            if idx == 0 {
+                if !config.iter().any(|(name, _)| name == "id") {
+                    names.push("id".to_string());
+                }
+                names.extend(config.iter().map(|(name, _)| name.clone()));
+            }
+            let mut config: HashMap<String, u64> = config.into_iter().collect();
+            config.entry("id".to_string()).or_insert(idx as u64);
+            configurations.push(config);
+        }
+        KnobTable {
+            names,
+            configurations,
+        }
+    }
+}
+
 impl KnobTable {
     pub fn new<P>(path: P) -> KnobTable
     where
         P: AsRef<Path> + Display,
     {
-        let (names, values) = read_table(path);
+        KnobTable::try_new(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new<P>(path: P) -> Result<KnobTable, ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        if ProfileFormat::from_path(&path).is_some() {
+            return Ok(ProfileFile::load(path)?.knob_table());
+        }
+        let (names, values) = read_table(path)?;
         let configurations = values
             .into_iter()
             .map(|config| names.iter().cloned().zip(config.into_iter()).collect())
             .collect();
 
-        KnobTable {
+        Ok(KnobTable {
             names,
             configurations,
-        }
+        })
+    }
+
+    /// Builds a knob table from a header and one row of knob values per
+    /// configuration.
+    pub fn from_rows(names: Vec<String>, rows: Vec<Vec<u64>>) -> Result<KnobTable, ProfileError> {
+        check_rows(&names, &rows)?;
+        let configurations = rows
+            .into_iter()
+            .map(|row| names.iter().cloned().zip(row).collect())
+            .collect();
+        Ok(KnobTable {
+            names,
+            configurations,
+        })
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        let rows: Vec<Vec<u64>> = self
+            .configurations
+            .iter()
+            .map(|config| self.names.iter().map(|name| config[name]).collect())
+            .collect();
+        write_table(path, &self.names, &rows)
     }
 }
 
 #[derive(Clone)]
 pub struct ActiveModel {
     pub configs: Vec<(Vec<f64>, HashMap<String, u64>)>,
+    /// Ids of configurations whose measures were predicted instead of profiled.
+    pub predicted: HashSet<u64>,
 }
 
 impl ActiveModel {
     pub fn new(measure_table: &MeasureTable, knob_table: &KnobTable) -> ActiveModel {
+        let id_idx = match measure_table.names.iter().position(|name| name == "id") {
+            Some(id_idx) => id_idx,
+            None => {
+                let configs = measure_table
+                    .profile
+                    .iter()
+                    .zip(knob_table.configurations.iter())
+                    .map(|(mt_entry, kt_entry)| (mt_entry.clone(), kt_entry.clone()))
+                    .collect();
+                return ActiveModel {
+                    configs,
+                    predicted: HashSet::new(),
+                };
+            }
+        };
+
+        // Join on id so that reordered tables still pair up correctly
+        let settings: HashMap<u64, &HashMap<String, u64>> = knob_table
+            .configurations
+            .iter()
+            .filter_map(|kt_entry| kt_entry.get("id").map(|&id| (id, kt_entry)))
+            .collect();
         let configs = measure_table
             .profile
             .iter()
-            .zip(knob_table.configurations.iter())
-            .map(|(mt_entry, kt_entry)| (mt_entry.clone(), kt_entry.clone()))
+            .filter_map(|mt_entry| {
+                settings
+                    .get(&(mt_entry[id_idx] as u64))
+                    .map(|&kt_entry| (mt_entry.clone(), kt_entry.clone()))
+            })
             .collect();
-        ActiveModel { configs }
+        ActiveModel {
+            configs,
+            predicted: HashSet::new(),
+        }
+    }
+
+    pub fn from_profile(profile: &ProfileFile) -> ActiveModel {
+        ActiveModel::new(&profile.measure_table(), &profile.knob_table())
+    }
+
+    pub fn mark_predicted<I: IntoIterator<Item = u64>>(&mut self, ids: I) {
+        self.predicted.extend(ids);
     }
 
-    pub fn find_id(&self, knobs: &HashMap<String, Rc<dyn Tunable<u64>>>) -> Option<usize> {
+    pub fn is_predicted(&self, idx: usize) -> bool {
+        self.configs[idx]
+            .1
+            .get("id")
+            .is_some_and(|id| self.predicted.contains(id))
+    }
+
+    /// Drops all predicted configurations and returns how many there were.
+    pub fn exclude_predicted(&mut self) -> usize {
+        let original_length = self.configs.len();
+        let predicted = std::mem::take(&mut self.predicted);
+        self.configs
+            .retain(|(_, settings)| !settings.get("id").is_some_and(|id| predicted.contains(id)));
+        original_length - self.configs.len()
+    }
+
+    pub fn find_id<H: Tunable<u64>>(&self, knobs: &HashMap<String, H>) -> Option<usize> {
         
         for (idx, (_, hmap)) in self.configs.iter().enumerate() {
             println!("Checking hmap: {:?}", hmap.keys().collect::<Vec<_>>());
@@ -125,7 +598,7 @@ impl ActiveModel {
         None
     }
 
-    pub fn restrict_model(&mut self, knobs: &HashMap<String, Rc<dyn Tunable<u64>>>) -> usize {
+    pub fn restrict_model<H: Tunable<u64>>(&mut self, knobs: &HashMap<String, H>) -> usize {
         let original_length = self.configs.len();
         let configs = std::mem::take(&mut self.configs);
         self.configs = configs
@@ -157,11 +630,97 @@ impl ActiveModel {
         &self.configs[idx].1
     }
 
+    /// Knob table id of the configuration at `idx`, if there is one.
+    pub fn config_id(&self, idx: usize) -> Option<u64> {
+        self.configs
+            .get(idx)
+            .and_then(|(_, settings)| settings.get("id").copied())
+    }
+
+    /// Folds `observed` (measure column, value) into the measures of the
+    /// configuration at `idx`. A predicted configuration takes its first
+    /// observation as it is and counts as observed from then on, since the
+    /// prediction is only an estimate.
+    pub fn refine(&mut self, idx: usize, observed: &[(usize, f64)], weight: f64) {
+        let weight = match self.config_id(idx) {
+            Some(id) if self.predicted.remove(&id) => 1.0,
+            _ => weight,
+        };
+        ewma(&mut self.configs[idx].0, observed, weight);
+    }
+
     pub fn sort_by_constraint(&mut self, idx: usize) {
         self.configs
             .sort_by(|e0, e1| e0.0[idx].partial_cmp(&e1.0[idx]).unwrap())
     }
 
+    /// Drops configurations that the controller would never schedule. The
+    /// controller mixes two configurations so that the constraint hits its
+    /// target, which interpolates the objective linearly in the inverse of the
+    /// constraint. A configuration is dominated when such a mix of two other
+    /// configurations reaches the same constraint value with an objective that
+    /// is at least as good, i.e. when it is not a vertex of the optimal convex
+    /// frontier of (1 / constraint, objective). Returns the number of dropped
+    /// configurations.
+    pub fn prune_dominated(
+        &mut self,
+        constraint_idx: usize,
+        objective_idx: usize,
+        opt_type: OptimizationType,
+    ) -> usize {
+        let sign = match opt_type {
+            OptimizationType::Minimize => 1.0,
+            OptimizationType::Maximize => -1.0,
+        };
+        let mut points: Vec<(f64, f64, usize)> = Vec::with_capacity(self.configs.len());
+        for (idx, (measures, _)) in self.configs.iter().enumerate() {
+            let constraint = measures[constraint_idx];
+            let objective = sign * measures[objective_idx];
+            // The frontier is only meaningful for positive, finite measurements.
+            if !(constraint > 0.0 && constraint.is_finite() && objective.is_finite()) {
+                return 0;
+            }
+            points.push((1.0 / constraint, objective, idx));
+        }
+        points.sort_by(|p0, p1| {
+            p0.0.partial_cmp(&p1.0)
+                .unwrap()
+                .then(p0.1.partial_cmp(&p1.1).unwrap())
+        });
+
+        // Lower convex hull (monotone chain) over the best point of every
+        // constraint value. Collinear points are dropped as well since mixing
+        // their neighbours is just as good.
+        let mut frontier: Vec<(f64, f64, usize)> = Vec::with_capacity(points.len());
+        for point in points.into_iter() {
+            if matches!(frontier.last(), Some(last) if last.0 == point.0) {
+                continue;
+            }
+            while frontier.len() >= 2 {
+                let (a, b) = (frontier[frontier.len() - 2], frontier[frontier.len() - 1]);
+                let cross = (b.0 - a.0) * (point.1 - a.1) - (b.1 - a.1) * (point.0 - a.0);
+                if cross > 0.0 {
+                    break;
+                }
+                frontier.pop();
+            }
+            frontier.push(point);
+        }
+
+        let mut keep = vec![false; self.configs.len()];
+        for (_, _, idx) in frontier.into_iter() {
+            keep[idx] = true;
+        }
+        let original_length = self.configs.len();
+        let configs = std::mem::take(&mut self.configs);
+        self.configs = configs
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(config, keep)| keep.then_some(config))
+            .collect();
+        original_length - self.configs.len()
+    }
+
     pub fn cost_model(&self, indices: &[usize]) -> Vec<Vec<f64>> {
         self.configs
             .iter()
@@ -173,10 +732,10 @@ impl ActiveModel {
 
 #[cfg(test)]
 mod tests {
-    use super::{ActiveModel, KnobTable, MeasureTable};
+    use super::{ActiveModel, KnobTable, MeasureTable, ProfileError};
     use lazy_static::lazy_static;
     use regex::Regex;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::io::Write;
 
     lazy_static! {
@@ -234,6 +793,72 @@ mod tests {
         std::fs::remove_file("/tmp/knobtable").expect("Could not clean test knobtable.");
     }
 
+    #[test]
+    fn read_table_errors() {
+        let missing = MeasureTable::try_new("/tmp/does_not_exist_measuretable");
+        assert!(matches!(missing, Err(ProfileError::Io { .. })));
+
+        std::fs::File::create("/tmp/ragged_measuretable")
+            .expect("Could not create test measuretable")
+            .write_all(b"id,quality,performance\n0,1,2\n1,2\n")
+            .expect("Could not write measure table.");
+        match MeasureTable::try_new("/tmp/ragged_measuretable") {
+            Err(ProfileError::RaggedRow {
+                line,
+                expected,
+                found,
+                ..
+            }) => assert_eq!((line, expected, found), (3, 3, 2)),
+            _ => panic!("Expected a ragged row error"),
+        }
+        std::fs::remove_file("/tmp/ragged_measuretable")
+            .expect("Could not clean test measuretable.");
+
+        std::fs::File::create("/tmp/unparsable_knobtable")
+            .expect("Could not create test knobtable")
+            .write_all(b"id,me,qp\n0,1,2\n\n1,x,2\n")
+            .expect("Could not write knob table.");
+        match KnobTable::try_new("/tmp/unparsable_knobtable") {
+            Err(ProfileError::UnparsableCell {
+                line,
+                column,
+                header,
+                value,
+                ..
+            }) => {
+                assert_eq!((line, column), (4, 2));
+                assert_eq!(header, "me");
+                assert_eq!(value, "x");
+            }
+            _ => panic!("Expected an unparsable cell error"),
+        }
+        std::fs::remove_file("/tmp/unparsable_knobtable").expect("Could not clean test knobtable.");
+    }
+
+    #[test]
+    fn objective_measures_missing_from_header() {
+        let table = MeasureTable {
+            names: vec![
+                "id".to_string(),
+                "performance".to_string(),
+                "energy".to_string(),
+            ],
+            profile: vec![],
+        };
+        let (names, indices) = table.objective_measures("performance / energy").unwrap();
+        assert_eq!(names, vec!["performance", "energy"]);
+        assert_eq!(indices, vec![1, 2]);
+
+        match table.objective_measures("performance / powerConsumption") {
+            Err(ProfileError::MissingMeasure { role, measure, .. }) => {
+                assert_eq!(role, "Objective");
+                assert_eq!(measure, "powerConsumption");
+            }
+            _ => panic!("Expected a missing measure error"),
+        }
+        assert!(table.try_constraint_idx("quality").is_err());
+    }
+
     #[test]
     fn regex_text() {
         let regex = regex::Regex::new("[[:alpha:]]+[a-zA-Z0-9_]*").unwrap();
@@ -341,4 +966,122 @@ mod tests {
             active_model.cost_model(&obj_measure_indices)
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn prune_dominated_configs() {
+        use OptimizingController::OptimizationType;
+
+        // (id, performance, powerConsumption)
+        let rows = [
+            (0, 1.0, 10.0),
+            (1, 2.0, 12.0),
+            (2, 2.0, 15.0),
+            (3, 4.0, 20.0),
+            (4, 1.5, 14.0),
+            (5, 3.0, 16.5),
+        ];
+        let model = ActiveModel {
+            predicted: HashSet::new(),
+            configs: rows
+                .iter()
+                .map(|&(id, performance, power)| {
+                    (
+                        vec![id as f64, performance, power, -power],
+                        [("id".to_string(), id), ("step".to_string(), id + 1)].into(),
+                    )
+                })
+                .collect(),
+        };
+        let kept_ids = |model: &ActiveModel| -> Vec<u64> {
+            model.configs.iter().map(|(_, kt)| kt["id"]).collect()
+        };
+
+        let mut minimized = model.clone();
+        assert_eq!(
+            minimized.prune_dominated(1, 2, OptimizationType::Minimize),
+            2
+        );
+        assert_eq!(kept_ids(&minimized), vec![0, 1, 3, 5]);
+        assert_eq!(minimized.get_knob_settings(3)["step"], 6);
+
+        // Maximizing the negated power keeps the same frontier.
+        let mut maximized = model;
+        assert_eq!(
+            maximized.prune_dominated(1, 3, OptimizationType::Maximize),
+            2
+        );
+        assert_eq!(kept_ids(&maximized), vec![0, 1, 3, 5]);
+    }
+
+    #[test]
+    fn refine_measures() {
+        let mut measure_table = MeasureTable {
+            names: vec!["id".to_string(), "latency".to_string(), "power".to_string()],
+            profile: vec![vec![1.0, 2.0, 10.0], vec![0.0, 4.0, 20.0]],
+        };
+        assert!(measure_table.refine(0, &[(1, 8.0), (2, f64::NAN)], 0.25));
+        assert_eq!(measure_table.profile[1], vec![0.0, 5.0, 20.0]);
+        assert!(!measure_table.refine(7, &[(1, 8.0)], 0.25));
+
+        let knob_table = KnobTable {
+            names: vec!["id".to_string()],
+            configurations: vec![
+                [("id".to_string(), 0)].into(),
+                [("id".to_string(), 1)].into(),
+            ],
+        };
+        let mut model = ActiveModel::new(&measure_table, &knob_table);
+        model.refine(0, &[(2, 30.0)], 0.5);
+        assert_eq!(model.configs[0].0, vec![1.0, 2.0, 20.0]);
+        model.mark_predicted([0]);
+        model.refine(1, &[(1, 8.0)], 0.5);
+        assert_eq!(model.configs[1].0, vec![0.0, 8.0, 20.0]);
+        assert!(!model.is_predicted(1));
+        model.refine(1, &[(1, 4.0)], 0.5);
+        assert_eq!(model.configs[1].0, vec![0.0, 6.0, 20.0]);
+    }
+
+    #[test]
+    fn tables_in_memory() {
+        let names = ["id", "step", "threshold"].map(String::from).to_vec();
+        let knob_table =
+            KnobTable::from_rows(names.clone(), vec![vec![0, 1, 50000], vec![1, 4, 200000]])
+                .unwrap();
+        assert_eq!(knob_table.configurations[1]["threshold"], 200000);
+        assert!(matches!(
+            KnobTable::from_rows(names, vec![vec![0, 1]]),
+            Err(ProfileError::RaggedRow { line: 2, .. })
+        ));
+
+        let collected: KnobTable = [1, 4]
+            .into_iter()
+            .map(|step| vec![("step".to_string(), step)])
+            .collect();
+        assert_eq!(collected.names, vec!["id", "step"]);
+        assert_eq!(collected.configurations[1]["id"], 1);
+
+        let measure_table: MeasureTable = [
+            vec![("quality".to_string(), 1.0), ("latency".to_string(), 0.2)],
+            vec![("quality".to_string(), 0.25)],
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(measure_table.names, vec!["id", "quality", "latency"]);
+        assert_eq!(measure_table.profile[0], vec![0.0, 1.0, 0.2]);
+        assert!(measure_table.profile[1][2].is_nan());
+
+        let json = serde_json::to_string(&knob_table).unwrap();
+        assert_eq!(
+            serde_json::from_str::<KnobTable>(&json).unwrap(),
+            knob_table
+        );
+        let from_rows =
+            MeasureTable::from_rows(measure_table.names.clone(), vec![vec![0.0, 1.0, 0.2]])
+                .unwrap();
+        let json = serde_json::to_string(&from_rows).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MeasureTable>(&json).unwrap(),
+            from_rows
+        );
+    }
+}
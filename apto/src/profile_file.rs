@@ -0,0 +1,366 @@
+use crate::profile::{write_atomic, KnobTable, MeasureTable, ProfileError};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::path::Path;
+
+const BINARY_MAGIC: &[u8; 8] = b"APTOPRF1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    Yaml,
+    Json,
+    Binary,
+}
+
+impl ProfileFormat {
+    /// Picks the combined profile format from the file extension. Anything else
+    /// is treated as a legacy CSV table.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ProfileFormat> {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Some(ProfileFormat::Yaml),
+            Some("json") => Some(ProfileFormat::Json),
+            Some("bin") => Some(ProfileFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileMetadata {
+    pub hostname: String,
+    pub cpu_model: String,
+    pub kernel: String,
+    /// Seconds since the UNIX epoch at which profiling finished.
+    pub recorded_at: u64,
+    pub window_size: u64,
+    pub iterations: u64,
+    /// Number of runs averaged per configuration.
+    #[serde(default = "default_repetitions")]
+    pub repetitions: u64,
+    /// Iterations discarded after applying each configuration.
+    #[serde(default)]
+    pub warmup: u64,
+    pub knob_domains: BTreeMap<String, Vec<u64>>,
+}
+
+impl ProfileMetadata {
+    pub fn current(
+        window_size: u64,
+        iterations: u64,
+        knob_domains: BTreeMap<String, Vec<u64>>,
+    ) -> ProfileMetadata {
+        let recorded_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ProfileMetadata {
+            hostname: hostname(),
+            cpu_model: cpu_model(),
+            kernel: kernel(),
+            recorded_at,
+            window_size,
+            iterations,
+            repetitions: 1,
+            warmup: 0,
+            knob_domains,
+        }
+    }
+
+    /// Lists the host properties that differ from the machine we are running on.
+    pub fn host_mismatches(&self) -> Vec<(&'static str, String, String)> {
+        let current = [
+            ("hostname", &self.hostname, hostname()),
+            ("cpu_model", &self.cpu_model, cpu_model()),
+            ("kernel", &self.kernel, kernel()),
+        ];
+        current
+            .into_iter()
+            .filter(|(_, recorded, current)| *recorded != current)
+            .map(|(field, recorded, current)| (field, recorded.clone(), current))
+            .collect()
+    }
+}
+
+fn default_repetitions() -> u64 {
+    1
+}
+
+fn read_proc(path: &str) -> String {
+    std::fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn hostname() -> String {
+    read_proc("/proc/sys/kernel/hostname")
+}
+
+fn kernel() -> String {
+    read_proc("/proc/sys/kernel/osrelease")
+}
+
+fn cpu_model() -> String {
+    read_proc("/proc/cpuinfo")
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split(':').nth(1))
+        .map(|model| model.trim().to_string())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileEntry {
+    pub id: u64,
+    pub knobs: Vec<u64>,
+    pub measures: Vec<f64>,
+}
+
+/// A measure table and knob table stored together with a description of
+/// the machine and settings they were recorded with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileFile {
+    pub metadata: ProfileMetadata,
+    pub knobs: Vec<String>,
+    pub measures: Vec<String>,
+    pub configurations: Vec<ProfileEntry>,
+}
+
+impl ProfileFile {
+    pub fn from_tables(
+        metadata: ProfileMetadata,
+        measure_table: &MeasureTable,
+        knob_table: &KnobTable,
+    ) -> ProfileFile {
+        let knobs: Vec<String> = knob_table
+            .names
+            .iter()
+            .filter(|&name| name != "id")
+            .cloned()
+            .collect();
+        let measures: Vec<String> = measure_table
+            .names
+            .iter()
+            .filter(|&name| name != "id")
+            .cloned()
+            .collect();
+        let mt_id_idx = measure_table.names.iter().position(|name| name == "id");
+
+        let settings: HashMap<u64, &HashMap<String, u64>> = knob_table
+            .configurations
+            .iter()
+            .enumerate()
+            .map(|(idx, config)| (config.get("id").copied().unwrap_or(idx as u64), config))
+            .collect();
+        let configurations = measure_table
+            .profile
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, row)| {
+                let id = mt_id_idx.map(|i| row[i] as u64).unwrap_or(idx as u64);
+                let config = settings.get(&id)?;
+                Some(ProfileEntry {
+                    id,
+                    knobs: knobs.iter().map(|name| config[name]).collect(),
+                    measures: measure_table
+                        .names
+                        .iter()
+                        .zip(row.iter())
+                        .filter(|(name, _)| *name != "id")
+                        .map(|(_, &value)| value)
+                        .collect(),
+                })
+            })
+            .collect();
+
+        ProfileFile {
+            metadata,
+            knobs,
+            measures,
+            configurations,
+        }
+    }
+
+    pub fn measure_table(&self) -> MeasureTable {
+        let names = std::iter::once("id".to_string())
+            .chain(self.measures.iter().cloned())
+            .collect();
+        let profile = self
+            .configurations
+            .iter()
+            .map(|entry| {
+                std::iter::once(entry.id as f64)
+                    .chain(entry.measures.iter().copied())
+                    .collect()
+            })
+            .collect();
+        MeasureTable { names, profile }
+    }
+
+    pub fn knob_table(&self) -> KnobTable {
+        let names: Vec<String> = std::iter::once("id".to_string())
+            .chain(self.knobs.iter().cloned())
+            .collect();
+        let configurations = self
+            .configurations
+            .iter()
+            .map(|entry| {
+                names
+                    .iter()
+                    .cloned()
+                    .zip(std::iter::once(entry.id).chain(entry.knobs.iter().copied()))
+                    .collect()
+            })
+            .collect();
+        KnobTable {
+            names,
+            configurations,
+        }
+    }
+
+    pub fn load<P>(path: P) -> Result<ProfileFile, ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        let format = ProfileFormat::from_path(&path).unwrap_or(ProfileFormat::Yaml);
+        let content = std::fs::read(&path).map_err(|source| ProfileError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        let format_error = |message: String| ProfileError::Format {
+            path: path.to_string(),
+            message,
+        };
+        match format {
+            ProfileFormat::Yaml => {
+                serde_yaml::from_slice(&content).map_err(|e| format_error(e.to_string()))
+            }
+            ProfileFormat::Json => {
+                serde_json::from_slice(&content).map_err(|e| format_error(e.to_string()))
+            }
+            ProfileFormat::Binary => match content.strip_prefix(BINARY_MAGIC.as_slice()) {
+                Some(payload) => {
+                    bincode::deserialize(payload).map_err(|e| format_error(e.to_string()))
+                }
+                None => Err(format_error("missing binary profile header".to_string())),
+            },
+        }
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), ProfileError>
+    where
+        P: AsRef<Path> + Display,
+    {
+        let format = ProfileFormat::from_path(&path).unwrap_or(ProfileFormat::Yaml);
+        let format_error = |message: String| ProfileError::Format {
+            path: path.to_string(),
+            message,
+        };
+        let content = match format {
+            ProfileFormat::Yaml => serde_yaml::to_string(self)
+                .map(String::into_bytes)
+                .map_err(|e| format_error(e.to_string()))?,
+            ProfileFormat::Json => {
+                serde_json::to_vec_pretty(self).map_err(|e| format_error(e.to_string()))?
+            }
+            ProfileFormat::Binary => {
+                let mut content = BINARY_MAGIC.to_vec();
+                bincode::serialize_into(&mut content, self)
+                    .map_err(|e| format_error(e.to_string()))?;
+                content
+            }
+        };
+        write_atomic(path, &content)
+    }
+
+    /// Refuses profiles that were recorded on a different machine unless
+    /// `any_host` is set. A different kernel release alone does not change
+    /// what the configurations cost, so it is only reported.
+    pub fn check_host(&self, path: &str, any_host: bool) -> Result<(), ProfileError> {
+        for (field, recorded, current) in self.metadata.host_mismatches() {
+            if field == "kernel" || any_host {
+                warn!(
+                    "Profile {} was recorded with {} {}, running on {}",
+                    path, field, recorded, current
+                );
+                continue;
+            }
+            return Err(ProfileError::ForeignHost {
+                path: path.to_string(),
+                field,
+                recorded,
+                current,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileFile, ProfileMetadata};
+    use crate::profile::{KnobTable, MeasureTable, ProfileError};
+    use std::collections::BTreeMap;
+
+    fn example_profile() -> ProfileFile {
+        let measure_table = MeasureTable {
+            names: vec![
+                "id".to_string(),
+                "latency".to_string(),
+                "quality".to_string(),
+            ],
+            profile: vec![vec![1.0, 0.8, 1.0], vec![0.0, 0.2, 0.25]],
+        };
+        let knob_table = KnobTable {
+            names: vec!["id".to_string(), "step".to_string()],
+            configurations: vec![
+                [("id".to_string(), 0), ("step".to_string(), 4)].into(),
+                [("id".to_string(), 1), ("step".to_string(), 1)].into(),
+            ],
+        };
+        let mut domains = BTreeMap::new();
+        domains.insert("step".to_string(), vec![1, 4]);
+        let metadata = ProfileMetadata::current(20, 100, domains);
+        ProfileFile::from_tables(metadata, &measure_table, &knob_table)
+    }
+
+    #[test]
+    fn combined_profile_round_trip() {
+        let profile = example_profile();
+        assert_eq!(profile.knobs, vec!["step"]);
+        assert_eq!(profile.configurations[0].id, 1);
+        assert_eq!(profile.configurations[0].knobs, vec![1]);
+        assert_eq!(profile.configurations[0].measures, vec![0.8, 1.0]);
+
+        for path in [
+            "/tmp/apto_profile.yaml",
+            "/tmp/apto_profile.json",
+            "/tmp/apto_profile.bin",
+        ] {
+            profile.save(path).unwrap();
+            let loaded = ProfileFile::load(path).unwrap();
+            assert_eq!(profile, loaded);
+            assert!(loaded.check_host(path, false).is_ok());
+            assert_eq!(loaded.knob_table().configurations[1]["step"], 4);
+            assert_eq!(loaded.measure_table().profile[1], vec![0.0, 0.2, 0.25]);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn foreign_host_profile() {
+        let mut profile = example_profile();
+        profile.metadata.kernel = "2.6.32".to_string();
+        assert!(profile.check_host("profile.yaml", false).is_ok());
+        profile.metadata.hostname = "some-other-box".to_string();
+        assert!(matches!(
+            profile.check_host("profile.yaml", false),
+            Err(ProfileError::ForeignHost {
+                field: "hostname",
+                ..
+            })
+        ));
+        assert!(profile.check_host("profile.yaml", true).is_ok());
+    }
+}
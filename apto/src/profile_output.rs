@@ -0,0 +1,222 @@
+use crate::profile::{write_atomic, ProfileError};
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How far every appended profiling row is pushed before profiling goes on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Hand the row to the operating system, which survives the process
+    /// crashing but not the machine.
+    Row,
+    /// Also wait until the row is on disk.
+    Sync,
+}
+
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "row" => Ok(FlushPolicy::Row),
+            "sync" => Ok(FlushPolicy::Sync),
+            _ => Err(format!("unknown flush policy '{}'", s)),
+        }
+    }
+}
+
+/// Where a profiling session writes its tables. By default everything goes
+/// to the current working directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileOutput {
+    pub knob_table: String,
+    pub measure_table: String,
+    /// Spread of repeated runs, only written with `PROFILE_REPETITIONS` > 1.
+    pub stats: String,
+    /// Combined profile written once profiling finishes.
+    pub profile: String,
+    pub flush: FlushPolicy,
+}
+
+impl Default for ProfileOutput {
+    fn default() -> Self {
+        ProfileOutput {
+            knob_table: "knobtable".to_string(),
+            measure_table: "measuretable".to_string(),
+            stats: "measuretable.stats".to_string(),
+            profile: "profile.yaml".to_string(),
+            flush: FlushPolicy::Row,
+        }
+    }
+}
+
+impl ProfileOutput {
+    /// The default file names inside `dir`.
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> ProfileOutput {
+        let path = |name: &str| dir.as_ref().join(name).display().to_string();
+        ProfileOutput {
+            knob_table: path("knobtable"),
+            measure_table: path("measuretable"),
+            stats: path("measuretable.stats"),
+            profile: path("profile.yaml"),
+            flush: FlushPolicy::Row,
+        }
+    }
+
+    pub fn flush(mut self, flush: FlushPolicy) -> ProfileOutput {
+        self.flush = flush;
+        self
+    }
+
+    /// Creates the directories the tables are written to.
+    pub(crate) fn create_dirs(&self) -> Result<(), ProfileError> {
+        for path in [
+            &self.knob_table,
+            &self.measure_table,
+            &self.stats,
+            &self.profile,
+        ] {
+            if let Some(dir) = Path::new(path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+            {
+                std::fs::create_dir_all(dir).map_err(|source| ProfileError::Io {
+                    path: dir.display().to_string(),
+                    source,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends rows to a profiling table. Every row goes out in a single write,
+/// so a crash leaves at most an incomplete last line, which is dropped the
+/// next time the table is opened.
+pub(crate) struct TableAppender {
+    path: String,
+    file: File,
+    flush: FlushPolicy,
+}
+
+impl TableAppender {
+    /// Opens `path` for appending, creating it with `header` if needed. An
+    /// existing table has to have exactly this header.
+    pub(crate) fn open(
+        path: &str,
+        header: &[String],
+        flush: FlushPolicy,
+    ) -> Result<TableAppender, ProfileError> {
+        let io_error = |source| ProfileError::Io {
+            path: path.to_string(),
+            source,
+        };
+        let header_line = format!("{}\n", header.join(","));
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let found: Option<Vec<String>> = content
+                    .lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| {
+                        line.split(',')
+                            .map(|name| name.trim().to_string())
+                            .collect()
+                    });
+                if let Some(found) = found.filter(|found| found != header) {
+                    // An unterminated header is only a torn write of our own
+                    if content.contains('\n') || !header_line.starts_with(content.trim()) {
+                        return Err(ProfileError::HeaderMismatch {
+                            path: path.to_string(),
+                            expected: header.to_vec(),
+                            found,
+                        });
+                    }
+                }
+                if !content.ends_with('\n') {
+                    match content.rfind('\n') {
+                        Some(end) => {
+                            warn!("Dropping incomplete last row of {}", path);
+                            OpenOptions::new()
+                                .write(true)
+                                .open(path)
+                                .and_then(|file| file.set_len(end as u64 + 1))
+                                .map_err(io_error)?;
+                        }
+                        None => write_atomic(path, header_line.as_bytes())?,
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                write_atomic(path, header_line.as_bytes())?
+            }
+            Err(source) => return Err(io_error(source)),
+        }
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        Ok(TableAppender {
+            path: path.to_string(),
+            file,
+            flush,
+        })
+    }
+
+    pub(crate) fn append(&mut self, row: &str) -> Result<(), ProfileError> {
+        let line = format!("{}\n", row);
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.flush())
+            .and_then(|_| match self.flush {
+                FlushPolicy::Row => Ok(()),
+                FlushPolicy::Sync => self.file.sync_data(),
+            })
+            .map_err(|source| ProfileError::Io {
+                path: self.path.clone(),
+                source,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlushPolicy, ProfileOutput, TableAppender};
+    use crate::profile::{MeasureTable, ProfileError};
+
+    #[test]
+    fn append_profile_rows() {
+        let output = ProfileOutput::in_dir("/tmp/apto_output").flush(FlushPolicy::Sync);
+        output.create_dirs().unwrap();
+        let path = output.measure_table.as_str();
+        let _ = std::fs::remove_file(path);
+        let header = ["id", "latency"].map(String::from).to_vec();
+
+        let mut table = TableAppender::open(path, &header, output.flush).unwrap();
+        table.append("0,1.5").unwrap();
+        drop(table);
+        // A crash in the middle of the second row
+        std::fs::write(path, "id,latency\n0,1.5\n1,2.").unwrap();
+
+        let mut table = TableAppender::open(path, &header, FlushPolicy::Row).unwrap();
+        table.append("1,2.5").unwrap();
+        let measure_table = MeasureTable::try_new(path).unwrap();
+        assert_eq!(measure_table.profile, vec![vec![0.0, 1.5], vec![1.0, 2.5]]);
+
+        let other = ["id", "power"].map(String::from).to_vec();
+        match TableAppender::open(path, &other, FlushPolicy::Row) {
+            Err(ProfileError::HeaderMismatch { found, .. }) => assert_eq!(found, header),
+            _ => panic!("appended to a table with a different header"),
+        }
+
+        // Torn header
+        std::fs::write(path, "id,lat").unwrap();
+        TableAppender::open(path, &header, FlushPolicy::Row).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "id,latency\n");
+
+        std::fs::remove_dir_all("/tmp/apto_output").unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use crate::profile::{ActiveModel, KnobTable, MeasureTable, ProfileError};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use OptimizingController::OptimizationType;
+
+/// How the configurations that only differ in knobs that are neither kept
+/// nor fixed are merged into one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProjectionAggregate {
+    /// Minimum of every measure.
+    Min,
+    /// Mean of every measure.
+    Mean,
+    /// The configuration with the best value of the given measure.
+    Best {
+        measure: String,
+        opt_type: OptimizationType,
+    },
+}
+
+impl FromStr for ProjectionAggregate {
+    type Err = String;
+
+    /// Parses `min`, `mean` or `best:<measure>:<minimize|maximize>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<&str>>()[..] {
+            ["min"] => Ok(ProjectionAggregate::Min),
+            ["mean"] => Ok(ProjectionAggregate::Mean),
+            ["best", measure, opt_type] => {
+                let opt_type = match opt_type {
+                    "min" | "minimize" => OptimizationType::Minimize,
+                    "max" | "maximize" => OptimizationType::Maximize,
+                    _ => return Err(format!("unknown optimization type '{}'", opt_type)),
+                };
+                Ok(ProjectionAggregate::Best {
+                    measure: measure.to_string(),
+                    opt_type,
+                })
+            }
+            _ => Err(format!("unknown aggregate '{}'", s)),
+        }
+    }
+}
+
+/// Projects a profile onto a subset of its knobs, e.g. to split a full
+/// system profile into an application and a system profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileProjection {
+    /// Knobs of the projected knob table, in this order.
+    pub keep: Vec<String>,
+    /// Dropped knobs restricted to a single value.
+    pub fixed: BTreeMap<String, u64>,
+    /// Merges configurations that differ in any other dropped knob.
+    pub aggregate: ProjectionAggregate,
+}
+
+impl ProfileProjection {
+    pub fn new(keep: Vec<String>) -> ProfileProjection {
+        ProfileProjection {
+            keep,
+            fixed: BTreeMap::new(),
+            aggregate: ProjectionAggregate::Min,
+        }
+    }
+
+    pub fn fix(mut self, knob: &str, value: u64) -> ProfileProjection {
+        self.fixed.insert(knob.to_string(), value);
+        self
+    }
+
+    pub fn aggregate(mut self, aggregate: ProjectionAggregate) -> ProfileProjection {
+        self.aggregate = aggregate;
+        self
+    }
+
+    /// Returns the projected measure and knob tables. Ids are renumbered from
+    /// zero in the order in which the kept knob settings first appear.
+    pub fn apply(
+        &self,
+        measure_table: &MeasureTable,
+        knob_table: &KnobTable,
+    ) -> Result<(MeasureTable, KnobTable), ProfileError> {
+        for knob in self.keep.iter().chain(self.fixed.keys()) {
+            if !knob_table.names.contains(knob) {
+                return Err(ProfileError::MissingKnob {
+                    knob: knob.clone(),
+                    header: knob_table.names.clone(),
+                });
+            }
+        }
+        let best = match &self.aggregate {
+            ProjectionAggregate::Best { measure, opt_type } => {
+                let idx = measure_table
+                    .names
+                    .iter()
+                    .position(|name| name == measure)
+                    .ok_or_else(|| ProfileError::MissingMeasure {
+                        role: "Projection",
+                        measure: measure.clone(),
+                        header: measure_table.names.clone(),
+                    })?;
+                Some((idx, *opt_type))
+            }
+            _ => None,
+        };
+        let id_idx = measure_table.names.iter().position(|name| name == "id");
+
+        let mut order: Vec<Vec<u64>> = Vec::new();
+        let mut groups: HashMap<Vec<u64>, Vec<Vec<f64>>> = HashMap::new();
+        for (measures, settings) in ActiveModel::new(measure_table, knob_table).configs {
+            if self
+                .fixed
+                .iter()
+                .any(|(knob, value)| settings.get(knob) != Some(value))
+            {
+                continue;
+            }
+            let kept: Vec<u64> = self.keep.iter().map(|knob| settings[knob]).collect();
+            groups
+                .entry(kept.clone())
+                .or_insert_with(|| {
+                    order.push(kept);
+                    Vec::new()
+                })
+                .push(measures);
+        }
+
+        let mut profile = Vec::with_capacity(order.len());
+        let mut configurations = Vec::with_capacity(order.len());
+        for (new_id, kept) in order.into_iter().enumerate() {
+            let rows = &groups[&kept];
+            let mut row = match best {
+                Some((idx, opt_type)) => rows
+                    .iter()
+                    .min_by(|a, b| {
+                        let ordering = a[idx].partial_cmp(&b[idx]).unwrap();
+                        match opt_type {
+                            OptimizationType::Minimize => ordering,
+                            OptimizationType::Maximize => ordering.reverse(),
+                        }
+                    })
+                    .unwrap()
+                    .clone(),
+                None => (0..rows[0].len())
+                    .map(|col| {
+                        let values = rows.iter().map(|row| row[col]);
+                        match self.aggregate {
+                            ProjectionAggregate::Mean => values.sum::<f64>() / rows.len() as f64,
+                            _ => values.fold(f64::INFINITY, f64::min),
+                        }
+                    })
+                    .collect(),
+            };
+            if let Some(id_idx) = id_idx {
+                row[id_idx] = new_id as f64;
+            }
+            profile.push(row);
+            configurations.push(
+                std::iter::once(("id".to_string(), new_id as u64))
+                    .chain(self.keep.iter().cloned().zip(kept))
+                    .collect(),
+            );
+        }
+
+        let names = std::iter::once("id".to_string())
+            .chain(self.keep.iter().cloned())
+            .collect();
+        Ok((
+            MeasureTable {
+                names: measure_table.names.clone(),
+                profile,
+            },
+            KnobTable {
+                names,
+                configurations,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileProjection, ProjectionAggregate};
+    use crate::profile::{KnobTable, MeasureTable};
+
+    fn profile() -> (MeasureTable, KnobTable) {
+        let names: Vec<String> = ["id", "cores", "uncore", "frequency"]
+            .map(String::from)
+            .to_vec();
+        let rows: [[u64; 4]; 6] = [
+            [0, 1, 24, 1000],
+            [1, 1, 24, 2000],
+            [2, 2, 24, 1000],
+            [3, 2, 24, 2000],
+            [4, 1, 12, 2000],
+            [5, 2, 12, 2000],
+        ];
+        let knob_table = KnobTable {
+            names: names.clone(),
+            configurations: rows
+                .iter()
+                .map(|row| names.iter().cloned().zip(row.iter().copied()).collect())
+                .collect(),
+        };
+        // power grows with every knob, latency shrinks with cores and frequency
+        let measure_table = MeasureTable {
+            names: vec!["id".to_string(), "latency".to_string(), "power".to_string()],
+            profile: rows
+                .iter()
+                .rev()
+                .map(|&[id, cores, uncore, frequency]| {
+                    let latency = 4000.0 / (cores * frequency) as f64;
+                    let power = (cores * frequency + 10 * uncore) as f64;
+                    vec![id as f64, latency, power]
+                })
+                .collect(),
+        };
+        (measure_table, knob_table)
+    }
+
+    #[test]
+    fn project_fixed_knobs() {
+        let (mt, kt) = profile();
+        let projection = ProfileProjection::new(vec!["cores".to_string()])
+            .fix("uncore", 24)
+            .fix("frequency", 2000);
+        let (pmt, pkt) = projection.apply(&mt, &kt).unwrap();
+        assert_eq!(pkt.names, vec!["id", "cores"]);
+        // Ids follow the order of the measure table, which lists cores 2 first
+        assert_eq!(
+            pmt.profile,
+            vec![vec![0.0, 1.0, 4240.0], vec![1.0, 2.0, 2240.0]]
+        );
+        assert_eq!(pkt.configurations[0]["cores"], 2);
+        assert_eq!(pkt.configurations[1]["id"], 1);
+
+        pmt.save("/tmp/apto_projected.mt").unwrap();
+        pkt.save("/tmp/apto_projected.kt").unwrap();
+        let reloaded = MeasureTable::try_new("/tmp/apto_projected.mt").unwrap();
+        assert_eq!(reloaded.profile, pmt.profile);
+        let reloaded = KnobTable::try_new("/tmp/apto_projected.kt").unwrap();
+        assert_eq!(reloaded.configurations, pkt.configurations);
+        std::fs::remove_file("/tmp/apto_projected.mt").unwrap();
+        std::fs::remove_file("/tmp/apto_projected.kt").unwrap();
+    }
+
+    #[test]
+    fn project_aggregated_knobs() {
+        let (mt, kt) = profile();
+        let keep = vec!["uncore".to_string(), "frequency".to_string()];
+
+        let projection = ProfileProjection::new(keep.clone()).fix("cores", 2);
+        let (_, pkt) = projection.apply(&mt, &kt).unwrap();
+        assert_eq!(pkt.configurations.len(), 3);
+
+        let mean = ProfileProjection::new(vec!["cores".to_string()])
+            .fix("uncore", 24)
+            .aggregate("mean".parse().unwrap());
+        let (pmt, _) = mean.apply(&mt, &kt).unwrap();
+        assert_eq!(pmt.profile[1], vec![1.0, 3.0, 1740.0]);
+
+        let best: ProjectionAggregate = "best:power:minimize".parse().unwrap();
+        let (pmt, _) = ProfileProjection::new(vec!["cores".to_string()])
+            .aggregate(best)
+            .apply(&mt, &kt)
+            .unwrap();
+        assert_eq!(pmt.profile[1], vec![1.0, 4.0, 1240.0]);
+
+        assert!(ProfileProjection::new(vec!["threads".to_string()])
+            .apply(&mt, &kt)
+            .is_err());
+        assert!("median".parse::<ProjectionAggregate>().is_err());
+    }
+}
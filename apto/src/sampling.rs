@@ -0,0 +1,321 @@
+use crate::profile::{KnobTable, ProfileError};
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Prefix of the knob table line that records the plan it was generated with.
+const PLAN_PREFIX: &str = "# plan=";
+
+/// Which configurations a profiling session measures. Parsed from the
+/// `PROFILE_PLAN` environment variable:
+///
+/// * `full`: every combination of knob values (the default)
+/// * `random:N:SEED`: N distinct combinations drawn uniformly
+/// * `lhs:N:SEED`: N combinations from a latin hypercube over the knob values
+/// * `corners`: the extreme values of every knob plus the center of the space
+/// * `file:PATH`: the configurations listed in a knob table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SamplingPlan {
+    Full,
+    Random { size: usize, seed: u64 },
+    LatinHypercube { size: usize, seed: u64 },
+    CornersAndCenter,
+    Explicit(String),
+}
+
+impl Display for SamplingPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            SamplingPlan::Full => write!(f, "full"),
+            SamplingPlan::Random { size, seed } => write!(f, "random:{}:{}", size, seed),
+            SamplingPlan::LatinHypercube { size, seed } => write!(f, "lhs:{}:{}", size, seed),
+            SamplingPlan::CornersAndCenter => write!(f, "corners"),
+            SamplingPlan::Explicit(path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+impl FromStr for SamplingPlan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_sized = |args: &str| -> Result<(usize, u64), String> {
+            let (size, seed) = args.split_once(':').unwrap_or((args, "0"));
+            let size = size
+                .parse()
+                .map_err(|_| format!("invalid sample size '{}' in plan '{}'", size, s))?;
+            let seed = seed
+                .parse()
+                .map_err(|_| format!("invalid seed '{}' in plan '{}'", seed, s))?;
+            Ok((size, seed))
+        };
+        let (kind, args) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        match kind {
+            "full" => Ok(SamplingPlan::Full),
+            "corners" => Ok(SamplingPlan::CornersAndCenter),
+            "random" => {
+                let (size, seed) = parse_sized(args)?;
+                Ok(SamplingPlan::Random { size, seed })
+            }
+            "lhs" => {
+                let (size, seed) = parse_sized(args)?;
+                Ok(SamplingPlan::LatinHypercube { size, seed })
+            }
+            "file" if !args.is_empty() => Ok(SamplingPlan::Explicit(args.to_string())),
+            _ => Err(format!("unknown profiling plan '{}'", s)),
+        }
+    }
+}
+
+impl SamplingPlan {
+    /// Reads the plan recorded in the first line of a knob table, if any.
+    pub fn recorded<P: AsRef<Path>>(kt_path: P) -> Option<SamplingPlan> {
+        let content = std::fs::read_to_string(kt_path).ok()?;
+        content
+            .lines()
+            .next()?
+            .strip_prefix(PLAN_PREFIX)?
+            .parse()
+            .ok()
+    }
+
+    /// The line a knob table generated with this plan starts with.
+    pub fn header_line(&self) -> String {
+        format!("{}{}", PLAN_PREFIX, self)
+    }
+
+    /// Lists the configurations to profile. `domains` holds the possible
+    /// values of every knob in the order the knob table uses.
+    pub fn configurations(
+        &self,
+        domains: &[(String, Vec<u64>)],
+    ) -> Result<Vec<Vec<(String, u64)>>, ProfileError> {
+        let indices: Vec<Vec<usize>> = match self {
+            SamplingPlan::Full => domains
+                .iter()
+                .map(|(_, values)| 0..values.len())
+                .multi_cartesian_product()
+                .collect(),
+            SamplingPlan::Random { size, seed } => random_indices(domains, *size, *seed),
+            SamplingPlan::LatinHypercube { size, seed } => {
+                latin_hypercube_indices(domains, *size, *seed)
+            }
+            SamplingPlan::CornersAndCenter => corner_indices(domains),
+            SamplingPlan::Explicit(path) => return explicit_configurations(path, domains),
+        };
+        Ok(indices
+            .into_iter()
+            .map(|config| {
+                config
+                    .into_iter()
+                    .zip(domains.iter())
+                    .map(|(idx, (name, values))| (name.clone(), values[idx]))
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+/// SplitMix64, so that a seed yields the same plan regardless of the version
+/// of any random number crate.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn random_indices(domains: &[(String, Vec<u64>)], size: usize, seed: u64) -> Vec<Vec<usize>> {
+    let total = domains
+        .iter()
+        .fold(1usize, |acc, (_, values)| acc.saturating_mul(values.len()));
+    let size = size.min(total);
+    let mut rng = SplitMix64(seed);
+    let mut seen = HashSet::new();
+    let mut sample = Vec::with_capacity(size);
+    while sample.len() < size {
+        let config: Vec<usize> = domains
+            .iter()
+            .map(|(_, values)| rng.below(values.len()))
+            .collect();
+        if seen.insert(config.clone()) {
+            sample.push(config);
+        }
+    }
+    sample
+}
+
+fn latin_hypercube_indices(
+    domains: &[(String, Vec<u64>)],
+    size: usize,
+    seed: u64,
+) -> Vec<Vec<usize>> {
+    let mut rng = SplitMix64(seed);
+    // One stratum per sample and knob, shuffled independently for every knob
+    let columns: Vec<Vec<usize>> = domains
+        .iter()
+        .map(|(_, values)| {
+            let mut strata: Vec<usize> = (0..size).collect();
+            for i in (1..size).rev() {
+                strata.swap(i, rng.below(i + 1));
+            }
+            strata
+                .into_iter()
+                .map(|stratum| {
+                    let position = (stratum as f64 + rng.unit()) / size as f64;
+                    ((position * values.len() as f64) as usize).min(values.len() - 1)
+                })
+                .collect()
+        })
+        .collect();
+
+    // Small knob domains make strata collapse onto the same configuration
+    (0..size)
+        .map(|sample| columns.iter().map(|column| column[sample]).collect())
+        .unique()
+        .collect()
+}
+
+fn corner_indices(domains: &[(String, Vec<u64>)]) -> Vec<Vec<usize>> {
+    let mut configs: Vec<Vec<usize>> = domains
+        .iter()
+        .map(|(_, values)| vec![0, values.len() - 1].into_iter().unique())
+        .multi_cartesian_product()
+        .collect();
+    configs.push(
+        domains
+            .iter()
+            .map(|(_, values)| (values.len() - 1) / 2)
+            .collect(),
+    );
+    configs.into_iter().unique().collect()
+}
+
+fn explicit_configurations(
+    path: &str,
+    domains: &[(String, Vec<u64>)],
+) -> Result<Vec<Vec<(String, u64)>>, ProfileError> {
+    let knob_table = KnobTable::try_new(path)?;
+    knob_table
+        .configurations
+        .iter()
+        .map(|config| {
+            domains
+                .iter()
+                .map(|(name, values)| match config.get(name) {
+                    Some(value) if values.contains(value) => Ok((name.clone(), *value)),
+                    Some(value) => Err(ProfileError::Format {
+                        path: path.to_string(),
+                        message: format!("{} is not a possible value of knob {}", value, name),
+                    }),
+                    None => Err(ProfileError::Format {
+                        path: path.to_string(),
+                        message: format!("missing column for knob {}", name),
+                    }),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SamplingPlan;
+    use std::collections::HashSet;
+
+    fn domains() -> Vec<(String, Vec<u64>)> {
+        vec![
+            ("cores".to_string(), vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            ("frequency".to_string(), vec![800, 1600, 2400]),
+            ("step".to_string(), vec![1, 4]),
+        ]
+    }
+
+    #[test]
+    fn parse_plans() {
+        for plan in [
+            SamplingPlan::Full,
+            SamplingPlan::Random { size: 10, seed: 7 },
+            SamplingPlan::LatinHypercube { size: 5, seed: 1 },
+            SamplingPlan::CornersAndCenter,
+            SamplingPlan::Explicit("/tmp/plan.kt".to_string()),
+        ] {
+            assert_eq!(plan.to_string().parse::<SamplingPlan>(), Ok(plan));
+        }
+        assert!("random:ten".parse::<SamplingPlan>().is_err());
+        assert!("everything".parse::<SamplingPlan>().is_err());
+    }
+
+    #[test]
+    fn plan_configurations() {
+        let domains = domains();
+        let full = SamplingPlan::Full.configurations(&domains).unwrap();
+        assert_eq!(full.len(), 48);
+        assert_eq!(
+            full[1],
+            vec![
+                ("cores".to_string(), 1),
+                ("frequency".to_string(), 800),
+                ("step".to_string(), 4)
+            ]
+        );
+
+        let random = SamplingPlan::Random { size: 20, seed: 3 };
+        let sample = random.configurations(&domains).unwrap();
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample.iter().collect::<HashSet<_>>().len(), 20);
+        assert_eq!(sample, random.configurations(&domains).unwrap());
+
+        // Every core count shows up exactly once across eight strata
+        let lhs = SamplingPlan::LatinHypercube { size: 8, seed: 3 };
+        let sample = lhs.configurations(&domains).unwrap();
+        let cores: HashSet<u64> = sample.iter().map(|config| config[0].1).collect();
+        assert_eq!(cores.len(), 8);
+
+        let corners = SamplingPlan::CornersAndCenter
+            .configurations(&domains)
+            .unwrap();
+        assert_eq!(corners.len(), 9);
+        assert_eq!(
+            corners.last().unwrap(),
+            &vec![
+                ("cores".to_string(), 4),
+                ("frequency".to_string(), 1600),
+                ("step".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_plan() {
+        std::fs::write(
+            "/tmp/apto_plan.kt",
+            "step,cores,frequency\n4,2,800\n1,8,2400\n",
+        )
+        .unwrap();
+        let plan: SamplingPlan = "file:/tmp/apto_plan.kt".parse().unwrap();
+        let configs = plan.configurations(&domains()).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0][0], ("cores".to_string(), 2));
+        assert_eq!(configs[1][2], ("step".to_string(), 1));
+
+        std::fs::write("/tmp/apto_plan.kt", "step,cores\n3,2\n").unwrap();
+        assert!(plan.configurations(&domains()).is_err());
+        std::fs::remove_file("/tmp/apto_plan.kt").unwrap();
+    }
+}
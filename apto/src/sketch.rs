@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Relative error of the quantiles reported by a default sketch.
+const DEFAULT_ACCURACY: f64 = 0.01;
+/// Buckets a default sketch keeps before it merges the ones closest to zero.
+/// With 1% accuracy this covers values that are more than 10^17 apart.
+const DEFAULT_MAX_BUCKETS: usize = 2048;
+
+/// Quantiles of a stream of values in bounded memory. Values are counted in
+/// logarithmically sized buckets, so every quantile is reported within a
+/// relative error of `accuracy` of a value of the stream. When more than
+/// `max_buckets` are used, the buckets closest to zero are merged, which
+/// keeps the tail accurate. Sketches with the same accuracy can be merged,
+/// e.g. to get the latency percentiles over several instances.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantileSketch {
+    accuracy: f64,
+    max_buckets: usize,
+    /// Counts of positive values by bucket index.
+    positive: BTreeMap<i32, u64>,
+    /// Counts of negative values by the bucket index of their magnitude.
+    negative: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> QuantileSketch {
+        QuantileSketch::new(DEFAULT_ACCURACY, DEFAULT_MAX_BUCKETS)
+    }
+}
+
+impl QuantileSketch {
+    /// `accuracy` is the relative error in (0, 1).
+    pub fn new(accuracy: f64, max_buckets: usize) -> QuantileSketch {
+        assert!(
+            accuracy > 0.0 && accuracy < 1.0,
+            "Sketch accuracy must be in (0, 1) but is {}",
+            accuracy
+        );
+        QuantileSketch {
+            accuracy,
+            max_buckets: max_buckets.max(1),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn gamma_ln(&self) -> f64 {
+        ((1.0 + self.accuracy) / (1.0 - self.accuracy)).ln()
+    }
+
+    fn index(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.gamma_ln()).ceil() as i32
+    }
+
+    /// Value that represents every value in the bucket.
+    fn bucket_value(&self, index: i32) -> f64 {
+        let gamma_ln = self.gamma_ln();
+        2.0 * (index as f64 * gamma_ln).exp() / (gamma_ln.exp() + 1.0)
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        if value > f64::MIN_POSITIVE {
+            *self.positive.entry(self.index(value)).or_insert(0) += 1;
+        } else if value < -f64::MIN_POSITIVE {
+            *self.negative.entry(self.index(-value)).or_insert(0) += 1;
+        } else {
+            self.zeros += 1;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.collapse();
+    }
+
+    /// Adds the values counted by `other`, which needs the same accuracy.
+    pub fn merge(&mut self, other: &QuantileSketch) -> Result<(), String> {
+        if self.accuracy != other.accuracy {
+            return Err(format!(
+                "cannot merge a sketch with accuracy {} into one with accuracy {}",
+                other.accuracy, self.accuracy
+            ));
+        }
+        for (index, count) in other.positive.iter() {
+            *self.positive.entry(*index).or_insert(0) += count;
+        }
+        for (index, count) in other.negative.iter() {
+            *self.negative.entry(*index).or_insert(0) += count;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.collapse();
+        Ok(())
+    }
+
+    /// Merges the buckets closest to zero until at most `max_buckets` are
+    /// left.
+    fn collapse(&mut self) {
+        while self.positive.len() + self.negative.len() > self.max_buckets {
+            // Positive values first, as the tail of latencies matters most
+            let store = if self.positive.len() > 1 {
+                &mut self.positive
+            } else {
+                &mut self.negative
+            };
+            let Some((_, count)) = store.pop_first() else {
+                return;
+            };
+            match store.first_entry() {
+                Some(mut next) => *next.get_mut() += count,
+                None => self.zeros += count,
+            }
+        }
+    }
+
+    /// The value at `quantile` in [0, 1], or NaN if the sketch is empty.
+    pub fn quantile(&self, quantile: f64) -> f64 {
+        if self.count == 0 {
+            return f64::NAN;
+        } else if quantile <= 0.0 {
+            return self.min;
+        } else if quantile >= 1.0 {
+            return self.max;
+        }
+        let rank = quantile * ((self.count - 1) as f64);
+        let mut seen = 0;
+        // Ascending order: large negative magnitudes, zeros, positive values
+        let buckets = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(index, count)| (-self.bucket_value(*index), *count))
+            .chain(std::iter::once((0.0, self.zeros)))
+            .chain(
+                self.positive
+                    .iter()
+                    .map(|(index, count)| (self.bucket_value(*index), *count)),
+            );
+        for (value, count) in buckets {
+            seen += count;
+            if seen as f64 > rank {
+                return value.clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Number of values inserted, including merged ones.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        self.accuracy
+    }
+
+    pub fn clear(&mut self) {
+        self.positive.clear();
+        self.negative.clear();
+        self.zeros = 0;
+        self.count = 0;
+        self.min = f64::INFINITY;
+        self.max = f64::NEG_INFINITY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuantileSketch;
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() <= 0.01 * expected.abs(),
+            "{} is not within 1% of {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn sketch_quantiles() {
+        let mut sketch = QuantileSketch::default();
+        assert!(sketch.quantile(0.5).is_nan());
+        for value in 1..=10000 {
+            sketch.insert(value as f64);
+        }
+        assert_eq!(sketch.count(), 10000);
+        assert_close(sketch.quantile(0.5), 5000.0);
+        assert_close(sketch.quantile(0.99), 9900.0);
+        assert_eq!(sketch.quantile(0.0), 1.0);
+        assert_eq!(sketch.quantile(1.0), 10000.0);
+
+        let mut signed = QuantileSketch::default();
+        for value in [-3.0, -1.0, 0.0, 2.0, 4.0] {
+            signed.insert(value);
+        }
+        assert_close(signed.quantile(0.25), -1.0);
+        assert_eq!(signed.quantile(0.5), 0.0);
+        assert_close(signed.quantile(0.75), 2.0);
+    }
+
+    #[test]
+    fn merge_and_bound_sketches() {
+        let (mut low, mut high) = (QuantileSketch::default(), QuantileSketch::default());
+        for value in 1..=500 {
+            low.insert(value as f64);
+            high.insert((value + 500) as f64);
+        }
+        low.merge(&high).unwrap();
+        assert_eq!(low.count(), 1000);
+        assert_close(low.quantile(0.9), 900.0);
+        assert!(low.merge(&QuantileSketch::new(0.05, 10)).is_err());
+
+        let mut bounded = QuantileSketch::new(0.01, 16);
+        for value in 1..=100000 {
+            bounded.insert(value as f64);
+        }
+        assert_eq!(bounded.positive.len(), 16);
+        assert_close(bounded.quantile(0.999), 99900.0);
+    }
+}
@@ -1,9 +1,13 @@
 use energy_monitor::EnergyMonitor;
 use energymon::EnergyMon as EM;
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::Instant;
 
+type Reading = Result<u64, &'static str>;
+
 pub struct Energymon {
-    em: EM,
+    requests: Sender<()>,
+    readings: Receiver<Reading>,
     start_energy: u64,
     end_energy: u64,
     start_time: Instant,
@@ -11,10 +15,39 @@ pub struct Energymon {
 }
 
 impl Energymon {
+    /// The monitor itself stays on a thread of its own, which reads it on
+    /// request: the energymon backends keep C state that is not meant to be
+    /// used from another thread, while an `Apto` instance may be moved.
     pub fn new() -> Energymon {
-        let em = EM::new().expect("Could not initialize Energy Monitor.");
+        let (requests, pending) = mpsc::channel::<()>();
+        let (reply, readings) = mpsc::channel();
+        let (ready, initialized) = mpsc::sync_channel(1);
+        std::thread::Builder::new()
+            .name("energymon".to_string())
+            .spawn(move || {
+                let em = match EM::new() {
+                    Ok(em) => em,
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready.send(Ok(()));
+                // Ends with the `Energymon` that holds the other side
+                for () in pending {
+                    if reply.send(em.read_uj()).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Could not start the Energy Monitor thread.");
+        initialized
+            .recv()
+            .expect("The Energy Monitor thread exited.")
+            .expect("Could not initialize Energy Monitor.");
         Energymon {
-            em,
+            requests,
+            readings,
             start_energy: 0,
             end_energy: 0,
             start_time: Instant::now(),
@@ -22,15 +55,25 @@ impl Energymon {
         }
     }
 
+    fn read_uj(&self) -> u64 {
+        self.requests
+            .send(())
+            .expect("The Energy Monitor thread exited.");
+        self.readings
+            .recv()
+            .expect("The Energy Monitor thread exited.")
+            .expect("Could not read energy.")
+    }
+
     pub fn start(&mut self) {
         self.reset();
 
-        self.start_energy = self.em.read_uj().expect("Could not read energy.");
+        self.start_energy = self.read_uj();
         self.start_time = Instant::now();
     }
 
     pub fn stop(&mut self) {
-        self.end_energy = self.em.read_uj().expect("Could not read energy.");
+        self.end_energy = self.read_uj();
         self.end_time = Instant::now();
     }
 
@@ -64,7 +107,7 @@ impl Energymon {
     }
 
     pub fn current_energy(&self) -> f64 {
-        self.em.read_uj().expect("Could not read energy") as f64
+        self.read_uj() as f64
     }
 
     pub fn duration(&self) -> Result<f64, &str> {
@@ -0,0 +1,554 @@
+use log::warn;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Magic bytes at the start of a binary telemetry file.
+const BINARY_MAGIC: &[u8; 8] = b"APTOTEL2";
+
+/// Tags of the entries of a binary telemetry file.
+const BINARY_RECORD: u8 = 0;
+const BINARY_EVENT: u8 = 1;
+
+/// The state of an instance after one iteration.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TelemetryRecord<'a> {
+    pub instance_id: usize,
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    pub iteration: u64,
+    pub window: u64,
+    /// Knob table id of the active configuration, if it is one of the profile.
+    pub configuration: Option<u64>,
+    /// One value per column the sink was started with, NaN if not measured yet.
+    pub values: &'a [f64],
+}
+
+/// Something that happened to an instance between two iterations, e.g. a
+/// change of its goal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TelemetryEvent<'a> {
+    pub instance_id: usize,
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    pub iteration: u64,
+    pub window: u64,
+    pub description: &'a str,
+}
+
+/// Receives a record for every iteration of an Apto instance. Sinks are `Send`
+/// so that the instance can be moved to another thread.
+pub trait TelemetrySink: Send {
+    /// Called once before the first record with the names of the recorded
+    /// measures and knobs.
+    fn start(&mut self, instance_id: usize, columns: &[String]) -> std::io::Result<()>;
+
+    fn record(&mut self, record: &TelemetryRecord) -> std::io::Result<()>;
+
+    fn event(&mut self, event: &TelemetryEvent) -> std::io::Result<()>;
+
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+/// Comma separated values with a header line.
+pub struct CsvSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> CsvSink<W> {
+        CsvSink { writer }
+    }
+}
+
+impl CsvSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(CsvSink::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> TelemetrySink for CsvSink<W> {
+    fn start(&mut self, _instance_id: usize, columns: &[String]) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "instance,timestamp,iteration,window,configuration,{}",
+            columns.join(",")
+        )
+    }
+
+    fn record(&mut self, record: &TelemetryRecord) -> std::io::Result<()> {
+        let configuration = record
+            .configuration
+            .map(|id| id.to_string())
+            .unwrap_or_default();
+        write!(
+            self.writer,
+            "{},{},{},{},{}",
+            record.instance_id, record.timestamp, record.iteration, record.window, configuration
+        )?;
+        for value in record.values {
+            write!(self.writer, ",{}", value)?;
+        }
+        writeln!(self.writer)
+    }
+
+    /// Written as a comment line, `# <instance>,<timestamp>,<iteration>,<window>,<description>`.
+    fn event(&mut self, event: &TelemetryEvent) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "# {},{},{},{},{}",
+            event.instance_id, event.timestamp, event.iteration, event.window, event.description
+        )
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// One JSON object per line with the values keyed by column name. Values
+/// that were not measured yet are null.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    columns: Vec<String>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> JsonLinesSink<W> {
+        JsonLinesSink {
+            writer,
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl JsonLinesSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(JsonLinesSink::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> TelemetrySink for JsonLinesSink<W> {
+    fn start(&mut self, _instance_id: usize, columns: &[String]) -> std::io::Result<()> {
+        self.columns = columns.to_vec();
+        Ok(())
+    }
+
+    fn record(&mut self, record: &TelemetryRecord) -> std::io::Result<()> {
+        let values: serde_json::Map<String, serde_json::Value> = self
+            .columns
+            .iter()
+            .zip(record.values.iter())
+            .map(|(name, &value)| (name.clone(), serde_json::json!(value)))
+            .collect();
+        let line = serde_json::json!({
+            "instance": record.instance_id,
+            "timestamp": record.timestamp,
+            "iteration": record.iteration,
+            "window": record.window,
+            "configuration": record.configuration,
+            "values": values,
+        });
+        serde_json::to_writer(&mut self.writer, &line)?;
+        writeln!(self.writer)
+    }
+
+    /// Written as an object with an `event` instead of `values`.
+    fn event(&mut self, event: &TelemetryEvent) -> std::io::Result<()> {
+        let line = serde_json::json!({
+            "instance": event.instance_id,
+            "timestamp": event.timestamp,
+            "iteration": event.iteration,
+            "window": event.window,
+            "event": event.description,
+        });
+        serde_json::to_writer(&mut self.writer, &line)?;
+        writeln!(self.writer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Serialize)]
+struct BinarySchema<'a> {
+    instance: usize,
+    /// Fields of every record in order, all little endian.
+    record: Vec<(&'a str, &'static str)>,
+    /// Fields of every event in order, the description as its length in
+    /// bytes followed by the UTF-8 text.
+    event: Vec<(&'static str, &'static str)>,
+}
+
+/// Little-endian entries after a header that describes them: the magic
+/// `APTOTEL2`, the length of the schema as a little-endian u32 and the schema
+/// as JSON. Every entry starts with a tag byte, 0 for a record and 1 for an
+/// event. A missing configuration is stored as `u64::MAX`.
+pub struct BinarySink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinarySink<W> {
+    pub fn new(writer: W) -> BinarySink<W> {
+        BinarySink { writer }
+    }
+}
+
+impl BinarySink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(BinarySink::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> TelemetrySink for BinarySink<W> {
+    fn start(&mut self, instance_id: usize, columns: &[String]) -> std::io::Result<()> {
+        let schema = BinarySchema {
+            instance: instance_id,
+            record: [
+                ("timestamp", "f64"),
+                ("iteration", "u64"),
+                ("window", "u64"),
+                ("configuration", "u64"),
+            ]
+            .into_iter()
+            .chain(columns.iter().map(|name| (name.as_str(), "f64")))
+            .collect(),
+            event: vec![
+                ("timestamp", "f64"),
+                ("iteration", "u64"),
+                ("window", "u64"),
+                ("description", "u32 length, utf8"),
+            ],
+        };
+        let schema = serde_json::to_vec(&schema)?;
+        self.writer.write_all(BINARY_MAGIC)?;
+        self.writer
+            .write_all(&(schema.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&schema)
+    }
+
+    fn record(&mut self, record: &TelemetryRecord) -> std::io::Result<()> {
+        self.writer.write_all(&[BINARY_RECORD])?;
+        self.writer.write_all(&record.timestamp.to_le_bytes())?;
+        self.writer.write_all(&record.iteration.to_le_bytes())?;
+        self.writer.write_all(&record.window.to_le_bytes())?;
+        self.writer
+            .write_all(&record.configuration.unwrap_or(u64::MAX).to_le_bytes())?;
+        for value in record.values {
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &TelemetryEvent) -> std::io::Result<()> {
+        let description = event.description.as_bytes();
+        self.writer.write_all(&[BINARY_EVENT])?;
+        self.writer.write_all(&event.timestamp.to_le_bytes())?;
+        self.writer.write_all(&event.iteration.to_le_bytes())?;
+        self.writer.write_all(&event.window.to_le_bytes())?;
+        self.writer
+            .write_all(&(description.len() as u32).to_le_bytes())?;
+        self.writer.write_all(description)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// File formats of the sinks that can be selected with `TELEMETRY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryFormat {
+    Csv,
+    JsonLines,
+    Binary,
+}
+
+impl TelemetryFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TelemetryFormat::Csv => "csv",
+            TelemetryFormat::JsonLines => "jsonl",
+            TelemetryFormat::Binary => "bin",
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> std::io::Result<Box<dyn TelemetrySink>> {
+        Ok(match self {
+            TelemetryFormat::Csv => Box::new(CsvSink::create(path)?),
+            TelemetryFormat::JsonLines => Box::new(JsonLinesSink::create(path)?),
+            TelemetryFormat::Binary => Box::new(BinarySink::create(path)?),
+        })
+    }
+}
+
+impl FromStr for TelemetryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(TelemetryFormat::Csv),
+            "jsonl" | "json" => Ok(TelemetryFormat::JsonLines),
+            "binary" | "bin" => Ok(TelemetryFormat::Binary),
+            _ => Err(format!("unknown telemetry format '{}'", s)),
+        }
+    }
+}
+
+/// Which measures and knobs are recorded, and where to.
+pub struct Telemetry {
+    pub measures: Vec<String>,
+    pub knobs: Vec<String>,
+    sinks: Vec<Box<dyn TelemetrySink>>,
+    started: bool,
+}
+
+impl Telemetry {
+    pub fn new(measures: Vec<String>, knobs: Vec<String>) -> Telemetry {
+        Telemetry {
+            measures,
+            knobs,
+            sinks: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// What the runtime system used to dump unconditionally.
+    pub fn legacy() -> Telemetry {
+        Telemetry::new(
+            [
+                "appLatency",
+                "numCores",
+                "powerConsumption",
+                "err0",
+                "err1",
+                "perf0",
+                "perf1",
+                "harmonicMeanPerf",
+            ]
+            .map(String::from)
+            .to_vec(),
+            ["utilizedCoreFrequency", "uncoreFrequency", "hyperthreading"]
+                .map(String::from)
+                .to_vec(),
+        )
+    }
+
+    pub fn sink<S: TelemetrySink + 'static>(mut self, sink: S) -> Telemetry {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn boxed_sink(mut self, sink: Box<dyn TelemetrySink>) -> Telemetry {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Measures first, then knobs.
+    pub(crate) fn columns(&self) -> Vec<String> {
+        self.measures
+            .iter()
+            .chain(self.knobs.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Hands one iteration to every sink. A sink that fails is dropped so
+    /// that a full disk does not stop the application.
+    pub(crate) fn write(
+        &mut self,
+        instance_id: usize,
+        iteration: u64,
+        window: u64,
+        configuration: Option<u64>,
+        values: &[f64],
+    ) {
+        self.start(instance_id);
+        let record = TelemetryRecord {
+            instance_id,
+            timestamp: now(),
+            iteration,
+            window,
+            configuration,
+            values,
+        };
+        self.sinks.retain_mut(|sink| match sink.record(&record) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping telemetry sink: {} (instance {})", e, instance_id);
+                false
+            }
+        });
+    }
+
+    pub(crate) fn event(
+        &mut self,
+        instance_id: usize,
+        iteration: u64,
+        window: u64,
+        description: &str,
+    ) {
+        self.start(instance_id);
+        let event = TelemetryEvent {
+            instance_id,
+            timestamp: now(),
+            iteration,
+            window,
+            description,
+        };
+        self.sinks.retain_mut(|sink| match sink.event(&event) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping telemetry sink: {} (instance {})", e, instance_id);
+                false
+            }
+        });
+    }
+
+    fn start(&mut self, instance_id: usize) {
+        if self.started {
+            return;
+        }
+        let columns = self.columns();
+        self.sinks
+            .retain_mut(|sink| match sink.start(instance_id, &columns) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Dropping telemetry sink: {} (instance {})", e, instance_id);
+                    false
+                }
+            });
+        self.started = true;
+    }
+
+    pub(crate) fn flush(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.flush() {
+                warn!("Could not flush telemetry: {}", e);
+            }
+        }
+    }
+}
+
+/// Seconds since the UNIX epoch.
+fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BinarySink, CsvSink, JsonLinesSink, TelemetryEvent, TelemetryRecord, TelemetrySink,
+    };
+    use crate::profile::ProfileError;
+    use crate::testing::{builder, env_lock, step};
+    use crate::Apto;
+
+    fn write_records<S: TelemetrySink>(sink: &mut S) {
+        let columns = ["latency", "cores"].map(String::from);
+        sink.start(2, &columns).unwrap();
+        for (iteration, values) in [[0.5, 4.0], [f64::NAN, 2.0]].iter().enumerate() {
+            sink.record(&TelemetryRecord {
+                instance_id: 2,
+                timestamp: 10.0 + iteration as f64,
+                iteration: iteration as u64,
+                window: 0,
+                configuration: (iteration == 1).then_some(7),
+                values,
+            })
+            .unwrap();
+        }
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn telemetry_formats() {
+        let mut csv = CsvSink::new(Vec::new());
+        write_records(&mut csv);
+        assert_eq!(
+            String::from_utf8(csv.writer).unwrap(),
+            "instance,timestamp,iteration,window,configuration,latency,cores\n\
+             2,10,0,0,,0.5,4\n\
+             2,11,1,0,7,NaN,2\n"
+        );
+
+        let mut json = JsonLinesSink::new(Vec::new());
+        write_records(&mut json);
+        let lines: Vec<serde_json::Value> = String::from_utf8(json.writer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["values"]["cores"], 4.0);
+        assert!(lines[0]["configuration"].is_null());
+        assert!(lines[1]["values"]["latency"].is_null());
+        assert_eq!(lines[1]["configuration"], 7);
+
+        let mut binary = BinarySink::new(Vec::new());
+        write_records(&mut binary);
+        let bytes = binary.writer;
+        assert_eq!(&bytes[..8], b"APTOTEL2");
+        let schema_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let schema: serde_json::Value =
+            serde_json::from_slice(&bytes[12..12 + schema_len]).unwrap();
+        assert_eq!(schema["record"][5][0], "cores");
+        let records = &bytes[12 + schema_len..];
+        // A tag, timestamp, iteration, window, configuration and two values
+        assert_eq!(records.len(), 2 * (1 + 6 * 8));
+        assert_eq!(records[49], 0);
+        assert_eq!(u64::from_le_bytes(records[74..82].try_into().unwrap()), 7);
+        assert_eq!(f64::from_le_bytes(records[90..98].try_into().unwrap()), 2.0);
+    }
+
+    #[test]
+    fn telemetry_events() {
+        let event = TelemetryEvent {
+            instance_id: 1,
+            timestamp: 12.0,
+            iteration: 40,
+            window: 2,
+            description: "constraint value: 400000",
+        };
+        let mut csv = CsvSink::new(Vec::new());
+        csv.event(&event).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.writer).unwrap(),
+            "# 1,12,40,2,constraint value: 400000\n"
+        );
+
+        let mut json = JsonLinesSink::new(Vec::new());
+        json.event(&event).unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&json.writer).unwrap();
+        assert_eq!(line["event"], "constraint value: 400000");
+        assert_eq!(line["window"], 2);
+
+        let mut binary = BinarySink::new(Vec::new());
+        binary.event(&event).unwrap();
+        let bytes = binary.writer;
+        assert_eq!(bytes[0], 1);
+        assert_eq!(u64::from_le_bytes(bytes[9..17].try_into().unwrap()), 40);
+        assert_eq!(u32::from_le_bytes(bytes[25..29].try_into().unwrap()), 24);
+        assert_eq!(&bytes[29..], b"constraint value: 400000");
+    }
+
+    #[test]
+    fn reject_invalid_format_from_environment() {
+        let _env = env_lock();
+        std::env::set_var("TELEMETRY", "xml");
+        let config = builder(&step()).env_overrides(true).build().unwrap();
+        assert!(matches!(
+            Apto::try_new(config),
+            Err(ProfileError::InvalidSetting {
+                setting: "TELEMETRY",
+                ..
+            })
+        ));
+        std::env::remove_var("TELEMETRY");
+    }
+}
@@ -0,0 +1,113 @@
+//! Fixtures for the tests that drive whole instances.
+use crate::configurations::ConfigurationsBuilder;
+use crate::knobs::ApplicationKnob;
+use crate::profile::{KnobTable, MeasureTable};
+use crate::{Apto, AptoConfig, Goal, OptimizationType, ProfileRun, Tunable};
+use std::rc::Rc;
+use std::sync::{Mutex, MutexGuard};
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Held by the tests that set environment variables, since every instance
+/// with `env_overrides` reads them.
+pub(crate) fn env_lock() -> MutexGuard<'static, ()> {
+    ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Two configurations of `step`: 1 keeps the full quality for 400
+/// operations, 4 a quarter of it for 100.
+pub(crate) fn tables() -> (MeasureTable, KnobTable) {
+    let knob_table = KnobTable::from_rows(
+        vec!["id".to_string(), "step".to_string()],
+        vec![vec![0, 1], vec![1, 4]],
+    )
+    .unwrap();
+    let measure_table = MeasureTable::from_rows(
+        ["id", "quality", "operations"].map(String::from).to_vec(),
+        vec![vec![0.0, 1.0, 400.0], vec![1.0, 0.25, 100.0]],
+    )
+    .unwrap();
+    (measure_table, knob_table)
+}
+
+/// The tables of `tables` with every measure profiling reports, 0 for the
+/// first configuration and 1 for the second.
+pub(crate) fn profiling_tables() -> (MeasureTable, KnobTable) {
+    let measure_table = MeasureTable::from_rows(
+        [
+            "id",
+            "quality",
+            "operations",
+            "latency",
+            "performance",
+            "energyDelta",
+            "windowLatency",
+            "powerConsumption",
+        ]
+        .map(String::from)
+        .to_vec(),
+        vec![vec![0.0; 8], vec![1.0; 8]],
+    )
+    .unwrap();
+    (measure_table, tables().1)
+}
+
+pub(crate) fn step() -> Rc<dyn Tunable<u64>> {
+    Rc::new(ApplicationKnob::new(
+        "step".to_string(),
+        vec![1, 4],
+        1,
+        None,
+    ))
+}
+
+/// Keeps the quality at 0.5 with as many operations as possible.
+pub(crate) fn goal() -> Goal {
+    Goal::new(
+        "quality".to_string(),
+        0.5,
+        OptimizationType::Maximize,
+        "operations".to_string(),
+    )
+}
+
+/// An instance of `tables` tuning `step` for `goal` in windows of 20
+/// iterations, which neither reads the environment nor logs every window.
+pub(crate) fn builder(step: &Rc<dyn Tunable<u64>>) -> ConfigurationsBuilder<u64> {
+    let (measure_table, knob_table) = tables();
+    AptoConfig::builder()
+        .profile(measure_table, knob_table)
+        .knob(step.clone())
+        .goal(goal())
+        .window_size(20)
+        .env_overrides(false)
+        .log_state(false)
+}
+
+/// Runs every configuration `config` profiles, reporting the measures of
+/// each iteration with `record`, and reads back the measure table.
+pub(crate) fn profile<F>(config: AptoConfig<u64>, mut record: F) -> MeasureTable
+where
+    F: FnMut(&mut Apto, &ProfileRun),
+{
+    let measure_table = config.output.measure_table.clone();
+    let mut apto = Apto::try_new(config).unwrap();
+    apto.start();
+    let runs: Vec<ProfileRun> = apto.profile_runs().collect();
+    for run in runs {
+        apto.start_profile_run(&run);
+        for _ in 0..(run.warmup + run.iterations) {
+            record(&mut apto, &run);
+            apto.end_iteration();
+        }
+        apto.end_profile_run(&run);
+    }
+    assert_eq!(apto.profile_runs().count(), 0);
+    apto.stop();
+    MeasureTable::try_new(&measure_table).unwrap()
+}
+
+/// Index of the column `name` of `table`.
+pub(crate) fn column(table: &MeasureTable, name: &str) -> usize {
+    table.names.iter().position(|other| other == name).unwrap()
+}
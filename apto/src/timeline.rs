@@ -0,0 +1,257 @@
+use crate::goal::OptimizationTypeDef;
+use crate::profile::ProfileError;
+use crate::window::parse_duration;
+use crate::Goal;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
+use OptimizingController::OptimizationType;
+
+/// When a timeline entry is applied, counted from `Apto::start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    After(Duration),
+    /// Once this many windows have ended.
+    Window(u64),
+    /// Once this many iterations have run.
+    Iteration(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoalChange {
+    Target(f64),
+    Objective {
+        /// Keeps the current direction if not given.
+        opt_type: Option<OptimizationType>,
+        opt_func: String,
+    },
+    Goal(Goal),
+}
+
+impl GoalChange {
+    pub(crate) fn apply_to(&self, goal: &Goal) -> Goal {
+        match self {
+            GoalChange::Target(target) => Goal {
+                target: *target,
+                ..goal.clone()
+            },
+            GoalChange::Objective { opt_type, opt_func } => Goal {
+                opt_type: opt_type.unwrap_or(goal.opt_type),
+                opt_func: opt_func.clone(),
+                ..goal.clone()
+            },
+            GoalChange::Goal(goal) => goal.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    pub trigger: Trigger,
+    pub change: GoalChange,
+}
+
+/// Goal changes that an instance applies on its own during a run, e.g. for
+/// experiments that change the SLO. Read from YAML, one entry per change
+/// with exactly one trigger (`at`, `window` or `iteration`) and one change
+/// (`target`, `objective` or a whole `goal`):
+///
+/// ```yaml
+/// - at: 60s
+///   target: 400000
+/// - window: 300
+///   objective: powerConsumption
+///   opt_type: Minimize
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GoalTimeline {
+    entries: Vec<TimelineEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEntry {
+    at: Option<String>,
+    window: Option<u64>,
+    iteration: Option<u64>,
+    target: Option<f64>,
+    objective: Option<String>,
+    opt_type: Option<RawOptimizationType>,
+    goal: Option<Goal>,
+}
+
+/// Spelled `Minimize` or `Maximize`, as in a goal file.
+#[derive(Deserialize)]
+struct RawOptimizationType(#[serde(with = "OptimizationTypeDef")] OptimizationType);
+
+impl RawEntry {
+    fn parse(self) -> Result<TimelineEntry, String> {
+        let trigger = match (self.at, self.window, self.iteration) {
+            (Some(at), None, None) => Trigger::After(
+                parse_duration(&at).ok_or_else(|| format!("invalid duration '{}'", at))?,
+            ),
+            (None, Some(window), None) => Trigger::Window(window),
+            (None, None, Some(iteration)) => Trigger::Iteration(iteration),
+            _ => return Err("expected exactly one of at, window and iteration".to_string()),
+        };
+        let opt_type = self.opt_type.map(|RawOptimizationType(opt_type)| opt_type);
+        let change = match (self.target, self.objective, self.goal) {
+            (Some(target), None, None) if opt_type.is_none() => GoalChange::Target(target),
+            (None, Some(opt_func), None) => GoalChange::Objective { opt_type, opt_func },
+            (None, None, Some(goal)) if opt_type.is_none() => GoalChange::Goal(goal),
+            _ => return Err(
+                "expected exactly one of target, objective (with an optional opt_type) and goal"
+                    .to_string(),
+            ),
+        };
+        Ok(TimelineEntry { trigger, change })
+    }
+}
+
+impl GoalTimeline {
+    pub fn new(entries: Vec<TimelineEntry>) -> GoalTimeline {
+        GoalTimeline { entries }
+    }
+
+    pub fn load<P: AsRef<Path> + Display>(path: P) -> Result<GoalTimeline, ProfileError> {
+        let content = std::fs::read_to_string(&path).map_err(|source| ProfileError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        GoalTimeline::from_yaml(&content).map_err(|message| ProfileError::Timeline {
+            path: path.to_string(),
+            message,
+        })
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<GoalTimeline, String> {
+        let entries: Vec<RawEntry> = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(idx, entry)| entry.parse().map_err(|e| format!("entry {}: {}", idx, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(GoalTimeline { entries })
+    }
+
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    /// Removes and returns the changes whose trigger fired, in timeline order.
+    pub(crate) fn due(
+        &mut self,
+        elapsed: Duration,
+        windows: u64,
+        iterations: u64,
+    ) -> Vec<GoalChange> {
+        let fired = |trigger: &Trigger| match *trigger {
+            Trigger::After(after) => elapsed >= after,
+            Trigger::Window(window) => windows >= window,
+            Trigger::Iteration(iteration) => iterations >= iteration,
+        };
+        let (due, pending) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| fired(&entry.trigger));
+        self.entries = pending;
+        due.into_iter()
+            .map(|entry: TimelineEntry| entry.change)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GoalChange, GoalTimeline, Trigger};
+    use crate::profile::ProfileError;
+    use crate::testing::{builder, step};
+    use crate::{Apto, Goal, JsonLinesSink, OperatingMode, Telemetry};
+    use std::time::Duration;
+    use OptimizingController::OptimizationType;
+
+    #[test]
+    fn parse_and_fire_timeline() {
+        let mut timeline = GoalTimeline::from_yaml(
+            r#"
+- at: 60s
+  target: 400000
+- window: 300
+  objective: powerConsumption
+  opt_type: Minimize
+- iteration: 10
+  goal:
+    constraint: latency
+    target: 0.5
+    opt_type: Maximize
+    opt_func: quality
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            timeline.entries()[0].trigger,
+            Trigger::After(Duration::from_secs(60))
+        );
+        assert_eq!(timeline.entries()[1].trigger, Trigger::Window(300));
+
+        let goal = Goal::new(
+            "latency".to_string(),
+            1.0,
+            OptimizationType::Maximize,
+            "quality".to_string(),
+        );
+        let due = timeline.due(Duration::from_secs(61), 300, 5);
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0], GoalChange::Target(400000.0));
+        let changed = due[1].apply_to(&goal);
+        assert_eq!(changed.opt_type, OptimizationType::Minimize);
+        assert_eq!(changed.opt_func, "powerConsumption");
+        assert_eq!(timeline.entries().len(), 1);
+        assert!(timeline.due(Duration::from_secs(62), 301, 9).is_empty());
+        assert_eq!(timeline.due(Duration::from_secs(63), 301, 10).len(), 1);
+
+        assert!(GoalTimeline::from_yaml("- at: 1s\n  window: 2\n  target: 1").is_err());
+        assert!(GoalTimeline::from_yaml("- at: 1s\n  target: 1\n  opt_type: Minimize").is_err());
+        assert!(GoalTimeline::from_yaml("- at: 1s\n  objective: x\n  opt_type: minimize").is_err());
+        assert!(GoalTimeline::from_yaml("- at: 1s\n  speedup: 2").is_err());
+    }
+
+    #[test]
+    fn apply_goal_timeline() {
+        let path = "/tmp/apto_goal_timeline.jsonl";
+        let timeline = GoalTimeline::from_yaml("- iteration: 3\n  target: 0.75").unwrap();
+        let config = builder(&step())
+            .mode(OperatingMode::NonAdaptive)
+            .telemetry(Telemetry::new(vec![], vec![]).sink(JsonLinesSink::create(path).unwrap()))
+            .goal_timeline(timeline)
+            .build()
+            .unwrap();
+        let mut apto = Apto::try_new(config).unwrap();
+        apto.start();
+        for _ in 0..5 {
+            apto.record("operations", 100.0);
+            apto.end_iteration();
+        }
+        apto.stop();
+
+        let events: Vec<serde_json::Value> = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|line| line.get("event").is_some())
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["iteration"], 3);
+        assert_eq!(
+            events[0]["event"],
+            "goal perturbation: constraint value: 0.75"
+        );
+
+        let unknown = GoalTimeline::from_yaml("- window: 1\n  objective: energy").unwrap();
+        let config = builder(&step()).goal_timeline(unknown).build().unwrap();
+        assert!(matches!(
+            Apto::try_new(config),
+            Err(ProfileError::MissingMeasure { .. })
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::profile::{KnobTable, MeasureTable};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileIssue {
+    MissingIdColumn(&'static str),
+    InvalidId {
+        row: usize,
+        value: f64,
+    },
+    DuplicateId {
+        table: &'static str,
+        id: u64,
+    },
+    MissingKnobRow(u64),
+    MissingMeasureRow(u64),
+    DuplicateConfiguration {
+        first: u64,
+        second: u64,
+    },
+    NonFiniteValue {
+        id: u64,
+        measure: String,
+        value: f64,
+    },
+    UnregisteredKnob(String),
+    UnprofiledKnob(String),
+}
+
+impl ProfileIssue {
+    /// Knobs registered with Apto but absent from the knob table are tolerated
+    /// because they are never actuated from the profile (e.g. hyperthreading).
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ProfileIssue::UnprofiledKnob(_))
+    }
+}
+
+impl std::fmt::Display for ProfileIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ProfileIssue::MissingIdColumn(table) => write!(f, "{} has no id column", table),
+            ProfileIssue::InvalidId { row, value } => {
+                write!(f, "measure table row {} has invalid id {}", row, value)
+            }
+            ProfileIssue::DuplicateId { table, id } => write!(f, "{} repeats id {}", table, id),
+            ProfileIssue::MissingKnobRow(id) => {
+                write!(
+                    f,
+                    "id {} is in the measure table but not the knob table",
+                    id
+                )
+            }
+            ProfileIssue::MissingMeasureRow(id) => {
+                write!(
+                    f,
+                    "id {} is in the knob table but not the measure table",
+                    id
+                )
+            }
+            ProfileIssue::DuplicateConfiguration { first, second } => write!(
+                f,
+                "ids {} and {} have the same knob configuration",
+                first, second
+            ),
+            ProfileIssue::NonFiniteValue { id, measure, value } => {
+                write!(f, "id {} has non-finite {} ({})", id, measure, value)
+            }
+            ProfileIssue::UnregisteredKnob(name) => {
+                write!(f, "knob table column {} has no registered knob", name)
+            }
+            ProfileIssue::UnprofiledKnob(name) => {
+                write!(f, "registered knob {} is not in the knob table", name)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub issues: Vec<ProfileIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        !self.issues.iter().any(ProfileIssue::is_fatal)
+    }
+
+    pub fn fatal_issues(&self) -> impl Iterator<Item = &ProfileIssue> {
+        self.issues.iter().filter(|issue| issue.is_fatal())
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{} profile issue(s)", self.issues.len())?;
+        for issue in self.issues.iter() {
+            write!(f, "\n  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Joins the measure table and the knob table on their `id` column and
+/// reports every row or column that would make the two disagree.
+pub fn validate_profile<I, S>(
+    measure_table: &MeasureTable,
+    knob_table: &KnobTable,
+    knob_names: I,
+) -> ValidationReport
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut issues = Vec::new();
+
+    let mt_id_idx = measure_table.names.iter().position(|name| name == "id");
+    if mt_id_idx.is_none() {
+        issues.push(ProfileIssue::MissingIdColumn("measure table"));
+    }
+    if !knob_table.names.iter().any(|name| name == "id") {
+        issues.push(ProfileIssue::MissingIdColumn("knob table"));
+    }
+    if !issues.is_empty() {
+        return ValidationReport { issues };
+    }
+    let mt_id_idx = mt_id_idx.unwrap();
+
+    let mut measure_ids = Vec::with_capacity(measure_table.profile.len());
+    let mut seen = HashSet::new();
+    for (row, values) in measure_table.profile.iter().enumerate() {
+        let value = values[mt_id_idx];
+        if !value.is_finite() || value < 0.0 || value.fract() != 0.0 {
+            issues.push(ProfileIssue::InvalidId { row, value });
+            continue;
+        }
+        let id = value as u64;
+        if !seen.insert(id) {
+            issues.push(ProfileIssue::DuplicateId {
+                table: "measure table",
+                id,
+            });
+        }
+        measure_ids.push(id);
+
+        for (name, &value) in measure_table.names.iter().zip(values.iter()) {
+            if !value.is_finite() {
+                issues.push(ProfileIssue::NonFiniteValue {
+                    id,
+                    measure: name.clone(),
+                    value,
+                });
+            }
+        }
+    }
+
+    let mut knob_ids = HashSet::new();
+    let mut configurations: BTreeMap<Vec<(&String, &u64)>, u64> = BTreeMap::new();
+    for config in knob_table.configurations.iter() {
+        let id = config["id"];
+        if !knob_ids.insert(id) {
+            issues.push(ProfileIssue::DuplicateId {
+                table: "knob table",
+                id,
+            });
+        }
+        let mut settings: Vec<(&String, &u64)> =
+            config.iter().filter(|(name, _)| *name != "id").collect();
+        settings.sort();
+        if let Some(&first) = configurations.get(&settings) {
+            issues.push(ProfileIssue::DuplicateConfiguration { first, second: id });
+        } else {
+            configurations.insert(settings, id);
+        }
+    }
+
+    for id in measure_ids.iter().filter(|id| !knob_ids.contains(id)) {
+        issues.push(ProfileIssue::MissingKnobRow(*id));
+    }
+    let mut unmeasured: Vec<u64> = knob_ids.difference(&seen).copied().collect();
+    unmeasured.sort_unstable();
+    issues.extend(unmeasured.into_iter().map(ProfileIssue::MissingMeasureRow));
+
+    let registered: HashMap<String, bool> = knob_names
+        .into_iter()
+        .map(|name| {
+            let name = name.as_ref().to_string();
+            let profiled = knob_table.names.contains(&name);
+            (name, profiled)
+        })
+        .collect();
+    for name in knob_table.names.iter().filter(|name| *name != "id") {
+        if !registered.contains_key(name) {
+            issues.push(ProfileIssue::UnregisteredKnob(name.clone()));
+        }
+    }
+    let mut unprofiled: Vec<&String> = registered
+        .iter()
+        .filter(|(_, &profiled)| !profiled)
+        .map(|(name, _)| name)
+        .collect();
+    unprofiled.sort();
+    issues.extend(
+        unprofiled
+            .into_iter()
+            .map(|name| ProfileIssue::UnprofiledKnob(name.clone())),
+    );
+
+    ValidationReport { issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_profile, ProfileIssue};
+    use crate::profile::{KnobTable, MeasureTable};
+    use std::collections::HashMap;
+
+    fn knob_table(names: &[&str], rows: &[&[u64]]) -> KnobTable {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let configurations = rows
+            .iter()
+            .map(|row| {
+                names
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().copied())
+                    .collect::<HashMap<String, u64>>()
+            })
+            .collect();
+        KnobTable {
+            names,
+            configurations,
+        }
+    }
+
+    fn measure_table(rows: Vec<Vec<f64>>) -> MeasureTable {
+        MeasureTable {
+            names: vec!["id".to_string(), "latency".to_string()],
+            profile: rows,
+        }
+    }
+
+    #[test]
+    fn consistent_profile() {
+        let mt = measure_table(vec![vec![1.0, 0.5], vec![0.0, 0.25]]);
+        let kt = knob_table(&["id", "step"], &[&[0, 1], &[1, 4]]);
+        let report = validate_profile(&mt, &kt, ["step"]);
+        assert!(report.is_valid());
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn inconsistent_profile() {
+        let mt = measure_table(vec![
+            vec![0.0, 0.5],
+            vec![0.0, f64::INFINITY],
+            vec![3.0, 0.25],
+        ]);
+        let kt = knob_table(&["id", "step", "threshold"], &[&[0, 1, 5], &[1, 1, 5]]);
+        let report = validate_profile(&mt, &kt, ["step", "hyperthreading"]);
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.issues,
+            vec![
+                ProfileIssue::DuplicateId {
+                    table: "measure table",
+                    id: 0
+                },
+                ProfileIssue::NonFiniteValue {
+                    id: 0,
+                    measure: "latency".to_string(),
+                    value: f64::INFINITY
+                },
+                ProfileIssue::DuplicateConfiguration {
+                    first: 0,
+                    second: 1
+                },
+                ProfileIssue::MissingKnobRow(3),
+                ProfileIssue::MissingMeasureRow(1),
+                ProfileIssue::UnregisteredKnob("threshold".to_string()),
+                ProfileIssue::UnprofiledKnob("hyperthreading".to_string()),
+            ]
+        );
+        assert_eq!(report.fatal_issues().count(), 6);
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of parts the controller splits a time-based window into when it
+/// schedules two configurations.
+const TIME_SPLIT_RESOLUTION: u64 = 1000;
+
+/// When a control window ends. Measurements are reset, energy is read and
+/// the controller computes a new schedule at every window boundary. Parsed
+/// from the `WINDOW` environment variable:
+///
+/// * `N`: every N iterations
+/// * `500ms`, `2s`: whenever that much time has passed
+/// * `N:500ms`: once the window holds N iterations, or after that much time
+///   if it does not get there first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowPolicy {
+    Iterations(u64),
+    Time(Duration),
+    Hybrid {
+        min_samples: u64,
+        max_duration: Duration,
+    },
+}
+
+impl Display for WindowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            WindowPolicy::Iterations(iterations) => write!(f, "{}", iterations),
+            WindowPolicy::Time(duration) => write!(f, "{}ms", duration.as_millis()),
+            WindowPolicy::Hybrid {
+                min_samples,
+                max_duration,
+            } => write!(f, "{}:{}ms", min_samples, max_duration.as_millis()),
+        }
+    }
+}
+
+impl FromStr for WindowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_duration = |duration: &str| -> Result<Duration, String> {
+            parse_duration(duration)
+                .filter(|duration| !duration.is_zero())
+                .ok_or_else(|| format!("invalid duration '{}' in window '{}'", duration, s))
+        };
+        let parse_samples = |samples: &str| -> Result<u64, String> {
+            match samples.parse() {
+                Ok(samples) if samples > 0 => Ok(samples),
+                _ => Err(format!(
+                    "invalid iterations '{}' in window '{}'",
+                    samples, s
+                )),
+            }
+        };
+        let s = s.trim();
+        match s.split_once(':') {
+            Some((min_samples, max_duration)) => Ok(WindowPolicy::Hybrid {
+                min_samples: parse_samples(min_samples)?,
+                max_duration: parse_duration(max_duration)?,
+            }),
+            None if s.ends_with('s') => Ok(WindowPolicy::Time(parse_duration(s)?)),
+            None => Ok(WindowPolicy::Iterations(parse_samples(s)?)),
+        }
+    }
+}
+
+/// Parses durations such as `500ms`, `2s` or `2` (seconds).
+pub(crate) fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let (value, unit) = match duration.strip_suffix("ms") {
+        Some(value) => (value, 1e-3),
+        None => (duration.strip_suffix('s').unwrap_or(duration), 1.0),
+    };
+    match value.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => {
+            Some(Duration::from_secs_f64(value * unit))
+        }
+        _ => None,
+    }
+}
+
+impl WindowPolicy {
+    /// Iterations per window, or the number of parts the controller splits a
+    /// time-based window into.
+    pub fn resolution(&self) -> u64 {
+        match self {
+            WindowPolicy::Iterations(iterations) => *iterations,
+            WindowPolicy::Time(_) | WindowPolicy::Hybrid { .. } => TIME_SPLIT_RESOLUTION,
+        }
+    }
+}
+
+/// Tracks the window that is in progress.
+pub(crate) struct WindowClock {
+    policy: WindowPolicy,
+    started: Instant,
+    /// Iterations begun in the current window.
+    samples: u64,
+    index: u64,
+    /// Iterations of the last completed window, read by aggregates that are
+    /// reported per iteration.
+    completed_samples: Arc<AtomicU64>,
+}
+
+impl WindowClock {
+    pub fn new(policy: WindowPolicy) -> WindowClock {
+        WindowClock {
+            policy,
+            started: Instant::now(),
+            samples: 0,
+            index: 0,
+            completed_samples: Arc::new(AtomicU64::new(policy.resolution())),
+        }
+    }
+
+    /// Starts over with the first window.
+    pub fn restart(&mut self) {
+        self.started = Instant::now();
+        self.samples = 0;
+        self.index = 0;
+    }
+
+    /// Counts the iteration that begins. Returns whether it starts a new
+    /// window, i.e. whether the previous one just ended.
+    pub fn begin_iteration(&mut self, iteration: u64) -> bool {
+        let elapsed = self.started.elapsed();
+        let closed = match self.policy {
+            WindowPolicy::Iterations(iterations) => iteration.is_multiple_of(iterations),
+            WindowPolicy::Time(duration) => iteration > 1 && elapsed >= duration,
+            WindowPolicy::Hybrid {
+                min_samples,
+                max_duration,
+            } => iteration > 1 && (self.samples >= min_samples || elapsed >= max_duration),
+        };
+        if closed {
+            self.completed_samples
+                .store(self.samples, Ordering::Relaxed);
+            self.started = Instant::now();
+            self.samples = 0;
+            self.index += 1;
+        }
+        self.samples += 1;
+        closed
+    }
+
+    /// Share of the current window that has passed, in `[0, 1]`.
+    pub fn progress(&self, iteration: u64) -> f64 {
+        let duration = match self.policy {
+            WindowPolicy::Iterations(iterations) => {
+                return (iteration % iterations) as f64 / iterations as f64
+            }
+            WindowPolicy::Time(duration) => duration,
+            WindowPolicy::Hybrid { max_duration, .. } => max_duration,
+        };
+        (self.started.elapsed().as_secs_f64() / duration.as_secs_f64()).min(1.0)
+    }
+
+    /// Number of windows that ended since the clock was (re)started.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn completed_samples(&self) -> Arc<AtomicU64> {
+        self.completed_samples.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WindowClock, WindowPolicy};
+    use crate::profile::ProfileError;
+    use crate::testing::{builder, env_lock, step};
+    use crate::Apto;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_window() {
+        assert_eq!("1000".parse(), Ok(WindowPolicy::Iterations(1000)));
+        assert_eq!(
+            "500ms".parse(),
+            Ok(WindowPolicy::Time(Duration::from_millis(500)))
+        );
+        assert_eq!("2s".parse(), Ok(WindowPolicy::Time(Duration::from_secs(2))));
+        let hybrid = WindowPolicy::Hybrid {
+            min_samples: 10,
+            max_duration: Duration::from_millis(250),
+        };
+        assert_eq!("10:250ms".parse(), Ok(hybrid));
+        assert_eq!(hybrid.to_string().parse(), Ok(hybrid));
+        assert!("0".parse::<WindowPolicy>().is_err());
+        assert!("10:soon".parse::<WindowPolicy>().is_err());
+    }
+
+    #[test]
+    fn window_boundaries() {
+        let mut clock = WindowClock::new(WindowPolicy::Iterations(4));
+        let closed: Vec<u64> = (1..=12)
+            .filter(|&iteration| clock.begin_iteration(iteration))
+            .collect();
+        assert_eq!(closed, vec![4, 8, 12]);
+        assert_eq!(clock.index(), 3);
+        assert_eq!(clock.progress(13), 0.25);
+
+        let policy = WindowPolicy::Hybrid {
+            min_samples: 3,
+            max_duration: Duration::from_secs(3600),
+        };
+        let mut clock = WindowClock::new(policy);
+        // The window holds enough iterations long before its time is up
+        assert!(!clock.begin_iteration(1));
+        assert!(!clock.begin_iteration(2));
+        assert!(!clock.begin_iteration(3));
+        assert!(clock.begin_iteration(4));
+        assert_eq!(clock.completed_samples().load(Ordering::Relaxed), 3);
+        assert!(clock.progress(4) < 1.0);
+    }
+
+    #[test]
+    fn hybrid_window_closes_after_max_duration() {
+        let policy = WindowPolicy::Hybrid {
+            min_samples: 1000,
+            max_duration: Duration::from_millis(5),
+        };
+        let mut clock = WindowClock::new(policy);
+        assert!(!clock.begin_iteration(1));
+        assert!(!clock.begin_iteration(2));
+        std::thread::sleep(Duration::from_millis(10));
+        // Far fewer iterations than asked for, but the time is up
+        assert!(clock.begin_iteration(3));
+        assert_eq!(clock.completed_samples().load(Ordering::Relaxed), 2);
+        assert_eq!(clock.index(), 1);
+    }
+
+    #[test]
+    fn reject_invalid_window_from_environment() {
+        let _env = env_lock();
+        std::env::set_var("WINDOW", "soon");
+        let config = builder(&step()).env_overrides(true).build().unwrap();
+        assert!(matches!(
+            Apto::try_new(config),
+            Err(ProfileError::InvalidSetting {
+                setting: "WINDOW",
+                ..
+            })
+        ));
+        std::env::remove_var("WINDOW");
+    }
+}